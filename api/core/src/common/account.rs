@@ -0,0 +1,74 @@
+//! Account resolution which maps player public keys to their accounts
+
+use logic::encryption::PublicKey;
+
+use crate::{
+    entities::{Account, AccountKey, UserId},
+    storage::{Storage, StorageErr},
+};
+
+/// Returns user id of the account which belongs to the public key, new account is created for unknown keys
+pub async fn resolve_account(
+    storage: &impl Storage,
+    public_key: &PublicKey,
+) -> Result<UserId, StorageErr> {
+    match storage
+        .read::<AccountKey>(AccountKey::key_for(public_key))
+        .await
+    {
+        Ok(account_key) => return Ok(account_key.user_id),
+        Err(StorageErr::NotFound) => {}
+        Err(err) => return Err(err),
+    }
+    // Account key is created only if it doesn't exist yet, so concurrent first messages of the same player resolve
+    // to the account of the one which wins
+    let account = Account::generate();
+    let user_id = account.key.user_id.clone();
+    let account_key = AccountKey {
+        key: AccountKey::key_for(public_key),
+        user_id: user_id.clone(),
+    };
+    let transaction = storage
+        .transaction()
+        .put(&account)
+        .put_if_version(&account_key, 0);
+    match storage.commit(transaction).await {
+        Ok(()) => Ok(user_id),
+        Err(StorageErr::Conflict) => Ok(storage
+            .read::<AccountKey>(AccountKey::key_for(public_key))
+            .await?
+            .user_id),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::encryption;
+
+    use crate::storage::{storage_memory::MemoryStorage, Key};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn resolve_accounts() {
+        let storage = MemoryStorage::new("test").await;
+        let key1 = encryption::generate_new_keys().public_key;
+        let key2 = encryption::generate_new_keys().public_key;
+
+        // Account is created once per public key
+        let user1 = resolve_account(&storage, &key1).await.unwrap();
+        assert_eq!(resolve_account(&storage, &key1).await.unwrap(), user1);
+        let user2 = resolve_account(&storage, &key2).await.unwrap();
+        assert_ne!(user1, user2);
+
+        let account: Account = storage
+            .read(Key {
+                user_id: user1.clone(),
+                entity_id: user1.as_str(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(account.key.user_id, user1);
+    }
+}
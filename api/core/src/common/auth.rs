@@ -0,0 +1,402 @@
+//! Challenge-response authentication of WebSocket connections. Once connection is authenticated player messages
+//! sent over it don't need to include public key and signature
+
+use std::sync::Arc;
+
+use logic::{
+    encryption::PublicKey,
+    messages::{
+        common::auth::{Authenticate, Authenticated, Challenge, ChallengeQuery},
+        ClientPublicMessage,
+    },
+    server_error::{ErrorCode, ServerError},
+};
+
+use crate::{
+    common::{account::resolve_account, connection::bind},
+    entities::{Connection, UserId},
+    lambda::{ConnectionMessageHandler, RequestContext},
+    storage::{Storage, StorageErr},
+};
+
+/// Returns public key and user id of the player to which connection was bound by the authentication
+pub async fn connection_player(
+    storage: &impl Storage,
+    connection_id: &str,
+    message_tag: u16,
+    request_id: u32,
+) -> Result<(Arc<PublicKey>, UserId), ServerError> {
+    let (connection, _) = read_connection(storage, connection_id, message_tag, request_id).await?;
+    let public_key = connection.public_key.ok_or_else(|| {
+        authentication_error("Connection is not authenticated", message_tag, request_id)
+    })?;
+    let public_key = PublicKey::deserialize(public_key)
+        .map_err(|err| ServerError::from_serialization_error(err, message_tag, request_id))?;
+    let user_id = match connection.user_id {
+        Some(user_id) => user_id,
+        None => resolve_account(storage, &public_key)
+            .await
+            .map_err(|err| err.into_server_error(message_tag, request_id))?,
+    };
+    Ok((public_key, user_id))
+}
+
+/// Handler for `ChallengeQuery` messages, issues a new challenge for the connection
+pub struct ChallengeHandler<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> ChallengeHandler<S> {
+    /// Creates a new handler which uses given storage
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S: Storage> ConnectionMessageHandler<ChallengeQuery> for ChallengeHandler<S> {
+    async fn process_message(
+        &self,
+        _: ChallengeQuery,
+        context: &RequestContext,
+        request_id: u32,
+    ) -> Result<Challenge, ServerError> {
+        let tag = ChallengeQuery::tag();
+        let (mut connection, version) = read_connection(
+            self.storage.as_ref(),
+            &context.connection_id,
+            tag,
+            request_id,
+        )
+        .await?;
+        let challenge = Challenge::generate();
+        connection.challenge = Some(challenge.nonce.clone());
+        write_connection(self.storage.as_ref(), &connection, version, tag, request_id).await?;
+        Ok(challenge)
+    }
+}
+
+/// Handler for `Authenticate` messages, verifies the signed challenge and binds connection to the public key and
+/// the account which belongs to it
+pub struct AuthenticateHandler<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> AuthenticateHandler<S> {
+    /// Creates a new handler which uses given storage
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S: Storage> ConnectionMessageHandler<Authenticate> for AuthenticateHandler<S> {
+    async fn process_message(
+        &self,
+        message: Authenticate,
+        context: &RequestContext,
+        request_id: u32,
+    ) -> Result<Authenticated, ServerError> {
+        let tag = Authenticate::tag();
+        let (mut connection, version) = read_connection(
+            self.storage.as_ref(),
+            &context.connection_id,
+            tag,
+            request_id,
+        )
+        .await?;
+
+        // Every challenge can be answered only once, even if verification fails
+        let nonce = connection
+            .challenge
+            .take()
+            .ok_or_else(|| authentication_error("No challenge was requested", tag, request_id))?;
+        let verified = message.verify(&nonce);
+        if let Ok(public_key) = &verified {
+            connection.public_key = Some(public_key.serialize());
+        }
+        write_connection(self.storage.as_ref(), &connection, version, tag, request_id).await?;
+        let public_key = verified.map_err(|err| {
+            let mut error = authentication_error("Challenge verification failed", tag, request_id);
+            error.error_context = Some(err.to_string());
+            error
+        })?;
+        let user_id = resolve_account(self.storage.as_ref(), &public_key)
+            .await
+            .map_err(|err| err.into_server_error(tag, request_id))?;
+        bind(self.storage.as_ref(), &context.connection_id, &user_id)
+            .await
+            .map_err(|err| err.into_server_error(tag, request_id))?;
+        Ok(Authenticated {})
+    }
+}
+
+async fn read_connection(
+    storage: &impl Storage,
+    connection_id: &str,
+    message_tag: u16,
+    request_id: u32,
+) -> Result<(Connection, u64), ServerError> {
+    match storage
+        .read_versioned(Connection::key_for(connection_id))
+        .await
+    {
+        Ok(connection) => Ok(connection),
+        Err(StorageErr::NotFound) => Err(authentication_error(
+            "Connection not found",
+            message_tag,
+            request_id,
+        )),
+        Err(err) => Err(err.into_server_error(message_tag, request_id)),
+    }
+}
+
+/// Writes connection only if it wasn't changed since it was read, so a challenge cannot be answered twice by
+/// concurrent messages and a newer challenge is never overwritten by an older one
+async fn write_connection(
+    storage: &impl Storage,
+    connection: &Connection,
+    version: u64,
+    message_tag: u16,
+    request_id: u32,
+) -> Result<(), ServerError> {
+    match storage.write_if_version(connection, version).await {
+        Ok(_) => Ok(()),
+        Err(StorageErr::Conflict) => Err(authentication_error(
+            "Connection was changed by another message, request a new challenge",
+            message_tag,
+            request_id,
+        )),
+        Err(err) => Err(err.into_server_error(message_tag, request_id)),
+    }
+}
+
+fn authentication_error(description: &str, message_tag: u16, request_id: u32) -> ServerError {
+    ServerError {
+        error_code: ErrorCode::AuthenticationError,
+        error_description: description.to_string(),
+        error_context: None,
+        request_id,
+        message_tag,
+        recoverable: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::{
+        datetime::{Duration, ServerTimestamp, Timestamp},
+        encryption::{self, Keys},
+        messages::{
+            game::decay::{Decay, DecayQuery},
+            ClientPlayerMessage,
+        },
+    };
+
+    use crate::{
+        common::connection::{connect, player_connections},
+        fixtures::{event_with_body, InterleavingStorage},
+        lambda::{process_connection_message_event, process_player_event, PlayerEventHandler},
+        storage::storage_memory::MemoryStorage,
+    };
+
+    use std::sync::Mutex;
+
+    use super::*;
+
+    const CONNECTION_ID: &str = "etAB-deZIAMCK2g=";
+
+    /// Remembers the last player the message was processed for
+    #[derive(Default)]
+    struct PublicKeyHandler {
+        player: Mutex<Option<(String, UserId)>>,
+    }
+
+    impl PublicKeyHandler {
+        fn player(&self) -> Option<(String, UserId)> {
+            self.player.lock().unwrap().clone()
+        }
+    }
+
+    impl PlayerEventHandler<DecayQuery> for PublicKeyHandler {
+        async fn process_message(
+            &self,
+            _: DecayQuery,
+            _: &RequestContext,
+            public_key: Arc<PublicKey>,
+            user_id: UserId,
+            _: u32,
+        ) -> Result<Decay, ServerError> {
+            *self.player.lock().unwrap() = Some((public_key.as_string(), user_id));
+            let now = ServerTimestamp::now();
+            Ok(Decay {
+                started_at: Arc::new(now.clone()),
+                length: Duration::from_milliseconds(0),
+                ends_at: Some(Arc::new(now)),
+                modifiers: vec![],
+            })
+        }
+    }
+
+    async fn connected_storage() -> Arc<MemoryStorage> {
+        let storage = Arc::new(MemoryStorage::new("test").await);
+        let connection = Connection::new(
+            CONNECTION_ID,
+            ServerTimestamp::from_milliseconds_pure(1),
+            "127.0.0.1".to_string(),
+        );
+        connect(storage.as_ref(), &connection).await.unwrap();
+        storage
+    }
+
+    async fn request_challenge(storage: &Arc<MemoryStorage>) -> Challenge {
+        let handler = ChallengeHandler::new(storage.clone());
+        let body = ChallengeQuery {}.serialize(1).unwrap();
+        let response = process_connection_message_event(event_with_body(body), &handler, &()).await;
+        Challenge::deserialize(&response).unwrap().0
+    }
+
+    async fn authenticate(storage: &Arc<MemoryStorage>, message: Arc<Authenticate>) -> String {
+        let handler = AuthenticateHandler::new(storage.clone());
+        let body = message.serialize(2).unwrap();
+        process_connection_message_event(event_with_body(body), &handler, &()).await
+    }
+
+    fn clone_keys(keys: &Keys) -> Keys {
+        Keys {
+            public_key: keys.public_key.clone(),
+            private_key: keys.private_key.clone(),
+        }
+    }
+
+    #[tokio::test]
+    async fn authenticate_connection() {
+        let storage = connected_storage().await;
+        let keys = encryption::generate_new_keys();
+        let handler = PublicKeyHandler::default();
+
+        // Unsigned player messages are rejected until connection is authenticated
+        let unsigned = DecayQuery {}.serialize_unsigned(3).unwrap();
+        let response = process_player_event(
+            event_with_body(unsigned.clone()),
+            &handler,
+            storage.as_ref(),
+            &(),
+        )
+        .await;
+        let (error, _) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(error.error_code, ErrorCode::AuthenticationError);
+        assert_eq!(error.request_id, 3);
+        assert_eq!(handler.player(), None);
+
+        let challenge = request_challenge(&storage).await;
+        let response = authenticate(
+            &storage,
+            Authenticate::new(challenge.nonce, clone_keys(&keys)),
+        )
+        .await;
+        assert_eq!(Authenticated::deserialize(&response).unwrap().1, 2);
+        let user_id = resolve_account(storage.as_ref(), &keys.public_key)
+            .await
+            .unwrap();
+        let connections = player_connections(storage.as_ref(), &user_id)
+            .await
+            .unwrap();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].connection_id(), CONNECTION_ID);
+        let response =
+            process_player_event(event_with_body(unsigned), &handler, storage.as_ref(), &()).await;
+        assert_eq!(Decay::deserialize(&response).unwrap().1, 3);
+        assert_eq!(
+            handler.player(),
+            Some((keys.public_key.as_string(), user_id.clone()))
+        );
+
+        // Signed messages keep working on authenticated connections
+        let other_keys = encryption::generate_new_keys();
+        let signed = DecayQuery {}
+            .serialize(
+                4,
+                &Timestamp::now(),
+                other_keys.public_key.as_ref().clone(),
+                other_keys.private_key.as_ref().clone(),
+            )
+            .unwrap();
+        let response =
+            process_player_event(event_with_body(signed), &handler, storage.as_ref(), &()).await;
+        let other_user_id = resolve_account(storage.as_ref(), &other_keys.public_key)
+            .await
+            .unwrap();
+        assert_ne!(other_user_id, user_id);
+        assert_eq!(Decay::deserialize(&response).unwrap().1, 4);
+        assert_eq!(
+            handler.player(),
+            Some((other_keys.public_key.as_string(), other_user_id))
+        );
+    }
+
+    #[tokio::test]
+    async fn authenticate_bad_challenge() {
+        let storage = connected_storage().await;
+        let keys = encryption::generate_new_keys();
+
+        // Challenge has to be requested first
+        let response = authenticate(
+            &storage,
+            Authenticate::new(Challenge::generate().nonce, clone_keys(&keys)),
+        )
+        .await;
+        let (error, _) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(error.error_code, ErrorCode::AuthenticationError);
+
+        // Wrong nonce fails and the challenge cannot be answered again
+        let challenge = request_challenge(&storage).await;
+        let response = authenticate(
+            &storage,
+            Authenticate::new(Challenge::generate().nonce, clone_keys(&keys)),
+        )
+        .await;
+        let (error, _) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(error.error_code, ErrorCode::AuthenticationError);
+        let response = authenticate(
+            &storage,
+            Authenticate::new(challenge.nonce, clone_keys(&keys)),
+        )
+        .await;
+        let (error, _) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(error.error_code, ErrorCode::AuthenticationError);
+        assert!(connection_player(storage.as_ref(), CONNECTION_ID, 0, 0)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn authenticate_concurrently() {
+        let storage = Arc::new(InterleavingStorage::new("test").await);
+        let connection = Connection::new(
+            CONNECTION_ID,
+            ServerTimestamp::from_milliseconds_pure(1),
+            "127.0.0.1".to_string(),
+        );
+        connect(storage.as_ref(), &connection).await.unwrap();
+        let handler = ChallengeHandler::new(storage.clone());
+        let body = ChallengeQuery {}.serialize(1).unwrap();
+        let response = process_connection_message_event(event_with_body(body), &handler, &()).await;
+        let challenge = Challenge::deserialize(&response).unwrap().0;
+
+        // Both messages read the same challenge, but only one of them can answer it
+        let handler = AuthenticateHandler::new(storage.clone());
+        let authenticate = |keys: Keys, request_id| {
+            let body = Authenticate::new(challenge.nonce.clone(), keys)
+                .serialize(request_id)
+                .unwrap();
+            process_connection_message_event(event_with_body(body), &handler, &())
+        };
+        let (first, second) = tokio::join!(
+            authenticate(encryption::generate_new_keys(), 2),
+            authenticate(encryption::generate_new_keys(), 3),
+        );
+        assert!(Authenticated::deserialize(&first).is_ok());
+        let (error, request_id) = ServerError::deserialize(&second).unwrap();
+        assert_eq!(error.error_code, ErrorCode::AuthenticationError);
+        assert_eq!(request_id, 3);
+    }
+}
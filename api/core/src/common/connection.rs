@@ -0,0 +1,202 @@
+//! Connection handlers which keep track of live WebSocket connections and the players they are bound to
+
+use std::sync::Arc;
+
+use aws_lambda_events::apigw::ApiGatewayWebsocketProxyRequest;
+use futures::TryStreamExt;
+use logic::datetime::ServerTimestamp;
+
+use crate::{
+    entities::{Connection, PlayerConnection, UserId},
+    lambda::ConnectionEventHandler,
+    storage::{Entity, Key, Storage, StorageErr},
+};
+
+/// Store a new connection which is not bound to any player yet
+pub async fn connect(storage: &impl Storage, connection: &Connection) -> Result<(), StorageErr> {
+    storage.write(connection).await
+}
+
+/// Bind existing connection to the player, so it can be found by the player user id
+pub async fn bind(
+    storage: &impl Storage,
+    connection_id: &str,
+    user_id: &UserId,
+) -> Result<(), StorageErr> {
+    let mut connection: Connection = storage.read(Connection::key_for(connection_id)).await?;
+    if let Some(bound) = &connection.user_id {
+        if bound == user_id {
+            return Ok(());
+        }
+        storage
+            .delete(
+                bound,
+                Some(PlayerConnection::entity_type()),
+                Some(connection_id),
+            )
+            .await?;
+    }
+    storage
+        .write(&PlayerConnection {
+            key: Key {
+                user_id: user_id.clone(),
+                entity_id: connection_id.to_string(),
+            },
+            connected_at: connection.connected_at.clone(),
+        })
+        .await?;
+    connection.user_id = Some(user_id.clone());
+    storage.write(&connection).await
+}
+
+/// Remove the connection and its binding to the player. Unknown connections are ignored
+pub async fn disconnect(storage: &impl Storage, connection_id: &str) -> Result<(), StorageErr> {
+    let connection: Connection = match storage.read(Connection::key_for(connection_id)).await {
+        Ok(connection) => connection,
+        Err(StorageErr::NotFound) => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if let Some(user_id) = &connection.user_id {
+        storage
+            .delete(
+                user_id,
+                Some(PlayerConnection::entity_type()),
+                Some(connection_id),
+            )
+            .await?;
+    }
+    storage.delete_entity(connection).await.map(|_| ())
+}
+
+/// Returns all live connections of the player
+pub async fn player_connections(
+    storage: &impl Storage,
+    user_id: &UserId,
+) -> Result<Vec<PlayerConnection>, StorageErr> {
+    storage.find(user_id).await.try_collect().await
+}
+
+/// Handler for `$connect` events, stores a new connection
+pub struct ConnectHandler<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> ConnectHandler<S> {
+    /// Creates a new handler which uses given storage
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S: Storage> ConnectionEventHandler for ConnectHandler<S> {
+    async fn process_event(
+        &self,
+        event: ApiGatewayWebsocketProxyRequest,
+    ) -> Result<(), StorageErr> {
+        let context = event.request_context;
+        let connection = Connection::new(
+            &connection_id(context.connection_id)?,
+            ServerTimestamp::from_milliseconds_pure(context.connected_at as u64),
+            context.identity.source_ip.unwrap_or_default(),
+        );
+        connect(self.storage.as_ref(), &connection).await
+    }
+}
+
+/// Handler for `$disconnect` events, removes the connection
+pub struct DisconnectHandler<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> DisconnectHandler<S> {
+    /// Creates a new handler which uses given storage
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S: Storage> ConnectionEventHandler for DisconnectHandler<S> {
+    async fn process_event(
+        &self,
+        event: ApiGatewayWebsocketProxyRequest,
+    ) -> Result<(), StorageErr> {
+        let connection_id = connection_id(event.request_context.connection_id)?;
+        disconnect(self.storage.as_ref(), &connection_id).await
+    }
+}
+
+fn connection_id(connection_id: Option<String>) -> Result<String, StorageErr> {
+    connection_id.ok_or_else(|| StorageErr::ValidationError("No connection id".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fixtures::event_with_body, lambda::process_connection_event,
+        storage::storage_memory::MemoryStorage,
+    };
+
+    use super::*;
+
+    const CONNECTION_ID: &str = "etAB-deZIAMCK2g=";
+
+    #[tokio::test]
+    async fn connect_bind_disconnect() {
+        let storage = Arc::new(MemoryStorage::new("test").await);
+        let handler = ConnectHandler::new(storage.clone());
+        let response = process_connection_event(event_with_body(String::new()), &handler).await;
+        assert_eq!(response["statusCode"], 200);
+        let connection: Connection = storage
+            .read(Connection::key_for(CONNECTION_ID))
+            .await
+            .unwrap();
+        assert_eq!(connection.source_ip, "87.95.116.76");
+        assert_eq!(connection.user_id, None);
+
+        // Bind to the player and then rebind to another one
+        let user1 = UserId::generate();
+        let user2 = UserId::generate();
+        bind(storage.as_ref(), CONNECTION_ID, &user1).await.unwrap();
+        let connections = player_connections(storage.as_ref(), &user1).await.unwrap();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].connection_id(), CONNECTION_ID);
+        bind(storage.as_ref(), CONNECTION_ID, &user2).await.unwrap();
+        assert!(player_connections(storage.as_ref(), &user1)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            player_connections(storage.as_ref(), &user2)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // Disconnect removes both connection and player binding, repeated disconnect is fine
+        let handler = DisconnectHandler::new(storage.clone());
+        let response = process_connection_event(event_with_body(String::new()), &handler).await;
+        assert_eq!(response["statusCode"], 200);
+        let response = process_connection_event(event_with_body(String::new()), &handler).await;
+        assert_eq!(response["statusCode"], 200);
+        assert!(matches!(
+            storage
+                .read::<Connection>(Connection::key_for(CONNECTION_ID))
+                .await,
+            Err(StorageErr::NotFound)
+        ));
+        assert!(player_connections(storage.as_ref(), &user2)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn bind_unknown_connection() {
+        let storage = MemoryStorage::new("test").await;
+        assert_eq!(
+            bind(&storage, CONNECTION_ID, &UserId::generate()).await,
+            Err(StorageErr::NotFound)
+        );
+    }
+}
@@ -4,7 +4,10 @@ use std::sync::Arc;
 
 use logic::{
     datetime::ServerTimestamp,
-    messages::common::ping::{ServerStatus, Status},
+    messages::{
+        common::ping::{ServerStatus, Status},
+        serializers::MIN_PROTOCOL_VERSION,
+    },
 };
 
 /// Returns a message when server is health and operational
@@ -12,5 +15,6 @@ pub fn healthy_status(timestamp: ServerTimestamp) -> ServerStatus {
     ServerStatus {
         timestamp: Arc::new(timestamp),
         status: Status::OK,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
     }
 }
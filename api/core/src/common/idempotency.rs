@@ -0,0 +1,150 @@
+//! Idempotency keys for player messages. Clients may attach a key to the message, the successful response is then
+//! remembered for a while and retries with the same key get it back without the message being applied again. The
+//! key is reserved before the message is processed, so concurrent retries are never applied twice
+
+use logic::{
+    datetime::ServerTimestamp,
+    encryption::PublicKey,
+    messages::serializers::ServerMessage,
+    server_error::{ErrorCode, ServerError},
+};
+
+use crate::{
+    entities::IdempotentResponse,
+    storage::{Entity, Storage, StorageErr},
+};
+
+/// How long responses are kept for retries, clients are expected to give up retrying much sooner
+pub const IDEMPOTENCY_KEY_TTL_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// How long the key stays reserved while its message is processed. Handlers are stopped much sooner, so a
+/// reservation which is still there afterwards belongs to a failed lambda and retries may take it over
+pub const IDEMPOTENCY_RESERVATION_TTL_MS: u64 = 60 * 1000;
+
+/// Result of reserving an idempotency key for the message
+#[derive(Debug, PartialEq)]
+pub enum Reservation {
+    /// Key is reserved with the given version, the message has to be processed and its response stored with
+    /// `store_response`, or the key released with `release_key` if processing fails
+    Reserved(u64),
+    /// Message was already processed, contains its stored response with the request id replaced
+    Processed(String),
+}
+
+/// Reserves the idempotency key for processing the message, so the handler never runs for its duplicates. Returns
+/// the stored response if the message was already processed, or a recoverable error while a duplicate of it is
+/// still being processed
+pub async fn reserve_key(
+    storage: &impl Storage,
+    public_key: &PublicKey,
+    idempotency_key: &str,
+    message_tag: u16,
+    request_id: u32,
+    now: ServerTimestamp,
+) -> Result<Reservation, ServerError> {
+    let key = IdempotentResponse::key_for(&public_key.serialize(), message_tag, idempotency_key);
+    let version = match storage
+        .read_versioned::<IdempotentResponse>(key.clone())
+        .await
+    {
+        Ok((stored, version)) => match stored.response {
+            Some(response) => {
+                return replace_request_id(&response, message_tag, request_id)
+                    .map(Reservation::Processed)
+            }
+            None if stored.expires_at.as_milliseconds() > now.as_milliseconds() => {
+                return Err(in_progress_error(message_tag, request_id))
+            }
+            None => version,
+        },
+        Err(StorageErr::NotFound) => 0,
+        Err(err) => return Err(err.into_server_error(message_tag, request_id)),
+    };
+    let reservation = IdempotentResponse {
+        key,
+        response: None,
+        expires_at: ServerTimestamp::from_milliseconds_pure(
+            now.as_milliseconds() + IDEMPOTENCY_RESERVATION_TTL_MS,
+        ),
+    };
+    match storage.write_if_version(&reservation, version).await {
+        Ok(version) => Ok(Reservation::Reserved(version)),
+        // Duplicate of the message reserved the key first
+        Err(StorageErr::Conflict) => Err(in_progress_error(message_tag, request_id)),
+        Err(err) => Err(err.into_server_error(message_tag, request_id)),
+    }
+}
+
+/// Remembers response to the message with the reserved idempotency key until `IDEMPOTENCY_KEY_TTL_MS` passes from
+/// `now`, `version` is the one returned by `reserve_key`
+pub async fn store_response(
+    storage: &impl Storage,
+    public_key: &PublicKey,
+    idempotency_key: &str,
+    message_tag: u16,
+    response: &str,
+    version: u64,
+    now: ServerTimestamp,
+) -> Result<u64, StorageErr> {
+    storage
+        .write_if_version(
+            &IdempotentResponse {
+                key: IdempotentResponse::key_for(
+                    &public_key.serialize(),
+                    message_tag,
+                    idempotency_key,
+                ),
+                response: Some(response.to_string()),
+                expires_at: ServerTimestamp::from_milliseconds_pure(
+                    now.as_milliseconds() + IDEMPOTENCY_KEY_TTL_MS,
+                ),
+            },
+            version,
+        )
+        .await
+}
+
+/// Releases the reserved idempotency key of a failed message, so it can be retried
+pub async fn release_key(
+    storage: &impl Storage,
+    public_key: &PublicKey,
+    idempotency_key: &str,
+    message_tag: u16,
+) -> Result<usize, StorageErr> {
+    let key = IdempotentResponse::key_for(&public_key.serialize(), message_tag, idempotency_key);
+    storage
+        .delete(
+            &key.user_id,
+            Some(IdempotentResponse::entity_type()),
+            Some(&key.entity_id),
+        )
+        .await
+}
+
+fn replace_request_id(
+    response: &str,
+    message_tag: u16,
+    request_id: u32,
+) -> Result<String, ServerError> {
+    ServerMessage::replace_request_id(response, request_id).map_err(|err| ServerError {
+        error_code: ErrorCode::ServerError,
+        error_description: "Stored response cannot be sent back".to_string(),
+        error_context: Some(err.to_string()),
+        request_id,
+        message_tag,
+        recoverable: false,
+    })
+}
+
+fn in_progress_error(message_tag: u16, request_id: u32) -> ServerError {
+    ServerError {
+        error_code: ErrorCode::ServerError,
+        error_description:
+            "Message with the same idempotency key is still being processed, try again later"
+                .to_string(),
+        error_context: None,
+        request_id,
+        message_tag,
+        recoverable: true,
+    }
+}
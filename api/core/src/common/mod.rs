@@ -1,3 +1,11 @@
 //! API logic for common handlers, like connect, disconnect, health
 
+pub mod account;
+pub mod auth;
+pub mod connection;
 pub mod health;
+pub mod idempotency;
+pub mod ping;
+pub mod rate_limit;
+pub mod replay;
+pub mod version;
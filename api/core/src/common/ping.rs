@@ -0,0 +1,46 @@
+//! Ping handler which replies to `Ping` messages with the current server status
+
+use logic::{
+    datetime::ServerTimestamp,
+    messages::common::ping::{Ping, ServerStatus},
+    server_error::ServerError,
+};
+
+use crate::{
+    common::health::healthy_status,
+    lambda::{PublicEventHandler, RequestContext},
+};
+
+/// Handler for `Ping` messages, returns `ServerStatus` with the current server timestamp
+pub struct PingHandler {}
+
+impl PublicEventHandler<Ping> for PingHandler {
+    async fn process_message(
+        &self,
+        _: Ping,
+        _: &RequestContext,
+        _: u32,
+    ) -> Result<ServerStatus, ServerError> {
+        Ok(healthy_status(ServerTimestamp::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::messages::{common::ping::Status, ClientPublicMessage};
+
+    use crate::{fixtures::event_with_body, lambda::process_public_event};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn process_message_ok() {
+        let before = ServerTimestamp::now();
+        let event = event_with_body(Ping {}.serialize(1).unwrap());
+        let response = process_public_event(event, &PingHandler {}, &()).await;
+        let (data, req_id) = ServerStatus::deserialize(&response).unwrap();
+        assert_eq!(data.status, Status::OK);
+        assert!(data.timestamp.as_milliseconds() >= before.as_milliseconds());
+        assert_eq!(req_id, 1);
+    }
+}
@@ -0,0 +1,306 @@
+//! Per client rate limiting. Every client has a token bucket per message tag, each message takes a token and the
+//! bucket is refilled over time. Player messages are limited by the public key, public messages by the source IP or
+//! by the connection if IP is unknown. Buckets are persisted in the storage, so limits work across lambda instances
+
+use std::{collections::HashMap, sync::Arc};
+
+use logic::{
+    datetime::ServerTimestamp,
+    server_error::{ErrorCode, ServerError},
+};
+
+use crate::{
+    entities::RateLimitBucket,
+    middleware::{MessageInfo, Middleware},
+    storage::{Key, Storage, StorageErr},
+};
+
+/// Token bucket limit, bucket holds up to `burst` tokens and gets `per_second` tokens back every second
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    /// Maximum amount of messages which can be sent at once
+    pub burst: u32,
+    /// Sustained amount of messages per second
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    /// Returns tokens in the bucket which had `tokens` at `updated_at` and was refilled until `now`
+    fn refill(&self, tokens: f64, updated_at: &ServerTimestamp, now: &ServerTimestamp) -> f64 {
+        let elapsed_ms = now
+            .as_milliseconds()
+            .saturating_sub(updated_at.as_milliseconds());
+        (tokens + elapsed_ms as f64 * self.per_second / 1000.0).min(self.burst as f64)
+    }
+
+    /// Returns timestamp when the bucket with `tokens` at `now` is full again
+    fn full_at(&self, tokens: f64, now: &ServerTimestamp) -> ServerTimestamp {
+        let missing = self.burst as f64 - tokens;
+        let refill_ms = (missing * 1000.0 / self.per_second).ceil() as u64;
+        ServerTimestamp::from_milliseconds_pure(now.as_milliseconds() + refill_ms)
+    }
+}
+
+/// Rate limits for every message tag, tags without their own limit use the default one
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimits {
+    default: RateLimit,
+    per_tag: HashMap<u16, RateLimit>,
+}
+
+impl Default for RateLimits {
+    // Limits are a pure guess for now, an active player sends a few messages per second at most
+    fn default() -> Self {
+        Self::new(RateLimit {
+            burst: 20,
+            per_second: 5.0,
+        })
+    }
+}
+
+impl RateLimits {
+    /// Creates limits where every message tag uses the given limit
+    pub fn new(default: RateLimit) -> Self {
+        Self {
+            default,
+            per_tag: HashMap::new(),
+        }
+    }
+
+    /// Overrides limit for messages with given tag
+    pub fn with_tag_limit(mut self, message_tag: u16, limit: RateLimit) -> Self {
+        self.per_tag.insert(message_tag, limit);
+        self
+    }
+
+    /// Returns limit for messages with given tag
+    pub fn limit(&self, message_tag: u16) -> &RateLimit {
+        self.per_tag.get(&message_tag).unwrap_or(&self.default)
+    }
+}
+
+/// How many times taking a token is attempted when other messages of the client change the bucket meanwhile
+const MAX_TOKEN_ATTEMPTS: u32 = 3;
+
+/// Takes a token from the client bucket for messages with given tag. Returns false if the bucket is empty and the
+/// message has to be rejected. Attempt is repeated if another message of the client took a token meanwhile, and
+/// `StorageErr::Conflict` is returned only if all `MAX_TOKEN_ATTEMPTS` fail
+pub async fn take_token(
+    storage: &impl Storage,
+    client: &str,
+    message_tag: u16,
+    limit: &RateLimit,
+    now: ServerTimestamp,
+) -> Result<bool, StorageErr> {
+    let key = RateLimitBucket::key_for(message_tag, client);
+    let mut attempt = 1;
+    loop {
+        match try_take_token(storage, key.clone(), limit, &now).await {
+            Err(StorageErr::Conflict) if attempt < MAX_TOKEN_ATTEMPTS => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+async fn try_take_token(
+    storage: &impl Storage,
+    key: Key,
+    limit: &RateLimit,
+    now: &ServerTimestamp,
+) -> Result<bool, StorageErr> {
+    // Bucket is written only if it wasn't changed since it was read, so concurrent messages never take the same token
+    let (tokens, version) = match storage.read_versioned::<RateLimitBucket>(key.clone()).await {
+        Ok((bucket, version)) => (
+            limit.refill(bucket.tokens, &bucket.updated_at, now),
+            version,
+        ),
+        Err(StorageErr::NotFound) => (limit.burst as f64, 0),
+        Err(err) => return Err(err),
+    };
+    if tokens < 1.0 {
+        return Ok(false);
+    }
+    let tokens = tokens - 1.0;
+    storage
+        .write_if_version(
+            &RateLimitBucket {
+                key,
+                tokens,
+                expires_at: limit.full_at(tokens, now),
+                updated_at: now.clone(),
+            },
+            version,
+        )
+        .await?;
+    Ok(true)
+}
+
+/// Middleware which rejects messages of clients which exceeded their rate limit
+pub struct RateLimitMiddleware<S: Storage> {
+    storage: Arc<S>,
+    limits: RateLimits,
+}
+
+impl<S: Storage> RateLimitMiddleware<S> {
+    /// Creates a new middleware which keeps buckets in the given storage
+    pub fn new(storage: Arc<S>, limits: RateLimits) -> Self {
+        Self { storage, limits }
+    }
+}
+
+impl<S: Storage> Middleware for RateLimitMiddleware<S> {
+    async fn before(&self, info: &MessageInfo<'_>) -> Result<(), ServerError> {
+        let (message_tag, request_id) = (info.message_tag, info.request_id);
+        let client = client_id(info);
+        let limit = self.limits.limit(message_tag);
+        let allowed = take_token(
+            self.storage.as_ref(),
+            &client,
+            message_tag,
+            limit,
+            ServerTimestamp::now(),
+        )
+        .await
+        .map_err(|err| err.into_server_error(message_tag, request_id))?;
+        if allowed {
+            return Ok(());
+        }
+        Err(ServerError {
+            error_code: ErrorCode::RateLimited,
+            error_description: "Too many messages, slow down and try again later".to_string(),
+            error_context: Some(format!(
+                "Limit is {} messages at once and {} per second",
+                limit.burst, limit.per_second
+            )),
+            request_id,
+            message_tag,
+            recoverable: true,
+        })
+    }
+}
+
+/// Identifier of the client which sent the message
+fn client_id(info: &MessageInfo<'_>) -> String {
+    if let Some(public_key) = &info.public_key {
+        return format!("key:{}", public_key.as_string());
+    }
+    let request_context = &info.event.request_context;
+    match (
+        &request_context.identity.source_ip,
+        &request_context.connection_id,
+    ) {
+        (Some(source_ip), _) => format!("ip:{}", source_ip),
+        (None, Some(connection_id)) => format!("connection:{}", connection_id),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::{
+        encryption::generate_new_keys,
+        messages::{
+            common::ping::{Ping, ServerStatus},
+            ClientPublicMessage,
+        },
+    };
+
+    use crate::{
+        common::ping::PingHandler, fixtures::event_with_body, lambda::process_public_event,
+        storage::storage_memory::MemoryStorage,
+    };
+
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        burst: 2,
+        per_second: 1.0,
+    };
+
+    #[tokio::test]
+    async fn token_bucket() {
+        let storage = MemoryStorage::new("test").await;
+        let at = ServerTimestamp::from_milliseconds_pure;
+        for (client, now, allowed) in [
+            ("a", 0, true),
+            ("a", 0, true),
+            ("a", 500, false),
+            // Other clients and other tags have their own buckets
+            ("b", 500, true),
+            ("a", 1000, true),
+            ("a", 1000, false),
+            // Bucket is never filled above the burst
+            ("a", 60_000, true),
+            ("a", 60_000, true),
+            ("a", 60_000, false),
+        ] {
+            assert_eq!(
+                take_token(&storage, client, 1, &LIMIT, at(now))
+                    .await
+                    .unwrap(),
+                allowed,
+                "{} at {}",
+                client,
+                now
+            );
+        }
+        assert!(take_token(&storage, "a", 2, &LIMIT, at(60_000))
+            .await
+            .unwrap());
+
+        let bucket: RateLimitBucket = storage
+            .read(RateLimitBucket::key_for(1, "a"))
+            .await
+            .unwrap();
+        assert_eq!(bucket.tokens, 0.0);
+        assert_eq!(bucket.expires_at.as_milliseconds(), 62_000);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_middleware() {
+        let storage = Arc::new(MemoryStorage::new("test").await);
+        let limits = RateLimits::new(RateLimit {
+            burst: 100,
+            per_second: 100.0,
+        })
+        .with_tag_limit(Ping::tag(), LIMIT);
+        assert_eq!(limits.limit(Ping::tag()), &LIMIT);
+        let middleware = &RateLimitMiddleware::new(storage, limits);
+
+        let ping = |request_id| async move {
+            let event = event_with_body(Ping {}.serialize(request_id).unwrap());
+            process_public_event(event, &PingHandler {}, middleware).await
+        };
+        assert!(ServerStatus::deserialize(&ping(1).await).is_ok());
+        assert!(ServerStatus::deserialize(&ping(2).await).is_ok());
+        let (error, request_id) = ServerError::deserialize(&ping(3).await).unwrap();
+        assert_eq!(error.error_code, ErrorCode::RateLimited);
+        assert_eq!(error.message_tag, Ping::tag());
+        assert_eq!(request_id, 3);
+        assert!(error.recoverable);
+    }
+
+    #[test]
+    fn client_identifiers() {
+        let mut event = event_with_body(String::new());
+        let mut info = MessageInfo {
+            event: &event.payload,
+            message_tag: 1,
+            request_id: 1,
+            public_key: None,
+        };
+        assert_eq!(client_id(&info), "ip:87.95.116.76");
+        let public_key = generate_new_keys().public_key;
+        info.public_key = Some(public_key.clone());
+        assert_eq!(client_id(&info), format!("key:{}", public_key.as_string()));
+
+        event.payload.request_context.identity.source_ip = None;
+        let info = MessageInfo {
+            event: &event.payload,
+            message_tag: 1,
+            request_id: 1,
+            public_key: None,
+        };
+        assert_eq!(client_id(&info), "connection:etAB-deZIAMCK2g=");
+    }
+}
@@ -0,0 +1,44 @@
+//! Replay protection for signed player messages. Stale messages are rejected during deserialization, while nonces
+//! of the fresh ones are remembered so the same message cannot be processed twice within the replay window
+
+use logic::{
+    datetime::{Duration, ServerTimestamp},
+    messages::serializers::MessageSignature,
+    server_error::{ErrorCode, ServerError},
+};
+
+use crate::{
+    entities::MessageNonce,
+    storage::{Storage, StorageErr},
+};
+
+/// Returns an error if the signed message nonce was already used, otherwise remembers it until the message
+/// timestamp gets outside of the replay window
+pub async fn check_nonce(
+    storage: &impl Storage,
+    signature: &MessageSignature,
+    window: &Duration,
+    message_tag: u16,
+    request_id: u32,
+) -> Result<(), ServerError> {
+    let key = MessageNonce::key_for(&signature.public_key.serialize(), &signature.nonce);
+    let expires_at = ServerTimestamp::from_milliseconds_pure(
+        signature.timestamp.as_milliseconds() + window.as_milliseconds(),
+    );
+    // Nonce is created only if it doesn't exist yet, so the same message processed concurrently fails as well
+    match storage
+        .write_if_version(&MessageNonce { key, expires_at }, 0)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(StorageErr::Conflict) => Err(ServerError {
+            error_code: ErrorCode::ReplayError,
+            error_description: "Message was already processed".to_string(),
+            error_context: None,
+            request_id,
+            message_tag,
+            recoverable: false,
+        }),
+        Err(err) => Err(err.into_server_error(message_tag, request_id)),
+    }
+}
@@ -0,0 +1,87 @@
+//! Handshake handler which replies to `Handshake` messages with the range of supported protocol versions
+
+use lambda_runtime::tracing;
+use logic::{
+    messages::{
+        common::version::{Handshake, HandshakeResult},
+        serializers::MIN_PROTOCOL_VERSION,
+        ClientPublicMessage,
+    },
+    server_error::{ErrorCode, ServerError},
+};
+
+use crate::lambda::{PublicEventHandler, RequestContext};
+
+/// Handler for `Handshake` messages. Outdated clients get an `OutdatedClient` error, while clients newer than the
+/// server are not accepted but may keep working with the older protocol
+pub struct HandshakeHandler {}
+
+impl PublicEventHandler<Handshake> for HandshakeHandler {
+    async fn process_message(
+        &self,
+        message: Handshake,
+        context: &RequestContext,
+        request_id: u32,
+    ) -> Result<HandshakeResult, ServerError> {
+        tracing::info!(
+            "Handshake connection_id={} protocol_version={} client_build={}",
+            context.connection_id,
+            message.protocol_version,
+            message.client_build
+        );
+        if message.protocol_version < MIN_PROTOCOL_VERSION {
+            return Err(ServerError {
+                error_code: ErrorCode::OutdatedClient,
+                error_description: "App is outdated, update it to continue playing".to_string(),
+                error_context: Some(format!(
+                    "Protocol version {} is older than the minimum supported {}",
+                    message.protocol_version, MIN_PROTOCOL_VERSION
+                )),
+                request_id,
+                message_tag: Handshake::tag(),
+                recoverable: false,
+            });
+        }
+        Ok(HandshakeResult::for_version(message.protocol_version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::messages::serializers::PROTOCOL_VERSION;
+
+    use crate::fixtures::request_context;
+
+    use super::*;
+
+    async fn handshake(protocol_version: u16) -> Result<HandshakeResult, ServerError> {
+        let message = Handshake {
+            protocol_version,
+            client_build: "test".to_string(),
+        };
+        HandshakeHandler {}
+            .process_message(message, &request_context(), 1)
+            .await
+    }
+
+    #[tokio::test]
+    async fn protocol_versions() {
+        let result = handshake(PROTOCOL_VERSION).await.unwrap();
+        assert_eq!(
+            result,
+            HandshakeResult {
+                accepted: true,
+                min_protocol_version: MIN_PROTOCOL_VERSION,
+                max_protocol_version: PROTOCOL_VERSION,
+            }
+        );
+        assert!(!handshake(PROTOCOL_VERSION + 1).await.unwrap().accepted);
+
+        // Clients before version 4 cannot decode tagged server messages
+        for protocol_version in [2, 3, MIN_PROTOCOL_VERSION - 1] {
+            let err = handshake(protocol_version).await.unwrap_err();
+            assert_eq!(err.error_code, ErrorCode::OutdatedClient);
+            assert!(!err.recoverable);
+        }
+    }
+}
@@ -1,14 +1,20 @@
 //! Entities
 
-use std::{collections::HashMap, str::FromStr};
+use std::{str::FromStr, sync::Arc};
 
-use aws_sdk_dynamodb::{
-    operation::put_item::builders::PutItemFluentBuilder, types::AttributeValue,
+use binary_encoding::encode_base94;
+use logic::{
+    datetime::{Date, Duration, ServerTimestamp},
+    decay::DecayModifier,
+    encryption::{PublicKey, SafeString},
+    goal::{Goal, GoalCategory, GoalStatus},
 };
-use logic::datetime::ServerTimestamp;
 use ulid::Ulid;
 
-use crate::storage::{Entity, Key, StorageErr};
+use crate::storage::{Entity, Key};
+
+/// Amount of partitions system entities with heavy traffic are spread over, see `UserId::system_shard`
+pub const SYSTEM_SHARDS: u64 = 16;
 
 /// User identifier, randomly generated ULID
 #[derive(Debug, PartialEq, Clone)]
@@ -24,6 +30,23 @@ impl UserId {
     pub fn as_str(&self) -> String {
         self.0.to_string()
     }
+
+    /// Reserved user identifier for the entities which don't belong to any player yet, e.g. connections which
+    /// are not bound to any player
+    pub fn system() -> Self {
+        Self(Ulid::nil())
+    }
+
+    /// Reserved user identifier for system entities with heavy traffic, which are spread over `SYSTEM_SHARDS`
+    /// partitions by a stable hash of the given value, so they don't all hit the same partition. Shards never
+    /// collide with `system()` or generated identifiers
+    pub fn system_shard(value: &str) -> Self {
+        // FNV-1a, unlike the std hasher it's guaranteed to be the same on every lambda instance
+        let hash = value.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        Self(Ulid::from(1 + (hash % SYSTEM_SHARDS) as u128))
+    }
 }
 
 impl FromStr for UserId {
@@ -37,7 +60,8 @@ impl FromStr for UserId {
 }
 
 /// Player account
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Entity)]
+#[entity_type = "account"]
 pub struct Account {
     // TODO Having public fields is handy during development, but should be removed once we know access patters that we need
     /// Account key
@@ -46,47 +70,283 @@ pub struct Account {
     pub created_at: ServerTimestamp,
 }
 
-impl Entity for Account {
-    fn entity_type() -> &'static str {
-        "account"
+impl Account {
+    /// Generate new account with random user_id
+    pub fn generate() -> Self {
+        let user_id = UserId::generate();
+        // For account entity id is the same as a user id
+        let entity_id = user_id.as_str().to_string();
+        Self {
+            created_at: ServerTimestamp::now(),
+            key: Key { user_id, entity_id },
+        }
     }
+}
 
-    fn key(&self) -> &Key {
-        &self.key
+/// Decay of the player, created once per player and never restarted
+#[derive(Debug, PartialEq, Entity)]
+#[entity_type = "decay"]
+pub struct PlayerDecay {
+    /// Decay key, as there is only one Decay per player entity id is the same as a user id
+    pub key: Key,
+    /// Timestamp when Decay was started
+    pub started_at: ServerTimestamp,
+    /// How long Decay lasts
+    pub length: Arc<Duration>,
+    /// Modifiers applied to the Decay ordered by their timestamps
+    pub modifiers: Vec<DecayModifier>,
+}
+
+impl PlayerDecay {
+    /// Returns key of the Decay of the given player
+    pub fn key_for(user_id: &UserId) -> Key {
+        Key {
+            user_id: user_id.clone(),
+            entity_id: user_id.as_str(),
+        }
     }
+}
 
-    fn serialize(&self, writer: PutItemFluentBuilder) -> PutItemFluentBuilder {
-        writer.item("created_at", AttributeValue::N(self.created_at.as_string()))
+/// Identity of the player. Encrypted names are stored as is, server never sees their plaintext
+#[derive(Debug, PartialEq, Entity)]
+#[entity_type = "identity"]
+pub struct PlayerIdentity {
+    /// Identity key, as there is only one identity per player entity id is the same as a user id
+    pub key: Key,
+    /// Identity name
+    pub name: SafeString,
+}
+
+impl PlayerIdentity {
+    /// Returns key of the identity of the given player
+    pub fn key_for(user_id: &UserId) -> Key {
+        Key {
+            user_id: user_id.clone(),
+            entity_id: user_id.as_str(),
+        }
     }
+}
 
-    fn deserialize(key: Key, data: HashMap<String, AttributeValue>) -> Result<Self, StorageErr> {
-        let created_at = read_number_attribute(data, "created_at")?;
-        Ok(Self { key, created_at })
+/// Goal of the player, entity id is the goal id so goals are ordered by their creation time
+#[derive(Debug, PartialEq, Entity)]
+#[entity_type = "goal"]
+pub struct PlayerGoal {
+    /// Goal key
+    pub key: Key,
+    /// Goal title, could be encrypted so only the player can read it
+    pub title: SafeString,
+    /// Goal category
+    pub category: GoalCategory,
+    /// Date by which the player wants to reach the goal
+    pub target_date: Arc<Date>,
+    /// Goal status
+    pub status: GoalStatus,
+    /// Timestamp when goal was created
+    pub created_at: Arc<ServerTimestamp>,
+    /// Timestamp when goal was completed
+    pub completed_at: Option<Arc<ServerTimestamp>>,
+}
+
+impl PlayerGoal {
+    /// Creates a new entity for the goal of the given player
+    pub fn new(user_id: &UserId, goal: Goal) -> Self {
+        Self {
+            key: PlayerGoal::key_for(user_id, &goal.goal_id),
+            title: goal.title,
+            category: goal.category,
+            target_date: goal.target_date,
+            status: goal.status,
+            created_at: goal.created_at,
+            completed_at: goal.completed_at,
+        }
+    }
+
+    /// Returns key of the goal of the given player
+    pub fn key_for(user_id: &UserId, goal_id: &str) -> Key {
+        Key {
+            user_id: user_id.clone(),
+            entity_id: goal_id.to_string(),
+        }
+    }
+
+    /// Returns the goal itself, its id is always the same as the key entity id
+    pub fn into_goal(self) -> Goal {
+        Goal {
+            goal_id: self.key.entity_id,
+            title: self.title,
+            category: self.category,
+            target_date: self.target_date,
+            status: self.status,
+            created_at: self.created_at,
+            completed_at: self.completed_at,
+        }
     }
 }
 
-impl Account {
-    /// Generate new account with random user_id
-    pub fn generate() -> Self {
-        let user_id = UserId::generate();
-        // For account entity id is the same as a user id
-        let entity_id = user_id.as_str().to_string();
+/// Secondary key which maps player public key to the account. Stored under the system user id with encoded public
+/// key as an entity id, so the account can be found when only the public key is known. Entity type doesn't start
+/// with `account`, as finding accounts would pick up account keys as well
+#[derive(Debug, PartialEq, Entity)]
+#[entity_type = "key_account"]
+pub struct AccountKey {
+    /// Key where entity id is an encoded public key
+    pub key: Key,
+    /// User id of the account
+    pub user_id: UserId,
+}
+
+impl AccountKey {
+    /// Returns key of an account key for the given public key
+    pub fn key_for(public_key: &PublicKey) -> Key {
+        Key {
+            user_id: UserId::system(),
+            entity_id: public_key.as_string(),
+        }
+    }
+}
+
+/// WebSocket connection of a client. Connections are created before the player is known, so they are stored under
+/// the system user id with connection id as an entity id
+#[derive(Debug, PartialEq, Entity)]
+#[entity_type = "connection"]
+pub struct Connection {
+    /// Connection key
+    pub key: Key,
+    /// Timestamp when connection was established
+    pub connected_at: ServerTimestamp,
+    /// IP address of the client
+    pub source_ip: String,
+    /// Player to which connection is bound, if any
+    pub user_id: Option<UserId>,
+    /// Serialized public key of the player if connection was authenticated
+    pub public_key: Option<Vec<u8>>,
+    /// Nonce of the last issued authentication challenge which is not answered yet
+    pub challenge: Option<Vec<u8>>,
+}
+
+impl Connection {
+    /// Creates a new connection which is not bound to any player
+    pub fn new(connection_id: &str, connected_at: ServerTimestamp, source_ip: String) -> Self {
         Self {
-            created_at: ServerTimestamp::now(),
-            key: Key { user_id, entity_id },
+            key: Connection::key_for(connection_id),
+            connected_at,
+            source_ip,
+            user_id: None,
+            public_key: None,
+            challenge: None,
+        }
+    }
+
+    /// Returns key of a connection with the given connection id
+    pub fn key_for(connection_id: &str) -> Key {
+        Key {
+            user_id: UserId::system(),
+            entity_id: connection_id.to_string(),
+        }
+    }
+
+    /// Returns API Gateway connection id
+    pub fn connection_id(&self) -> &str {
+        &self.key.entity_id
+    }
+}
+
+/// Live connection of a player, stored in the player partition so all the connections of a player can be found
+#[derive(Debug, PartialEq, Entity)]
+#[entity_type = "player_connection"]
+pub struct PlayerConnection {
+    /// Player connection key where entity id is a connection id
+    pub key: Key,
+    /// Timestamp when connection was established
+    pub connected_at: ServerTimestamp,
+}
+
+impl PlayerConnection {
+    /// Returns API Gateway connection id
+    pub fn connection_id(&self) -> &str {
+        &self.key.entity_id
+    }
+}
+
+/// Nonce of an already processed signed player message. It's kept until the message timestamp gets outside of the
+/// replay window, so the same message cannot be processed twice. Stored under a system shard of the public key as
+/// the nonce belongs to the public key which may not be bound to any player yet
+#[derive(Debug, PartialEq, Entity)]
+#[entity_type = "message_nonce"]
+pub struct MessageNonce {
+    /// Key where entity id is an encoded public key and nonce
+    pub key: Key,
+    /// Timestamp after which nonce can be removed, stored with seconds precision as DynamoDB TTL requires
+    #[ttl]
+    pub expires_at: ServerTimestamp,
+}
+
+impl MessageNonce {
+    /// Returns key of a nonce used by the given public key, nonces of different public keys are spread over system
+    /// shards as every signed message writes one
+    pub fn key_for(public_key: &[u8], nonce: &[u8]) -> Key {
+        let mut data = Vec::with_capacity(public_key.len() + nonce.len());
+        data.extend_from_slice(public_key);
+        data.extend_from_slice(nonce);
+        Key {
+            user_id: UserId::system_shard(&encode_base94(public_key)),
+            entity_id: encode_base94(&data),
         }
     }
 }
 
-fn read_number_attribute<T: FromStr>(
-    attributes: HashMap<String, AttributeValue>,
-    key: &str,
-) -> Result<T, StorageErr> {
-    attributes
-        .get(key)
-        .ok_or_else(|| StorageErr::ValidationError(format!("{} attribute not found", key)))?
-        .as_n()
-        .map_err(|_| StorageErr::ValidationError(format!("{} is not a number attribute", key)))?
-        .parse()
-        .map_err(|_| StorageErr::ValidationError(format!("{} cannot be parsed as number", key)))
+/// Serialized response to a player message with an idempotency key, it's sent back instead of processing retries of
+/// the message again. The key is reserved without a response while the message is processed. Stored under a system
+/// shard of the public key like nonces, as keys belong to the public key
+#[derive(Debug, PartialEq, Entity)]
+#[entity_type = "idempotent_response"]
+pub struct IdempotentResponse {
+    /// Key where entity id is the message tag, idempotency key and an encoded public key
+    pub key: Key,
+    /// Serialized server message sent back to the original message, `None` while the message is being processed
+    pub response: Option<String>,
+    /// Timestamp after which the response can be removed or the reservation taken over, stored with seconds
+    /// precision as DynamoDB TTL requires
+    #[ttl]
+    pub expires_at: ServerTimestamp,
+}
+
+impl IdempotentResponse {
+    /// Returns key of a response to the message with given tag and idempotency key sent by the public key owner.
+    /// Idempotency keys never contain colons, so the public key part cannot be confused
+    pub fn key_for(public_key: &[u8], message_tag: u16, idempotency_key: &str) -> Key {
+        let public_key = encode_base94(public_key);
+        Key {
+            user_id: UserId::system_shard(&public_key),
+            entity_id: format!("{}:{}:{}", message_tag, idempotency_key, public_key),
+        }
+    }
+}
+
+/// Token bucket of the rate limiter for a single client and message tag. Stored under a system shard as clients of
+/// public messages are not bound to any player
+#[derive(Debug, PartialEq, Entity)]
+#[entity_type = "rate_limit"]
+pub struct RateLimitBucket {
+    /// Key where entity id is the message tag and the client identifier
+    pub key: Key,
+    /// Tokens left in the bucket at `updated_at`
+    pub tokens: f64,
+    /// Timestamp when tokens were counted
+    pub updated_at: ServerTimestamp,
+    /// Timestamp after which the bucket is full again and can be removed, stored with seconds precision as
+    /// DynamoDB TTL requires
+    #[ttl]
+    pub expires_at: ServerTimestamp,
+}
+
+impl RateLimitBucket {
+    /// Returns key of a bucket for the client messages with given tag, buckets of different clients are spread over
+    /// system shards as every message takes a token
+    pub fn key_for(message_tag: u16, client: &str) -> Key {
+        Key {
+            user_id: UserId::system_shard(client),
+            entity_id: format!("{}:{}", message_tag, client),
+        }
+    }
 }
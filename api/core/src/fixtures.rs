@@ -1,8 +1,19 @@
 //! Fixtures for testing
 
+use std::pin::Pin;
+
 use aws_lambda_events::apigw::ApiGatewayWebsocketProxyRequest;
+use futures::Stream;
 use lambda_runtime::{Context, LambdaEvent};
 
+use crate::{
+    entities::UserId,
+    lambda::RequestContext,
+    storage::{
+        storage_memory::MemoryStorage, transaction::Transaction, Entity, Key, Storage, StorageErr,
+    },
+};
+
 /// Returns API Gateway Websocket full serialized request which can be used for testing
 pub fn event_with_body(body: String) -> LambdaEvent<ApiGatewayWebsocketProxyRequest> {
     let api_gateway_request = r#"{
@@ -60,3 +71,75 @@ pub fn event_with_body(body: String) -> LambdaEvent<ApiGatewayWebsocketProxyRequ
     request.body = Some(body);
     LambdaEvent::new(request, Context::default())
 }
+
+/// Returns request context of the `event_with_body` request which can be passed to handlers in tests
+pub fn request_context() -> RequestContext {
+    RequestContext::new(&event_with_body(String::new()).payload)
+}
+
+/// Memory storage which yields to other tasks before every operation, so messages processed concurrently within a
+/// test interleave between reading an entity and writing it back the same way they do in production
+pub struct InterleavingStorage(MemoryStorage);
+
+impl Storage for InterleavingStorage {
+    async fn new(table: &'static str) -> Self {
+        Self(MemoryStorage::new(table).await)
+    }
+
+    async fn write<T: Entity>(&self, entity: &T) -> Result<(), StorageErr> {
+        tokio::task::yield_now().await;
+        self.0.write(entity).await
+    }
+
+    async fn write_if_version<T: Entity>(
+        &self,
+        entity: &T,
+        version: u64,
+    ) -> Result<u64, StorageErr> {
+        tokio::task::yield_now().await;
+        self.0.write_if_version(entity, version).await
+    }
+
+    fn transaction(&self) -> Transaction {
+        self.0.transaction()
+    }
+
+    async fn commit(&self, transaction: Transaction) -> Result<(), StorageErr> {
+        tokio::task::yield_now().await;
+        self.0.commit(transaction).await
+    }
+
+    async fn read_versioned<T>(&self, key: Key) -> Result<(T, u64), StorageErr>
+    where
+        T: Entity,
+    {
+        tokio::task::yield_now().await;
+        self.0.read_versioned(key).await
+    }
+
+    async fn read<T>(&self, key: Key) -> Result<T, StorageErr>
+    where
+        T: Entity,
+    {
+        tokio::task::yield_now().await;
+        self.0.read(key).await
+    }
+
+    async fn find<T>(&self, user_id: &UserId) -> Pin<Box<dyn Stream<Item = Result<T, StorageErr>>>>
+    where
+        T: Entity + 'static,
+    {
+        tokio::task::yield_now().await;
+        self.0.find(user_id).await
+    }
+
+    async fn delete(
+        &self,
+        user_id: &UserId,
+        entity_type: Option<&str>,
+        entity_id: Option<&str>,
+    ) -> Result<usize, StorageErr> {
+        tokio::task::yield_now().await;
+        self.0.delete(user_id, entity_type, entity_id).await
+    }
+}
@@ -0,0 +1,265 @@
+//! Decay handler which replies to `DecayQuery` messages with the player's `Decay`
+
+use std::sync::Arc;
+
+use logic::{
+    datetime::{Duration, ServerTimestamp},
+    decay::{decay_ends_at, DecayModifier},
+    encryption::PublicKey,
+    messages::{
+        game::decay::{Decay, DecayQuery},
+        ClientPlayerMessage,
+    },
+    server_error::ServerError,
+};
+
+use crate::{
+    entities::{PlayerDecay, UserId},
+    lambda::{PlayerEventHandler, RequestContext},
+    storage::{Storage, StorageErr},
+};
+
+/// How long the Decay lasts in days
+pub const DECAY_DURATION_DAYS: u64 = 365 * 10 + 1;
+
+/// How many times applying a modifier is attempted when the Decay is changed by other messages meanwhile
+const MAX_MODIFIER_ATTEMPTS: u32 = 3;
+
+/// Returns the Decay of the player, the Decay is started at `now` if the player doesn't have one yet
+pub async fn player_decay(
+    storage: &impl Storage,
+    user_id: &UserId,
+    now: ServerTimestamp,
+) -> Result<PlayerDecay, StorageErr> {
+    Ok(player_decay_versioned(storage, user_id, now).await?.0)
+}
+
+/// Returns the Decay of the player together with its version, Decay which is started by another message meanwhile
+/// is returned instead of the new one
+async fn player_decay_versioned(
+    storage: &impl Storage,
+    user_id: &UserId,
+    now: ServerTimestamp,
+) -> Result<(PlayerDecay, u64), StorageErr> {
+    match storage.read_versioned(PlayerDecay::key_for(user_id)).await {
+        Ok(decay) => return Ok(decay),
+        Err(StorageErr::NotFound) => {}
+        Err(err) => return Err(err),
+    }
+    let decay = PlayerDecay {
+        key: PlayerDecay::key_for(user_id),
+        started_at: now,
+        length: Duration::from_milliseconds(DECAY_DURATION_DAYS * 24 * 60 * 60 * 1000),
+        modifiers: vec![],
+    };
+    // Decay is created only if it doesn't exist yet, so concurrent messages never restart it
+    match storage.write_if_version(&decay, 0).await {
+        Ok(version) => Ok((decay, version)),
+        Err(StorageErr::Conflict) => storage.read_versioned(PlayerDecay::key_for(user_id)).await,
+        Err(err) => Err(err),
+    }
+}
+
+/// Applies modifier to the Decay of the player, modifier timestamp is expected to be not earlier than the
+/// previously applied ones. Attempt is repeated if the Decay was changed meanwhile, and `StorageErr::Conflict` is
+/// returned only if all `MAX_MODIFIER_ATTEMPTS` fail
+pub async fn add_decay_modifier(
+    storage: &impl Storage,
+    user_id: &UserId,
+    modifier: DecayModifier,
+) -> Result<PlayerDecay, StorageErr> {
+    let mut attempt = 1;
+    loop {
+        match try_add_decay_modifier(storage, user_id, modifier.clone()).await {
+            Err(StorageErr::Conflict) if attempt < MAX_MODIFIER_ATTEMPTS => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+async fn try_add_decay_modifier(
+    storage: &impl Storage,
+    user_id: &UserId,
+    modifier: DecayModifier,
+) -> Result<PlayerDecay, StorageErr> {
+    let now = modifier.applied_at.as_ref().clone();
+    let (mut decay, version) = player_decay_versioned(storage, user_id, now).await?;
+    decay.modifiers.push(modifier);
+    storage.write_if_version(&decay, version).await?;
+    Ok(decay)
+}
+
+/// Handler for `DecayQuery` messages
+pub struct DecayHandler<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> DecayHandler<S> {
+    /// Creates a new handler which uses given storage
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+
+    async fn process(
+        &self,
+        user_id: &UserId,
+        request_id: u32,
+        now: ServerTimestamp,
+    ) -> Result<Decay, ServerError> {
+        let decay = player_decay(self.storage.as_ref(), user_id, now)
+            .await
+            .map_err(|err| err.into_server_error(DecayQuery::tag(), request_id))?;
+        Ok(Decay {
+            ends_at: decay_ends_at(&decay.started_at, &decay.length, &decay.modifiers),
+            started_at: Arc::new(decay.started_at),
+            length: decay.length,
+            modifiers: decay.modifiers,
+        })
+    }
+}
+
+impl<S: Storage> PlayerEventHandler<DecayQuery> for DecayHandler<S> {
+    async fn process_message(
+        &self,
+        _: DecayQuery,
+        _: &RequestContext,
+        _: Arc<PublicKey>,
+        user_id: UserId,
+        request_id: u32,
+    ) -> Result<Decay, ServerError> {
+        self.process(&user_id, request_id, ServerTimestamp::now())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::decay::DecayModifierKind;
+
+    use crate::{fixtures::InterleavingStorage, storage::storage_memory::MemoryStorage};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn process_message_ok() {
+        let handler = DecayHandler::new(Arc::new(MemoryStorage::new("test").await));
+        let now = ServerTimestamp::from_milliseconds_pure(10);
+        let decay = handler
+            .process(&UserId::generate(), 1, now.clone())
+            .await
+            .unwrap();
+        assert_eq!(*decay.started_at, now);
+        assert_eq!(decay.length.whole_days(), DECAY_DURATION_DAYS);
+        assert_eq!(
+            decay.ends_at.unwrap().as_milliseconds(),
+            10 + decay.length.as_milliseconds()
+        );
+        assert!(decay.modifiers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn decay_is_stable() {
+        let handler = DecayHandler::new(Arc::new(MemoryStorage::new("test").await));
+        let user_id = UserId::generate();
+        let started_at = ServerTimestamp::from_milliseconds_pure(10);
+        let first = handler
+            .process(&user_id, 1, started_at.clone())
+            .await
+            .unwrap();
+
+        // Later queries return the same Decay
+        let decay = handler
+            .process(&user_id, 1, ServerTimestamp::from_milliseconds_pure(20))
+            .await
+            .unwrap();
+        assert_eq!(decay, first);
+        assert_eq!(*decay.started_at, started_at);
+
+        // While every player has its own one
+        let decay = handler
+            .process(
+                &UserId::generate(),
+                1,
+                ServerTimestamp::from_milliseconds_pure(30),
+            )
+            .await
+            .unwrap();
+        assert_eq!(decay.started_at.as_milliseconds(), 30);
+    }
+
+    #[tokio::test]
+    async fn decay_modifiers() {
+        let storage = Arc::new(MemoryStorage::new("test").await);
+        let handler = DecayHandler::new(storage.clone());
+        let user_id = UserId::generate();
+        let day = 24 * 60 * 60 * 1000;
+        handler
+            .process(&user_id, 1, ServerTimestamp::from_milliseconds_pure(0))
+            .await
+            .unwrap();
+        let modifier = DecayModifier {
+            applied_at: ServerTimestamp::from_milliseconds(day),
+            kind: DecayModifierKind::Delay {
+                duration: Duration::from_milliseconds(2 * day),
+            },
+        };
+        add_decay_modifier(storage.as_ref(), &user_id, modifier.clone())
+            .await
+            .unwrap();
+
+        let decay = handler
+            .process(&user_id, 1, ServerTimestamp::from_milliseconds_pure(day))
+            .await
+            .unwrap();
+        assert_eq!(decay.modifiers, vec![modifier]);
+        assert_eq!(
+            decay.ends_at.unwrap().as_milliseconds(),
+            decay.length.as_milliseconds() + 2 * day
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_decay_modifiers() {
+        let storage = InterleavingStorage::new("test").await;
+        let user_id = UserId::generate();
+        let day = 24 * 60 * 60 * 1000;
+        let delay = |applied_at| DecayModifier {
+            applied_at: ServerTimestamp::from_milliseconds(applied_at),
+            kind: DecayModifierKind::Delay {
+                duration: Duration::from_milliseconds(day),
+            },
+        };
+
+        // Both messages start the Decay, but only one of them creates it
+        let (first, second) = tokio::join!(
+            player_decay(
+                &storage,
+                &user_id,
+                ServerTimestamp::from_milliseconds_pure(1)
+            ),
+            player_decay(
+                &storage,
+                &user_id,
+                ServerTimestamp::from_milliseconds_pure(2)
+            ),
+        );
+        assert_eq!(first.unwrap(), second.unwrap());
+
+        // Both messages read the Decay before any of them writes it back, none of the modifiers is lost
+        let (first, second) = tokio::join!(
+            add_decay_modifier(&storage, &user_id, delay(day)),
+            add_decay_modifier(&storage, &user_id, delay(day)),
+        );
+        first.unwrap();
+        second.unwrap();
+        let decay = player_decay(
+            &storage,
+            &user_id,
+            ServerTimestamp::from_milliseconds_pure(3),
+        )
+        .await
+        .unwrap();
+        assert_eq!(decay.started_at.as_milliseconds(), 1);
+        assert_eq!(decay.modifiers, vec![delay(day), delay(day)]);
+    }
+}
@@ -0,0 +1,366 @@
+//! Goals handler which creates and changes the player's goals. Every message is replied with the full list of
+//! the player's goals, so the client always has the actual state
+
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use logic::{
+    datetime::ServerTimestamp,
+    encryption::PublicKey,
+    goal::{Goal, GoalError, GoalStatus},
+    messages::{
+        game::goals::{ArchiveGoal, CompleteGoal, CreateGoal, Goals, GoalsQuery, UpdateGoal},
+        serializers::SerializationError,
+        ClientPlayerMessage,
+    },
+    server_error::{ErrorCode, ServerError},
+};
+use ulid::Ulid;
+
+use crate::{
+    entities::{PlayerGoal, UserId},
+    lambda::{PlayerEventHandler, RequestContext},
+    storage::{Storage, StorageErr},
+};
+
+/// Returns all the goals of the player ordered by their creation time
+pub async fn player_goals(
+    storage: &impl Storage,
+    user_id: &UserId,
+) -> Result<Vec<PlayerGoal>, StorageErr> {
+    storage.find(user_id).await.try_collect().await
+}
+
+/// Handler for all the goal messages, a separate lambda is deployed for every message type
+pub struct GoalsHandler<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> GoalsHandler<S> {
+    /// Creates a new handler which uses given storage
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+
+    /// Applies change to the existing goal and replies with all the player's goals. Goal is written only if it wasn't
+    /// changed concurrently, so simultaneous changes never overwrite each other
+    async fn change_goal(
+        &self,
+        user_id: &UserId,
+        goal_id: &str,
+        tag: u16,
+        request_id: u32,
+        change: impl FnOnce(&mut Goal) -> Result<(), GoalError>,
+    ) -> Result<Goals, ServerError> {
+        let (entity, version): (PlayerGoal, _) = match self
+            .storage
+            .read_versioned(PlayerGoal::key_for(user_id, goal_id))
+            .await
+        {
+            Ok(versioned) => versioned,
+            Err(StorageErr::NotFound) => {
+                return Err(invalid_goal_error("Goal not found", None, tag, request_id))
+            }
+            Err(err) => return Err(err.into_server_error(tag, request_id)),
+        };
+        let mut goal = entity.into_goal();
+        change(&mut goal).map_err(|err| match err {
+            GoalError::InvalidData(err) => {
+                invalid_goal_error("Goal is not valid", Some(err), tag, request_id)
+            }
+            GoalError::InvalidState { .. } => ServerError {
+                error_code: ErrorCode::InvalidGoalState,
+                error_description: "Goal cannot be changed".to_string(),
+                error_context: Some(err.to_string()),
+                request_id,
+                message_tag: tag,
+                recoverable: false,
+            },
+        })?;
+        self.storage
+            .write_if_version(&PlayerGoal::new(user_id, goal), version)
+            .await
+            .map_err(|err| err.into_server_error(tag, request_id))?;
+        self.goals(user_id, tag, request_id).await
+    }
+
+    async fn goals(
+        &self,
+        user_id: &UserId,
+        tag: u16,
+        request_id: u32,
+    ) -> Result<Goals, ServerError> {
+        let goals = player_goals(self.storage.as_ref(), user_id)
+            .await
+            .map_err(|err| err.into_server_error(tag, request_id))?;
+        Ok(Goals {
+            goals: goals.into_iter().map(PlayerGoal::into_goal).collect(),
+        })
+    }
+}
+
+impl<S: Storage> PlayerEventHandler<CreateGoal> for GoalsHandler<S> {
+    async fn process_message(
+        &self,
+        message: CreateGoal,
+        _: &RequestContext,
+        _: Arc<PublicKey>,
+        user_id: UserId,
+        request_id: u32,
+    ) -> Result<Goals, ServerError> {
+        let tag = CreateGoal::tag();
+        Goal::validate_title(&message.title)
+            .map_err(|err| invalid_goal_error("Goal is not valid", Some(err), tag, request_id))?;
+        let goal = Goal {
+            goal_id: Ulid::new().to_string(),
+            title: message.title,
+            category: message.category,
+            target_date: message.target_date,
+            status: GoalStatus::Active,
+            created_at: Arc::new(ServerTimestamp::now()),
+            completed_at: None,
+        };
+        self.storage
+            .write(&PlayerGoal::new(&user_id, goal))
+            .await
+            .map_err(|err| err.into_server_error(tag, request_id))?;
+        self.goals(&user_id, tag, request_id).await
+    }
+}
+
+impl<S: Storage> PlayerEventHandler<UpdateGoal> for GoalsHandler<S> {
+    async fn process_message(
+        &self,
+        message: UpdateGoal,
+        _: &RequestContext,
+        _: Arc<PublicKey>,
+        user_id: UserId,
+        request_id: u32,
+    ) -> Result<Goals, ServerError> {
+        self.change_goal(
+            &user_id,
+            &message.goal_id,
+            UpdateGoal::tag(),
+            request_id,
+            |goal| goal.update(message.title, message.category, message.target_date),
+        )
+        .await
+    }
+}
+
+impl<S: Storage> PlayerEventHandler<CompleteGoal> for GoalsHandler<S> {
+    async fn process_message(
+        &self,
+        message: CompleteGoal,
+        _: &RequestContext,
+        _: Arc<PublicKey>,
+        user_id: UserId,
+        request_id: u32,
+    ) -> Result<Goals, ServerError> {
+        self.change_goal(
+            &user_id,
+            &message.goal_id,
+            CompleteGoal::tag(),
+            request_id,
+            |goal| goal.complete(ServerTimestamp::now()),
+        )
+        .await
+    }
+}
+
+impl<S: Storage> PlayerEventHandler<ArchiveGoal> for GoalsHandler<S> {
+    async fn process_message(
+        &self,
+        message: ArchiveGoal,
+        _: &RequestContext,
+        _: Arc<PublicKey>,
+        user_id: UserId,
+        request_id: u32,
+    ) -> Result<Goals, ServerError> {
+        self.change_goal(
+            &user_id,
+            &message.goal_id,
+            ArchiveGoal::tag(),
+            request_id,
+            |goal| goal.archive(),
+        )
+        .await
+    }
+}
+
+impl<S: Storage> PlayerEventHandler<GoalsQuery> for GoalsHandler<S> {
+    async fn process_message(
+        &self,
+        _: GoalsQuery,
+        _: &RequestContext,
+        _: Arc<PublicKey>,
+        user_id: UserId,
+        request_id: u32,
+    ) -> Result<Goals, ServerError> {
+        self.goals(&user_id, GoalsQuery::tag(), request_id).await
+    }
+}
+
+fn invalid_goal_error(
+    description: &str,
+    err: Option<SerializationError>,
+    message_tag: u16,
+    request_id: u32,
+) -> ServerError {
+    ServerError {
+        error_code: ErrorCode::InvalidData,
+        error_description: description.to_string(),
+        error_context: err.map(|err| err.to_string()),
+        request_id,
+        message_tag,
+        recoverable: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::{
+        datetime::Date,
+        encryption::{generate_new_keys, EncryptedString, SafeString},
+        goal::GoalCategory,
+    };
+
+    use crate::{fixtures::request_context, storage::storage_memory::MemoryStorage};
+
+    use super::*;
+
+    struct Player {
+        handler: GoalsHandler<MemoryStorage>,
+        public_key: Arc<PublicKey>,
+        user_id: UserId,
+    }
+
+    impl Player {
+        async fn new() -> Self {
+            Self {
+                handler: GoalsHandler::new(Arc::new(MemoryStorage::new("test").await)),
+                public_key: generate_new_keys().public_key,
+                user_id: UserId::generate(),
+            }
+        }
+
+        async fn send<T>(&self, message: T) -> Result<Vec<Goal>, ServerError>
+        where
+            T: ClientPlayerMessage<Response = Goals>,
+            GoalsHandler<MemoryStorage>: PlayerEventHandler<T>,
+        {
+            let response = self
+                .handler
+                .process_message(
+                    message,
+                    &request_context(),
+                    self.public_key.clone(),
+                    self.user_id.clone(),
+                    1,
+                )
+                .await?;
+            Ok(response.goals)
+        }
+    }
+
+    fn plaintext(value: &str) -> SafeString {
+        SafeString::Plaintext {
+            value: value.to_string(),
+        }
+    }
+
+    fn goal<'a>(goals: &'a [Goal], goal_id: &str) -> &'a Goal {
+        goals.iter().find(|goal| goal.goal_id == goal_id).unwrap()
+    }
+
+    fn create_goal(title: SafeString) -> CreateGoal {
+        CreateGoal {
+            title,
+            category: GoalCategory::Learning,
+            target_date: Arc::new(Date::new(2030, 1, 1)),
+        }
+    }
+
+    #[tokio::test]
+    async fn goals_lifecycle() {
+        let player = Player::new().await;
+        assert!(player.send(GoalsQuery {}).await.unwrap().is_empty());
+
+        let goals = player
+            .send(create_goal(plaintext("Learn Rust")))
+            .await
+            .unwrap();
+        assert_eq!(goals.len(), 1);
+        assert_eq!(goals[0].status, GoalStatus::Active);
+        let goal_id = goals[0].goal_id.clone();
+
+        let keys = generate_new_keys();
+        let secret = SafeString::Encrypted {
+            data: EncryptedString::new("Secret".to_string(), &keys.private_key),
+        };
+        let goals = player.send(create_goal(secret.clone())).await.unwrap();
+        assert_eq!(goals.len(), 2);
+        let secret_goal = goals.iter().find(|goal| goal.goal_id != goal_id).unwrap();
+        assert_eq!(secret_goal.title, secret);
+
+        let goals = player
+            .send(UpdateGoal {
+                goal_id: goal_id.clone(),
+                title: plaintext("Learn Rust well"),
+                category: GoalCategory::Career,
+                target_date: Arc::new(Date::new(2031, 1, 1)),
+            })
+            .await
+            .unwrap();
+        assert_eq!(goal(&goals, &goal_id).title, plaintext("Learn Rust well"));
+        assert_eq!(goal(&goals, &goal_id).category, GoalCategory::Career);
+        assert_eq!(*goal(&goals, &goal_id).target_date, Date::new(2031, 1, 1));
+
+        let goals = player
+            .send(CompleteGoal {
+                goal_id: goal_id.clone(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(goal(&goals, &goal_id).status, GoalStatus::Completed);
+        assert!(goal(&goals, &goal_id).completed_at.is_some());
+
+        let goals = player
+            .send(ArchiveGoal {
+                goal_id: goal_id.clone(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(goal(&goals, &goal_id).status, GoalStatus::Archived);
+        assert_eq!(player.send(GoalsQuery {}).await.unwrap(), goals);
+    }
+
+    #[tokio::test]
+    async fn goals_invalid_changes() {
+        let player = Player::new().await;
+        let err = player.send(create_goal(plaintext(" "))).await.unwrap_err();
+        assert_eq!(err.error_code, ErrorCode::InvalidData);
+
+        let err = player
+            .send(CompleteGoal {
+                goal_id: Ulid::new().to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.error_code, ErrorCode::InvalidData);
+
+        let goals = player
+            .send(create_goal(plaintext("Learn Rust")))
+            .await
+            .unwrap();
+        let goal_id = goals[0].goal_id.clone();
+        player
+            .send(ArchiveGoal {
+                goal_id: goal_id.clone(),
+            })
+            .await
+            .unwrap();
+        let err = player.send(CompleteGoal { goal_id }).await.unwrap_err();
+        assert_eq!(err.error_code, ErrorCode::InvalidGoalState);
+    }
+}
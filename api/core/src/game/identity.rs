@@ -0,0 +1,205 @@
+//! Identity handlers which store the player's `Identity` and reply to `IdentityQuery` messages with it
+
+use std::sync::Arc;
+
+use logic::{
+    encryption::PublicKey,
+    messages::{
+        game::identity::{Identity, IdentityQuery, IdentityState},
+        ClientPlayerMessage,
+    },
+    server_error::{ErrorCode, ServerError},
+};
+
+use crate::{
+    entities::{PlayerIdentity, UserId},
+    lambda::{PlayerEventHandler, RequestContext},
+    storage::{Storage, StorageErr},
+};
+
+/// Returns the identity of the player if it was set
+pub async fn player_identity(
+    storage: &impl Storage,
+    user_id: &UserId,
+) -> Result<Option<PlayerIdentity>, StorageErr> {
+    match storage.read(PlayerIdentity::key_for(user_id)).await {
+        Ok(identity) => Ok(Some(identity)),
+        Err(StorageErr::NotFound) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Handler for `Identity` messages, validates and stores the identity and replies with it
+pub struct IdentityHandler<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> IdentityHandler<S> {
+    /// Creates a new handler which uses given storage
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S: Storage> PlayerEventHandler<Identity> for IdentityHandler<S> {
+    async fn process_message(
+        &self,
+        message: Identity,
+        _: &RequestContext,
+        _: Arc<PublicKey>,
+        user_id: UserId,
+        request_id: u32,
+    ) -> Result<IdentityState, ServerError> {
+        let tag = Identity::tag();
+        message.validate().map_err(|err| ServerError {
+            error_code: ErrorCode::InvalidData,
+            error_description: "Identity is not valid".to_string(),
+            error_context: Some(err.to_string()),
+            request_id,
+            message_tag: tag,
+            recoverable: false,
+        })?;
+        let identity = PlayerIdentity {
+            key: PlayerIdentity::key_for(&user_id),
+            name: message.name,
+        };
+        self.storage
+            .write(&identity)
+            .await
+            .map_err(|err| err.into_server_error(tag, request_id))?;
+        Ok(IdentityState {
+            name: Some(identity.name),
+        })
+    }
+}
+
+/// Handler for `IdentityQuery` messages
+pub struct IdentityQueryHandler<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> IdentityQueryHandler<S> {
+    /// Creates a new handler which uses given storage
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S: Storage> PlayerEventHandler<IdentityQuery> for IdentityQueryHandler<S> {
+    async fn process_message(
+        &self,
+        _: IdentityQuery,
+        _: &RequestContext,
+        _: Arc<PublicKey>,
+        user_id: UserId,
+        request_id: u32,
+    ) -> Result<IdentityState, ServerError> {
+        let identity = player_identity(self.storage.as_ref(), &user_id)
+            .await
+            .map_err(|err| err.into_server_error(IdentityQuery::tag(), request_id))?;
+        Ok(IdentityState {
+            name: identity.map(|identity| identity.name),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::encryption::{generate_new_keys, EncryptedString, Keys, SafeString};
+
+    use crate::{fixtures::request_context, storage::storage_memory::MemoryStorage};
+
+    use super::*;
+
+    async fn set_identity(
+        storage: &Arc<MemoryStorage>,
+        keys: &Keys,
+        user_id: &UserId,
+        name: SafeString,
+    ) -> Result<IdentityState, ServerError> {
+        IdentityHandler::new(storage.clone())
+            .process_message(
+                Identity { name },
+                &request_context(),
+                keys.public_key.clone(),
+                user_id.clone(),
+                1,
+            )
+            .await
+    }
+
+    async fn query_identity(
+        storage: &Arc<MemoryStorage>,
+        keys: &Keys,
+        user_id: &UserId,
+    ) -> IdentityState {
+        IdentityQueryHandler::new(storage.clone())
+            .process_message(
+                IdentityQuery {},
+                &request_context(),
+                keys.public_key.clone(),
+                user_id.clone(),
+                2,
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn plaintext_identity() {
+        let storage = Arc::new(MemoryStorage::new("test").await);
+        let keys = generate_new_keys();
+        let user_id = UserId::generate();
+        assert_eq!(query_identity(&storage, &keys, &user_id).await.name, None);
+
+        let name = SafeString::Plaintext {
+            value: "Player".to_string(),
+        };
+        let state = set_identity(&storage, &keys, &user_id, name.clone())
+            .await
+            .unwrap();
+        assert_eq!(state.name, Some(name.clone()));
+        assert_eq!(
+            query_identity(&storage, &keys, &user_id).await.name,
+            Some(name)
+        );
+
+        // Invalid identity doesn't replace the stored one
+        let err = set_identity(
+            &storage,
+            &keys,
+            &user_id,
+            SafeString::Plaintext {
+                value: String::new(),
+            },
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.error_code, ErrorCode::InvalidData);
+        assert!(query_identity(&storage, &keys, &user_id)
+            .await
+            .name
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn encrypted_identity() {
+        let storage = Arc::new(MemoryStorage::new("test").await);
+        let keys = generate_new_keys();
+        let user_id = UserId::generate();
+        let name = SafeString::Encrypted {
+            data: EncryptedString::new("Secret".to_string(), &keys.private_key),
+        };
+        set_identity(&storage, &keys, &user_id, name.clone())
+            .await
+            .unwrap();
+
+        // Only the player can read the stored name back
+        let state = query_identity(&storage, &keys, &user_id).await;
+        assert_eq!(state.name, Some(name));
+        let Some(SafeString::Encrypted { data }) = state.name else {
+            panic!("Identity name should stay encrypted");
+        };
+        assert_eq!(data.decrypt(&keys.private_key).unwrap(), "Secret");
+    }
+}
@@ -0,0 +1,5 @@
+//! API logic for game handlers
+
+pub mod decay;
+pub mod goals;
+pub mod identity;
@@ -1,23 +1,88 @@
 //! Helpers for AWS lambda
 
-use std::sync::Arc;
+use std::{
+    any::Any, collections::HashMap, future::Future, panic::AssertUnwindSafe, pin::Pin, rc::Rc,
+    sync::Arc,
+};
 
 use aws_lambda_events::apigw::ApiGatewayWebsocketProxyRequest;
+use futures::FutureExt;
 use lambda_runtime::{run, service_fn, tracing, Error, LambdaEvent};
 use logic::{
+    datetime::{Duration, ServerTimestamp, Timestamp},
     encryption::PublicKey,
-    messages::{ClientPlayerMessage, ClientPublicMessage},
-    server_error::ServerError,
+    messages::{
+        serializers::{self, DEFAULT_REPLAY_WINDOW_MS},
+        ClientPlayerMessage, ClientPublicMessage, ServerMessage,
+    },
+    server_error::{ErrorCode, ServerError},
 };
 use serde_json::{json, Value};
 
+use crate::{
+    common::{
+        account::resolve_account,
+        auth::connection_player,
+        idempotency::{release_key, reserve_key, store_response, Reservation},
+        rate_limit::{RateLimitMiddleware, RateLimits},
+        replay::check_nonce,
+    },
+    entities::UserId,
+    middleware::{DefaultMiddleware, LoggingMiddleware, MessageInfo, Middleware},
+    storage::{Storage, StorageErr},
+};
+
+/// Environment variable with maximum allowed age of signed player messages in milliseconds
+pub const REPLAY_WINDOW_ENV: &str = "REPLAY_WINDOW_MS";
+
+/// Environment variable with maximum time in milliseconds a handler may process a message
+pub const HANDLER_TIMEOUT_ENV: &str = "HANDLER_TIMEOUT_MS";
+
+/// Default handler timeout, it's lower than the default 3 seconds lambda timeout so the error could still be sent
+pub const DEFAULT_HANDLER_TIMEOUT_MS: u64 = 2_500;
+
+/// Details of the API Gateway request the message came with
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestContext {
+    /// WebSocket connection the message was sent over
+    pub connection_id: String,
+    /// IP address of the client
+    pub source_ip: Option<String>,
+    /// Time when API Gateway received the message
+    pub request_time: ServerTimestamp,
+    /// API Gateway stage which received the message
+    pub stage: Option<String>,
+}
+
+impl RequestContext {
+    /// Creates context from the API Gateway event, request time falls back to the current time if it's missing
+    pub fn new(event: &ApiGatewayWebsocketProxyRequest) -> Self {
+        let request_context = &event.request_context;
+        let request_time = match u64::try_from(request_context.request_time_epoch) {
+            Ok(epoch) if epoch > 0 => ServerTimestamp::from_milliseconds_pure(epoch),
+            _ => ServerTimestamp::now(),
+        };
+        Self {
+            connection_id: request_context.connection_id.clone().unwrap_or_default(),
+            source_ip: request_context.identity.source_ip.clone(),
+            request_time,
+            stage: request_context.stage.clone(),
+        }
+    }
+}
+
 /// Event handler for events that are public and not require authentication
 pub trait PublicEventHandler<T>
 where
     T: ClientPublicMessage,
 {
-    /// Process message
-    async fn process_message(&self, message: T, request_id: u8) -> Result<String, ServerError>;
+    /// Process message and return the response paired with the message, it's serialized by the caller
+    async fn process_message(
+        &self,
+        message: T,
+        context: &RequestContext,
+        request_id: u32,
+    ) -> Result<T::Response, ServerError>;
 }
 
 /// Event handler for events that requires authentication
@@ -25,90 +90,633 @@ pub trait PlayerEventHandler<T>
 where
     T: ClientPlayerMessage,
 {
-    /// Process message
+    /// Process message and return the response paired with the message, it's serialized by the caller
     async fn process_message(
         &self,
         message: T,
+        context: &RequestContext,
         public_key: Arc<PublicKey>,
-        request_id: u8,
-    ) -> Result<String, ServerError>;
+        user_id: UserId,
+        request_id: u32,
+    ) -> Result<T::Response, ServerError>;
 }
 
-/// Run public event handler using AWS Lambda which does not require authentication. Handler may be reused many
-/// times in case of warm start. In case of error the returned ServerError will be serialized as a server message
-/// so it can be processed by WebSocket clients
-pub async fn run_public_handler<T>(handler: &impl PublicEventHandler<T>) -> Result<(), Error>
+/// Event handler for public events which are processed in the context of the WebSocket connection they came from,
+/// e.g. connection authentication
+pub trait ConnectionMessageHandler<T>
 where
     T: ClientPublicMessage,
 {
-    tracing::init_default_subscriber();
-    run(service_fn(
-        |event: LambdaEvent<ApiGatewayWebsocketProxyRequest>| async move {
-            Result::<Value, Error>::Ok(to_json_response(process_public_event(event, handler).await))
-        },
-    ))
-    .await
+    /// Process message and return the response paired with the message, it's serialized by the caller
+    async fn process_message(
+        &self,
+        message: T,
+        context: &RequestContext,
+        request_id: u32,
+    ) -> Result<T::Response, ServerError>;
 }
 
-/// Run player event handler using AWS Lambda which requires player authentication. Handler may be reused many
-/// times in case of warm start. In case of error the returned ServerError will be serialized as a server message
-/// so it can be processed by WebSocket clients
-pub async fn run_player_handler<T>(handler: &impl PlayerEventHandler<T>) -> Result<(), Error>
+/// Event handler for WebSocket connection events like `$connect` and `$disconnect`
+pub trait ConnectionEventHandler {
+    /// Process connection event, for `$connect` event returning an error rejects the connection
+    async fn process_event(&self, event: ApiGatewayWebsocketProxyRequest)
+        -> Result<(), StorageErr>;
+}
+
+/// Middlewares which wrap handlers of every deployed lambda, both the per route ones and the router
+pub type LambdaMiddleware<S> = (LoggingMiddleware, RateLimitMiddleware<S>);
+
+/// Returns middlewares of deployed lambdas, rate limit buckets are kept in the given storage
+pub fn lambda_middleware<S: Storage>(storage: Arc<S>) -> LambdaMiddleware<S> {
+    (
+        LoggingMiddleware {},
+        RateLimitMiddleware::new(storage, RateLimits::default()),
+    )
+}
+
+/// Registry of a per route lambda with the single handler of public messages of type `T`
+pub fn public_lambda<T, S>(
+    handler: impl PublicEventHandler<T> + 'static,
+    storage: Arc<S>,
+) -> HandlerRegistry<S, LambdaMiddleware<S>>
 where
-    T: ClientPlayerMessage,
+    T: ClientPublicMessage + 'static,
+    S: Storage + 'static,
 {
-    tracing::init_default_subscriber();
-    run(service_fn(
-        |event: LambdaEvent<ApiGatewayWebsocketProxyRequest>| async move {
-            Result::<Value, Error>::Ok(to_json_response(process_player_event(event, handler).await))
-        },
-    ))
-    .await
+    HandlerRegistry::with_middleware(storage.clone(), lambda_middleware(storage)).public(handler)
+}
+
+/// Registry of a per route lambda with the single handler of player messages of type `T`
+pub fn player_lambda<T, S>(
+    handler: impl PlayerEventHandler<T> + 'static,
+    storage: Arc<S>,
+) -> HandlerRegistry<S, LambdaMiddleware<S>>
+where
+    T: ClientPlayerMessage + 'static,
+    S: Storage + 'static,
+{
+    HandlerRegistry::with_middleware(storage.clone(), lambda_middleware(storage)).player(handler)
+}
+
+/// Registry of a per route lambda with the single handler of connection messages of type `T`
+pub fn connection_message_lambda<T, S>(
+    handler: impl ConnectionMessageHandler<T> + 'static,
+    storage: Arc<S>,
+) -> HandlerRegistry<S, LambdaMiddleware<S>>
+where
+    T: ClientPublicMessage + 'static,
+    S: Storage + 'static,
+{
+    HandlerRegistry::with_middleware(storage.clone(), lambda_middleware(storage))
+        .connection(handler)
+}
+
+/// Run public event handler using AWS Lambda which does not require authentication. Storage keeps rate limits of
+/// the clients. Handler may be reused many times in case of warm start. In case of error the returned ServerError
+/// will be serialized as a server message so it can be processed by WebSocket clients
+pub async fn run_public_handler<T, S>(
+    handler: impl PublicEventHandler<T> + 'static,
+    storage: Arc<S>,
+) -> Result<(), Error>
+where
+    T: ClientPublicMessage + 'static,
+    S: Storage + 'static,
+{
+    run_router(&public_lambda(handler, storage)).await
+}
+
+/// Run player event handler using AWS Lambda which requires player authentication. Storage is used to authenticate
+/// unsigned messages by their connection and keeps rate limits of the players. Handler may be reused many times in
+/// case of warm start. In case of error the returned ServerError will be serialized as a server message so it can be
+/// processed by WebSocket clients
+pub async fn run_player_handler<T, S>(
+    handler: impl PlayerEventHandler<T> + 'static,
+    storage: Arc<S>,
+) -> Result<(), Error>
+where
+    T: ClientPlayerMessage + 'static,
+    S: Storage + 'static,
+{
+    run_router(&player_lambda(handler, storage)).await
 }
 
-async fn process_public_event<T>(
+/// Run connection message handler using AWS Lambda which does not require authentication. Storage keeps rate limits
+/// of the clients. Handler may be reused many times in case of warm start. In case of error the returned ServerError
+/// will be serialized as a server message so it can be processed by WebSocket clients
+pub async fn run_connection_message_handler<T, S>(
+    handler: impl ConnectionMessageHandler<T> + 'static,
+    storage: Arc<S>,
+) -> Result<(), Error>
+where
+    T: ClientPublicMessage + 'static,
+    S: Storage + 'static,
+{
+    run_router(&connection_message_lambda(handler, storage)).await
+}
+
+/// Deserialize public message from the API Gateway event, process it with a given handler wrapped by the middleware
+/// and return serialized response. Errors are returned as a serialized `ServerError`
+pub async fn process_public_event<T>(
     event: LambdaEvent<ApiGatewayWebsocketProxyRequest>,
     handler: &impl PublicEventHandler<T>,
+    middleware: &impl Middleware,
 ) -> String
 where
     T: ClientPublicMessage,
 {
-    let (msg, request_id) = match T::deserialize(event.payload.body.unwrap_or_default()) {
+    let mut info = MessageInfo {
+        event: &event.payload,
+        message_tag: T::tag(),
+        request_id: 0,
+        public_key: None,
+    };
+    let (msg, request_id) = match T::deserialize(event.payload.body.clone().unwrap_or_default()) {
         Ok(msg) => msg,
         Err(err) => {
-            return ServerError::from_serialization_error(err, T::tag(), 0)
-                .serialize(0)
-                .expect("Failed to serialize an error");
+            let err = ServerError::from_serialization_error(err, T::tag(), 0);
+            return respond(middleware, &info, Err::<T::Response, _>(err)).await;
         }
     };
-    match handler.process_message(msg, request_id).await {
-        Ok(output) => output,
-        Err(err) => err
-            .serialize(request_id)
-            .expect("Failed to serialize an error"),
-    }
+    info.request_id = request_id;
+    let response = match middleware.before(&info).await {
+        Ok(()) => {
+            let context = RequestContext::new(&event.payload);
+            // Guarded handler future is too deep to be nested unboxed, the same as for player messages
+            Box::pin(guard(
+                handler.process_message(msg, &context, request_id),
+                &info,
+            ))
+            .await
+        }
+        Err(err) => Err(err),
+    };
+    respond(middleware, &info, response).await
 }
 
-async fn process_player_event<T>(
+/// Deserialize and verify player message from the API Gateway event, process it with a given handler wrapped by the
+/// middleware and return serialized response. Signed messages are verified by their signature, while unsigned ones
+/// by the authenticated connection they came from. Errors are returned as a serialized `ServerError`
+pub async fn process_player_event<T>(
     event: LambdaEvent<ApiGatewayWebsocketProxyRequest>,
     handler: &impl PlayerEventHandler<T>,
+    storage: &impl Storage,
+    middleware: &impl Middleware,
 ) -> String
 where
     T: ClientPlayerMessage,
 {
-    let (msg, public_key, request_id) =
-        match T::deserialize(event.payload.body.clone().unwrap_or_default()) {
+    let mut info = MessageInfo {
+        event: &event.payload,
+        message_tag: T::tag(),
+        request_id: 0,
+        public_key: None,
+    };
+    let body = event.payload.body.as_deref().unwrap_or_default();
+    let (body, idempotency_key) =
+        match serializers::ClientPlayerMessage::split_idempotency_key(body) {
+            Ok(split) => split,
+            Err(err) => {
+                let err = ServerError::from_serialization_error(err, T::tag(), 0);
+                return respond(middleware, &info, Err::<T::Response, _>(err)).await;
+            }
+        };
+    let (msg, public_key, user_id, request_id) =
+        match deserialize_player_event(&event.payload, body, storage).await {
             Ok(msg) => msg,
             Err(err) => {
-                return ServerError::from_serialization_error(err, T::tag(), 0)
-                    .serialize(0)
-                    .expect("Failed to serialize an error");
+                info.request_id = err.request_id;
+                return respond(middleware, &info, Err::<T::Response, _>(err)).await;
             }
         };
-    match handler.process_message(msg, public_key, request_id).await {
+    info.request_id = request_id;
+    info.public_key = Some(public_key.clone());
+    let response = match middleware.before(&info).await {
+        // Handlers usually talk to the storage, boxing keeps the future type shallow as in `deserialize_player_event`
+        Ok(()) => {
+            let context = RequestContext::new(&event.payload);
+            let process =
+                handler.process_message(msg, &context, public_key.clone(), user_id, request_id);
+            let process = guard(process, &info);
+            match idempotency_key {
+                Some(key) => Box::pin(idempotent(storage, &public_key, &key, &info, process)).await,
+                None => Box::pin(process)
+                    .await
+                    .and_then(|response| serialize_response(&info, response)),
+            }
+        }
+        Err(err) => Err(err),
+    };
+    send(middleware, &info, response).await
+}
+
+/// Deserialize public message from the API Gateway event, process it with a given handler wrapped by the middleware
+/// in the context of the connection and return serialized response. Errors are returned as a serialized `ServerError`
+pub async fn process_connection_message_event<T>(
+    event: LambdaEvent<ApiGatewayWebsocketProxyRequest>,
+    handler: &impl ConnectionMessageHandler<T>,
+    middleware: &impl Middleware,
+) -> String
+where
+    T: ClientPublicMessage,
+{
+    let mut info = MessageInfo {
+        event: &event.payload,
+        message_tag: T::tag(),
+        request_id: 0,
+        public_key: None,
+    };
+    let (msg, request_id) = match T::deserialize(event.payload.body.clone().unwrap_or_default()) {
+        Ok(msg) => msg,
+        Err(err) => {
+            let err = ServerError::from_serialization_error(err, T::tag(), 0);
+            return respond(middleware, &info, Err::<T::Response, _>(err)).await;
+        }
+    };
+    info.request_id = request_id;
+    let response = match middleware.before(&info).await {
+        Ok(()) => {
+            let context = RequestContext::new(&event.payload);
+            // Guarded handler future is too deep to be nested unboxed, the same as for player messages
+            Box::pin(guard(
+                handler.process_message(msg, &context, request_id),
+                &info,
+            ))
+            .await
+        }
+        Err(err) => Err(err),
+    };
+    respond(middleware, &info, response).await
+}
+
+async fn deserialize_player_event<T>(
+    event: &ApiGatewayWebsocketProxyRequest,
+    body: String,
+    storage: &impl Storage,
+) -> Result<(T, Arc<PublicKey>, UserId, u32), ServerError>
+where
+    T: ClientPlayerMessage,
+{
+    if !T::is_unsigned(&body) {
+        let window = replay_window();
+        let (msg, signature, request_id) = T::deserialize(body, &Timestamp::now(), &window)
+            .map_err(|err| ServerError::from_serialization_error(err, T::tag(), 0))?;
+        Box::pin(check_nonce(
+            storage,
+            &signature,
+            &window,
+            T::tag(),
+            request_id,
+        ))
+        .await?;
+        let user_id = Box::pin(resolve_account(storage, &signature.public_key))
+            .await
+            .map_err(|err| err.into_server_error(T::tag(), request_id))?;
+        return Ok((msg, signature.public_key, user_id, request_id));
+    }
+    let (msg, request_id) = T::deserialize_unsigned(body)
+        .map_err(|err| ServerError::from_serialization_error(err, T::tag(), 0))?;
+    let connection_id = event
+        .request_context
+        .connection_id
+        .as_deref()
+        .unwrap_or_default();
+    // Boxing keeps the future type shallow, otherwise DynamoDB client futures overflow the compiler query depth
+    let (public_key, user_id) = Box::pin(connection_player(
+        storage,
+        connection_id,
+        T::tag(),
+        request_id,
+    ))
+    .await?;
+    Ok((msg, public_key, user_id, request_id))
+}
+
+type Event = LambdaEvent<ApiGatewayWebsocketProxyRequest>;
+type HandlerFn = Box<dyn Fn(Event) -> Pin<Box<dyn Future<Output = String>>>>;
+
+/// Registry of message handlers which routes messages by their tag, so a single lambda can process all of them.
+/// Every handler is processed the same way as when it's deployed as a separate lambda
+pub struct HandlerRegistry<S: Storage, M: Middleware = DefaultMiddleware> {
+    handlers: HashMap<u16, HandlerFn>,
+    storage: Arc<S>,
+    middleware: Rc<M>,
+}
+
+impl<S: Storage + 'static> HandlerRegistry<S> {
+    /// Creates an empty registry with default middlewares, storage is used to authenticate player messages
+    pub fn new(storage: Arc<S>) -> Self {
+        Self::with_middleware(storage, DefaultMiddleware {})
+    }
+}
+
+impl<S: Storage + 'static, M: Middleware + 'static> HandlerRegistry<S, M> {
+    /// Creates an empty registry which wraps all the handlers with given middleware
+    pub fn with_middleware(storage: Arc<S>, middleware: M) -> Self {
+        Self {
+            handlers: HashMap::new(),
+            storage,
+            middleware: Rc::new(middleware),
+        }
+    }
+
+    /// Registers handler for public messages of type `T`
+    pub fn public<T, H>(self, handler: H) -> Self
+    where
+        T: ClientPublicMessage + 'static,
+        H: PublicEventHandler<T> + 'static,
+    {
+        let handler = Rc::new(handler);
+        let middleware = self.middleware.clone();
+        self.register(
+            T::tag(),
+            Box::new(move |event| {
+                let handler = handler.clone();
+                let middleware = middleware.clone();
+                Box::pin(async move {
+                    process_public_event(event, handler.as_ref(), middleware.as_ref()).await
+                })
+            }),
+        )
+    }
+
+    /// Registers handler for player messages of type `T`
+    pub fn player<T, H>(self, handler: H) -> Self
+    where
+        T: ClientPlayerMessage + 'static,
+        H: PlayerEventHandler<T> + 'static,
+    {
+        let handler = Rc::new(handler);
+        let storage = self.storage.clone();
+        let middleware = self.middleware.clone();
+        self.register(
+            T::tag(),
+            Box::new(move |event| {
+                let handler = handler.clone();
+                let storage = storage.clone();
+                let middleware = middleware.clone();
+                Box::pin(async move {
+                    process_player_event(
+                        event,
+                        handler.as_ref(),
+                        storage.as_ref(),
+                        middleware.as_ref(),
+                    )
+                    .await
+                })
+            }),
+        )
+    }
+
+    /// Registers handler for public messages of type `T` which are processed in the context of the connection
+    pub fn connection<T, H>(self, handler: H) -> Self
+    where
+        T: ClientPublicMessage + 'static,
+        H: ConnectionMessageHandler<T> + 'static,
+    {
+        let handler = Rc::new(handler);
+        let middleware = self.middleware.clone();
+        self.register(
+            T::tag(),
+            Box::new(move |event| {
+                let handler = handler.clone();
+                let middleware = middleware.clone();
+                Box::pin(async move {
+                    process_connection_message_event(event, handler.as_ref(), middleware.as_ref())
+                        .await
+                })
+            }),
+        )
+    }
+
+    fn register(mut self, tag: u16, handler: HandlerFn) -> Self {
+        if self.handlers.insert(tag, handler).is_some() {
+            panic!("Handler for message tag={} is already registered", tag);
+        }
+        self
+    }
+
+    /// Returns handler response or None if there is no handler for the message tag in the body
+    pub async fn dispatch(&self, event: Event) -> Option<String> {
+        let tag = route_tag(event.payload.body.as_deref().unwrap_or_default())?;
+        let handler = self.handlers.get(&tag)?;
+        Some(handler(event).await)
+    }
+
+    /// Returns handler response, messages without a handler are replied with a serialized `ServerError`
+    pub async fn process_event(&self, event: Event) -> String {
+        let tag = route_tag(event.payload.body.as_deref().unwrap_or_default());
+        match tag.and_then(|tag| self.handlers.get(&tag)) {
+            Some(handler) => handler(event).await,
+            None => ServerError {
+                error_code: ErrorCode::UnknownMessage,
+                error_description: "Message is not supported, try to update the app".to_string(),
+                error_context: Some(format!("No handler for message tag={:?}", tag)),
+                request_id: 0,
+                message_tag: tag.unwrap_or_default(),
+                recoverable: false,
+            }
+            .serialize(0)
+            .expect("Failed to serialize an error"),
+        }
+    }
+}
+
+/// Run all the registered handlers using a single AWS Lambda. Registry may be reused many times in case of
+/// warm start
+pub async fn run_router<S: Storage + 'static, M: Middleware + 'static>(
+    registry: &HandlerRegistry<S, M>,
+) -> Result<(), Error> {
+    tracing::init_default_subscriber();
+    run(service_fn(|event: Event| async move {
+        Result::<Value, Error>::Ok(to_json_response(registry.process_event(event).await))
+    }))
+    .await
+}
+
+/// Extracts message tag from the `k` field of a JSON body, the same way API Gateway selects the route
+pub fn route_tag(body: &str) -> Option<u16> {
+    let json: Value = serde_json::from_str(body).ok()?;
+    let key = json.get("k")?.as_str()?;
+    binary_encoding::decode_message_tag(key.as_bytes()).ok()
+}
+
+/// Run connection event handler using AWS Lambda. Handler may be reused many times in case of warm start
+pub async fn run_connection_handler(handler: &impl ConnectionEventHandler) -> Result<(), Error> {
+    tracing::init_default_subscriber();
+    run(service_fn(
+        |event: LambdaEvent<ApiGatewayWebsocketProxyRequest>| async move {
+            Result::<Value, Error>::Ok(process_connection_event(event, handler).await)
+        },
+    ))
+    .await
+}
+
+/// Process connection event with a given handler and return response for the API Gateway. Any status code
+/// other than 200 for `$connect` event rejects the connection
+pub async fn process_connection_event(
+    event: LambdaEvent<ApiGatewayWebsocketProxyRequest>,
+    handler: &impl ConnectionEventHandler,
+) -> Value {
+    match handler.process_event(event.payload).await {
+        Ok(_) => json!({ "statusCode": 200 }),
+        Err(err) => {
+            tracing::error!("Failed to process connection event: {:?}", err);
+            json!({ "statusCode": 500 })
+        }
+    }
+}
+
+/// Maximum allowed difference between signed player message timestamp and the server time, can be overridden
+/// with `REPLAY_WINDOW_ENV` environment variable
+fn replay_window() -> Arc<Duration> {
+    let window = std::env::var(REPLAY_WINDOW_ENV)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_REPLAY_WINDOW_MS);
+    Duration::from_milliseconds(window)
+}
+
+/// Maximum time a handler may process a message, can be overridden with `HANDLER_TIMEOUT_ENV` environment variable
+fn handler_timeout() -> std::time::Duration {
+    let timeout = std::env::var(HANDLER_TIMEOUT_ENV)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_HANDLER_TIMEOUT_MS);
+    std::time::Duration::from_millis(timeout)
+}
+
+/// Processes message with the handler, so the client gets a `ServerError` back even if handler panics or hangs
+async fn guard<R>(
+    process: impl Future<Output = Result<R, ServerError>>,
+    info: &MessageInfo<'_>,
+) -> Result<R, ServerError> {
+    guard_with_timeout(process, info, handler_timeout()).await
+}
+
+async fn guard_with_timeout<R>(
+    process: impl Future<Output = Result<R, ServerError>>,
+    info: &MessageInfo<'_>,
+    timeout: std::time::Duration,
+) -> Result<R, ServerError> {
+    let (error_description, error_context, recoverable) =
+        match tokio::time::timeout(timeout, AssertUnwindSafe(process).catch_unwind()).await {
+            Ok(Ok(response)) => return response,
+            // Same message would most likely fail again, so it's not recoverable
+            Ok(Err(panic)) => (
+                "Server failed to process the message",
+                format!("Handler panicked: {}", panic_message(panic.as_ref())),
+                false,
+            ),
+            Err(_) => (
+                "Server is busy and could not process the message in time, try again later",
+                format!("Handler timed out after {}ms", timeout.as_millis()),
+                true,
+            ),
+        };
+    Err(ServerError {
+        error_code: ErrorCode::ServerError,
+        error_description: error_description.to_string(),
+        error_context: Some(error_context),
+        request_id: info.request_id,
+        message_tag: info.message_tag,
+        recoverable,
+    })
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        return message;
+    }
+    panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .unwrap_or("unknown panic")
+}
+
+/// Replays the stored response if the message with the same idempotency key was already processed, otherwise
+/// reserves the key, processes the message and stores its response. Errors are not stored, so failed messages can
+/// be retried
+async fn idempotent(
+    storage: &impl Storage,
+    public_key: &PublicKey,
+    idempotency_key: &str,
+    info: &MessageInfo<'_>,
+    process: impl Future<Output = Result<impl ServerMessage, ServerError>>,
+) -> Result<String, ServerError> {
+    let (message_tag, request_id) = (info.message_tag, info.request_id);
+    let version = match reserve_key(
+        storage,
+        public_key,
+        idempotency_key,
+        message_tag,
+        request_id,
+        ServerTimestamp::now(),
+    )
+    .await?
+    {
+        Reservation::Reserved(version) => version,
+        Reservation::Processed(response) => return Ok(response),
+    };
+    let response = process
+        .await
+        .and_then(|response| serialize_response(info, response));
+    match &response {
+        Ok(response) => {
+            let stored = store_response(
+                storage,
+                public_key,
+                idempotency_key,
+                message_tag,
+                response,
+                version,
+                ServerTimestamp::now(),
+            )
+            .await;
+            // Message is already applied, failing it would make the client retry and apply it again
+            if let Err(err) = stored {
+                tracing::error!("Failed to store idempotent response: {:?}", err);
+            }
+        }
+        Err(_) => {
+            if let Err(err) = release_key(storage, public_key, idempotency_key, message_tag).await {
+                tracing::error!("Failed to release idempotency key: {:?}", err);
+            }
+        }
+    }
+    response
+}
+
+fn serialize_response(
+    info: &MessageInfo<'_>,
+    response: impl ServerMessage,
+) -> Result<String, ServerError> {
+    response.serialize(info.request_id).map_err(|err| {
+        ServerError::from_serialization_error(err, info.message_tag, info.request_id)
+    })
+}
+
+/// Serializes handler response to the processed message, errors are serialized as `ServerError`. Middleware is
+/// notified about the result before it's sent back
+async fn respond(
+    middleware: &impl Middleware,
+    info: &MessageInfo<'_>,
+    response: Result<impl ServerMessage, ServerError>,
+) -> String {
+    let response = response.and_then(|response| serialize_response(info, response));
+    send(middleware, info, response).await
+}
+
+/// Notifies middleware about the already serialized response and serializes errors as `ServerError`
+async fn send(
+    middleware: &impl Middleware,
+    info: &MessageInfo<'_>,
+    response: Result<String, ServerError>,
+) -> String {
+    middleware.after(info, response.as_ref().err()).await;
+    match response {
         Ok(output) => output,
         Err(err) => err
-            .serialize(request_id)
+            .serialize(info.request_id)
             .expect("Failed to serialize an error"),
     }
 }
@@ -123,39 +731,62 @@ fn to_json_response(response: String) -> Value {
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
+
     use logic::{
+        datetime::ServerTimestamp,
         encryption,
-        messages::{common::ping::Ping, game::decay::DecayQuery},
+        messages::{
+            common::ping::{Ping, ServerStatus},
+            game::decay::{Decay, DecayQuery},
+        },
     };
 
-    use crate::fixtures::event_with_body;
+    use crate::{
+        common::health::healthy_status, fixtures::event_with_body,
+        storage::storage_memory::MemoryStorage,
+    };
 
     use super::*;
 
     struct HandlerSuccess {}
     impl PublicEventHandler<Ping> for HandlerSuccess {
-        async fn process_message(&self, _: Ping, request_id: u8) -> Result<String, ServerError> {
-            Ok(format!("Ping={}", request_id))
+        async fn process_message(
+            &self,
+            _: Ping,
+            _: &RequestContext,
+            _: u32,
+        ) -> Result<ServerStatus, ServerError> {
+            Ok(healthy_status(ServerTimestamp::from_milliseconds_pure(1)))
         }
     }
     impl PlayerEventHandler<DecayQuery> for HandlerSuccess {
         async fn process_message(
             &self,
             _: DecayQuery,
+            _: &RequestContext,
             public_key: Arc<PublicKey>,
-            request_id: u8,
-        ) -> Result<String, ServerError> {
-            Ok(format!(
-                "Query={}, pub_key_len={}",
-                request_id,
-                public_key.as_string().len()
-            ))
+            _: UserId,
+            _: u32,
+        ) -> Result<Decay, ServerError> {
+            // Length of the public key is passed back so tests can check it
+            Ok(Decay {
+                started_at: ServerTimestamp::from_milliseconds(0),
+                length: Duration::from_milliseconds(public_key.as_string().len() as u64),
+                ends_at: None,
+                modifiers: vec![],
+            })
         }
     }
 
     struct HandlerError {}
     impl PublicEventHandler<Ping> for HandlerError {
-        async fn process_message(&self, _: Ping, request_id: u8) -> Result<String, ServerError> {
+        async fn process_message(
+            &self,
+            _: Ping,
+            _: &RequestContext,
+            request_id: u32,
+        ) -> Result<ServerStatus, ServerError> {
             Err(ServerError {
                 error_code: logic::server_error::ErrorCode::SerializationError,
                 error_description: "error_description".to_string(),
@@ -171,9 +802,11 @@ mod tests {
         async fn process_message(
             &self,
             _: DecayQuery,
+            _: &RequestContext,
             _: Arc<PublicKey>,
-            request_id: u8,
-        ) -> Result<String, ServerError> {
+            _: UserId,
+            request_id: u32,
+        ) -> Result<Decay, ServerError> {
             Err(ServerError {
                 error_code: logic::server_error::ErrorCode::SerializationError,
                 error_description: "error_description".to_string(),
@@ -189,18 +822,20 @@ mod tests {
     async fn public_handler_success() {
         let request_id = 1;
         let event = event_with_body(Ping {}.serialize(request_id).unwrap());
-        let response = process_public_event(event, &HandlerSuccess {}).await;
-        assert_eq!(response, "Ping=1");
+        let response = process_public_event(event, &HandlerSuccess {}, &()).await;
+        let (status, response_request_id) = ServerStatus::deserialize(&response).unwrap();
+        assert_eq!(status.timestamp.as_milliseconds(), 1);
+        assert_eq!(response_request_id, request_id);
     }
 
     #[tokio::test]
     async fn public_handler_error() {
         let request_id = 1;
         let event = event_with_body(Ping {}.serialize(request_id).unwrap());
-        let response = process_public_event(event, &HandlerError {}).await;
+        let response = process_public_event(event, &HandlerError {}, &()).await;
         assert_eq!(
             response,
-            "-0-.m~#Y\u{7f}4e=3Nn^AU!PCr&Ux2p9&,y^ mVk9u%I5E!qqx0p"
+            "-0-.§2 A?&tD4##gvG0s^1+(~ZxBGQ>zJtjE`(DCgzV]F94ww~Hs5*3LZJiO:  Y=<lj"
         );
         let error = ServerError::deserialize(&response).unwrap();
         assert_eq!(error.1, request_id);
@@ -220,8 +855,8 @@ mod tests {
     #[tokio::test]
     async fn public_handler_bad_data() {
         let event = event_with_body("bad_data".to_string());
-        let response = process_public_event(event, &HandlerError {}).await;
-        assert_eq!(response, "-0--,ZCq?4CjI'UrF!;$9vZ!>x?c=)>$#TOrW+p<m<GBPerVu\u{7f}[#Tm?hirY}P H1*`u:Wf pK)1>L`u)~h~%%a?/jqeZ-c}Joqn,9CUFDL4XEw 3o_ejI9");
+        let response = process_public_event(event, &HandlerError {}, &()).await;
+        assert_eq!(response, "-0--§2 A?&tQ,|:%YB9r%Q;4prCFF-/u-RQ= D*BC`?:+=X9|MGQ'Vxi(2Oa#!)sc0PL2D&WCgzV]F147rP,*WYLTYKQLmD( ^#Il&k\u{7f}IHS:^yY%H0M_J.=Ek_Q=b2$  6e_z3vRC");
         let error = ServerError::deserialize(&response).unwrap();
         assert_eq!(error.1, 0);
         assert_eq!(
@@ -237,6 +872,135 @@ mod tests {
         )
     }
 
+    struct HandlerPanic {}
+    impl PublicEventHandler<Ping> for HandlerPanic {
+        async fn process_message(
+            &self,
+            _: Ping,
+            _: &RequestContext,
+            _: u32,
+        ) -> Result<ServerStatus, ServerError> {
+            panic!("Public handler failed")
+        }
+    }
+
+    impl PlayerEventHandler<DecayQuery> for HandlerPanic {
+        async fn process_message(
+            &self,
+            _: DecayQuery,
+            _: &RequestContext,
+            _: Arc<PublicKey>,
+            _: UserId,
+            _: u32,
+        ) -> Result<Decay, ServerError> {
+            panic!("Player handler failed with code {}", 42)
+        }
+    }
+
+    #[tokio::test]
+    async fn public_handler_panic() {
+        let event = event_with_body(Ping {}.serialize(7).unwrap());
+        let response = process_public_event(event, &HandlerPanic {}, &()).await;
+        let (error, request_id) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(request_id, 7);
+        assert_eq!(error.error_code, ErrorCode::ServerError);
+        assert_eq!(error.message_tag, Ping::tag());
+        assert_eq!(
+            error.error_context.as_deref(),
+            Some("Handler panicked: Public handler failed")
+        );
+        assert!(!error.recoverable);
+    }
+
+    #[tokio::test]
+    async fn player_handler_panic() {
+        let keys = encryption::generate_new_keys();
+        let body = DecayQuery {}
+            .serialize(
+                8,
+                &Timestamp::now(),
+                keys.public_key.as_ref().clone(),
+                keys.private_key.as_ref().clone(),
+            )
+            .unwrap();
+        let storage = MemoryStorage::new("test").await;
+        let response =
+            process_player_event(event_with_body(body), &HandlerPanic {}, &storage, &()).await;
+        let (error, request_id) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(request_id, 8);
+        assert_eq!(error.error_code, ErrorCode::ServerError);
+        assert_eq!(error.message_tag, DecayQuery::tag());
+        assert_eq!(
+            error.error_context.as_deref(),
+            Some("Handler panicked: Player handler failed with code 42")
+        );
+        assert!(!error.recoverable);
+    }
+
+    #[tokio::test]
+    async fn handler_timeout() {
+        let event = event_with_body(String::new());
+        let info = MessageInfo {
+            event: &event.payload,
+            message_tag: Ping::tag(),
+            request_id: 9,
+            public_key: None,
+        };
+        let timeout = std::time::Duration::from_millis(10);
+        let slow = async {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            Ok(healthy_status(ServerTimestamp::now()))
+        };
+        let error = guard_with_timeout(slow, &info, timeout).await.unwrap_err();
+        assert_eq!(error.error_code, ErrorCode::ServerError);
+        assert_eq!(error.request_id, 9);
+        assert_eq!(error.message_tag, Ping::tag());
+        assert!(error.recoverable);
+
+        let fast = async { Ok(healthy_status(ServerTimestamp::now())) };
+        assert!(guard_with_timeout(fast, &info, timeout).await.is_ok());
+    }
+
+    #[test]
+    fn request_context_from_event() {
+        let mut event = event_with_body(String::new());
+        let context = RequestContext::new(&event.payload);
+        assert_eq!(context.connection_id, "etAB-deZIAMCK2g=");
+        assert_eq!(context.source_ip.as_deref(), Some("87.95.116.76"));
+        assert_eq!(context.request_time.as_milliseconds(), 1727337382493);
+        assert_eq!(context.stage.as_deref(), Some("v1"));
+
+        event.payload.request_context.request_time_epoch = 0;
+        let context = RequestContext::new(&event.payload);
+        assert!(context.request_time.as_milliseconds() > 1727337382493);
+    }
+
+    #[tokio::test]
+    async fn registry_routes_messages() {
+        let storage = Arc::new(MemoryStorage::new("test").await);
+        let registry = HandlerRegistry::new(storage).public(HandlerSuccess {});
+        let event = event_with_body(Ping {}.serialize(1).unwrap());
+        let response = registry.process_event(event).await;
+        assert_eq!(ServerStatus::deserialize(&response).unwrap().1, 1);
+
+        // Messages without a handler are replied with an error instead of being dropped
+        let body = DecayQuery {}.serialize_unsigned(2).unwrap();
+        assert!(registry
+            .dispatch(event_with_body(body.clone()))
+            .await
+            .is_none());
+        let response = registry.process_event(event_with_body(body)).await;
+        let (error, _) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(error.error_code, ErrorCode::UnknownMessage);
+        assert_eq!(error.message_tag, DecayQuery::tag());
+
+        let response = registry
+            .process_event(event_with_body("bad_data".to_string()))
+            .await;
+        let (error, _) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(error.error_code, ErrorCode::UnknownMessage);
+    }
+
     #[tokio::test]
     async fn player_handler_success() {
         let request_id = 1;
@@ -245,13 +1009,180 @@ mod tests {
             DecayQuery {}
                 .serialize(
                     request_id,
+                    &Timestamp::now(),
                     keys.public_key.as_ref().clone(),
                     keys.private_key.as_ref().clone(),
                 )
                 .unwrap(),
         );
-        let response = process_player_event(event, &HandlerSuccess {}).await;
-        assert_eq!(response, "Query=1, pub_key_len=40");
+        let storage = MemoryStorage::new("test").await;
+        let response = process_player_event(event, &HandlerSuccess {}, &storage, &()).await;
+        let (decay, response_request_id) = Decay::deserialize(&response).unwrap();
+        assert_eq!(decay.length.as_milliseconds(), 40);
+        assert_eq!(response_request_id, request_id);
+    }
+
+    #[tokio::test]
+    async fn route_lambdas_rate_limit() {
+        let storage = Arc::new(MemoryStorage::new("test").await);
+        let burst = RateLimits::default().limit(Ping::tag()).burst;
+        // Buckets refill meanwhile, so a few more messages than the burst may get through on a slow machine
+        let rate_limited_at = |responses: Vec<String>| {
+            responses.iter().position(|response| {
+                ServerError::deserialize(response)
+                    .is_ok_and(|(error, _)| error.error_code == ErrorCode::RateLimited)
+            })
+        };
+
+        // Per route lambdas apply the same middlewares as the router, so clients cannot bypass the limits
+        let ping_lambda = public_lambda(HandlerSuccess {}, storage.clone());
+        let mut responses = vec![];
+        for request_id in 0..2 * burst {
+            let event = event_with_body(Ping {}.serialize(request_id).unwrap());
+            responses.push(ping_lambda.process_event(event).await);
+        }
+        let limited = rate_limited_at(responses).unwrap();
+        assert!(limited >= burst as usize);
+
+        let keys = encryption::generate_new_keys();
+        let decay_lambda = player_lambda(HandlerSuccess {}, storage);
+        let mut responses = vec![];
+        for request_id in 0..2 * burst {
+            let body = DecayQuery {}
+                .serialize(
+                    request_id,
+                    &Timestamp::now(),
+                    keys.public_key.as_ref().clone(),
+                    keys.private_key.as_ref().clone(),
+                )
+                .unwrap();
+            responses.push(decay_lambda.process_event(event_with_body(body)).await);
+        }
+        let limited = rate_limited_at(responses).unwrap();
+        assert!(limited >= burst as usize);
+    }
+
+    #[tokio::test]
+    async fn player_handler_replay() {
+        let request_id = 1;
+        let keys = encryption::generate_new_keys();
+        let body = DecayQuery {}
+            .serialize(
+                request_id,
+                &Timestamp::now(),
+                keys.public_key.as_ref().clone(),
+                keys.private_key.as_ref().clone(),
+            )
+            .unwrap();
+        let storage = MemoryStorage::new("test").await;
+        let response = process_player_event(
+            event_with_body(body.clone()),
+            &HandlerSuccess {},
+            &storage,
+            &(),
+        )
+        .await;
+        assert_eq!(Decay::deserialize(&response).unwrap().1, request_id);
+
+        // Exactly the same message is rejected
+        let response =
+            process_player_event(event_with_body(body), &HandlerSuccess {}, &storage, &()).await;
+        let (error, _) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(error.error_code, ErrorCode::ReplayError);
+        assert_eq!(error.request_id, request_id);
+
+        // So as the stale one
+        let stale = Timestamp::from_milliseconds(
+            Timestamp::now().as_milliseconds() - DEFAULT_REPLAY_WINDOW_MS - 1000,
+        );
+        let body = DecayQuery {}
+            .serialize(
+                request_id,
+                &stale,
+                keys.public_key.as_ref().clone(),
+                keys.private_key.as_ref().clone(),
+            )
+            .unwrap();
+        let response =
+            process_player_event(event_with_body(body), &HandlerSuccess {}, &storage, &()).await;
+        let (error, _) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(error.error_code, ErrorCode::ReplayError);
+    }
+
+    /// Counts processed messages and replies with the count as Decay length
+    struct HandlerCounter {
+        calls: Cell<u64>,
+    }
+    impl PlayerEventHandler<DecayQuery> for HandlerCounter {
+        async fn process_message(
+            &self,
+            _: DecayQuery,
+            _: &RequestContext,
+            _: Arc<PublicKey>,
+            _: UserId,
+            _: u32,
+        ) -> Result<Decay, ServerError> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(Decay {
+                started_at: ServerTimestamp::from_milliseconds(0),
+                length: Duration::from_milliseconds(self.calls.get()),
+                ends_at: None,
+                modifiers: vec![],
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn player_handler_idempotency() {
+        let keys = encryption::generate_new_keys();
+        let storage = MemoryStorage::new("test").await;
+        let handler = HandlerCounter {
+            calls: Cell::new(0),
+        };
+        let event = |keys: &encryption::Keys, request_id, idempotency_key: &str| {
+            let body = DecayQuery {}
+                .serialize(
+                    request_id,
+                    &Timestamp::now(),
+                    keys.public_key.as_ref().clone(),
+                    keys.private_key.as_ref().clone(),
+                )
+                .unwrap();
+            let body =
+                serializers::ClientPlayerMessage::add_idempotency_key(&body, idempotency_key)
+                    .unwrap();
+            event_with_body(body)
+        };
+        let send = |event| process_player_event(event, &handler, &storage, &());
+
+        let (decay, request_id) = Decay::deserialize(&send(event(&keys, 1, "a")).await).unwrap();
+        assert_eq!((decay.length.as_milliseconds(), request_id), (1, 1));
+
+        // Retry gets the same response to its own request id without processing the message again
+        let (decay, request_id) = Decay::deserialize(&send(event(&keys, 2, "a")).await).unwrap();
+        assert_eq!((decay.length.as_milliseconds(), request_id), (1, 2));
+
+        // Other keys and keys of other players are processed
+        let (decay, _) = Decay::deserialize(&send(event(&keys, 3, "b")).await).unwrap();
+        assert_eq!(decay.length.as_milliseconds(), 2);
+        let other_keys = encryption::generate_new_keys();
+        let (decay, _) = Decay::deserialize(&send(event(&other_keys, 4, "a")).await).unwrap();
+        assert_eq!(decay.length.as_milliseconds(), 3);
+
+        // Errors are not stored, so failed messages are processed again
+        let response =
+            process_player_event(event(&keys, 5, "c"), &HandlerError {}, &storage, &()).await;
+        assert!(ServerError::deserialize(&response).is_ok());
+        let (decay, _) = Decay::deserialize(&send(event(&keys, 6, "c")).await).unwrap();
+        assert_eq!(decay.length.as_milliseconds(), 4);
+
+        // Duplicate of a message which is still being processed is not processed again
+        let now = ServerTimestamp::now();
+        let reservation = reserve_key(&storage, &keys.public_key, "d", DecayQuery::tag(), 7, now);
+        assert_eq!(reservation.await.unwrap(), Reservation::Reserved(1));
+        let (error, _) = ServerError::deserialize(&send(event(&keys, 8, "d")).await).unwrap();
+        assert!(error.recoverable);
+        assert_eq!(handler.calls.get(), 4);
     }
 
     #[tokio::test]
@@ -262,15 +1193,17 @@ mod tests {
             DecayQuery {}
                 .serialize(
                     request_id,
+                    &Timestamp::now(),
                     keys.public_key.as_ref().clone(),
                     keys.private_key.as_ref().clone(),
                 )
                 .unwrap(),
         );
-        let response = process_player_event(event, &HandlerError {}).await;
+        let storage = MemoryStorage::new("test").await;
+        let response = process_player_event(event, &HandlerError {}, &storage, &()).await;
         assert_eq!(
             response,
-            "-0-.m~#Y\u{7f}4e=3Nn^AU!PCr&Ux2p9&,y^ mVk9u%I5E!qqx3U"
+            "-0-.§2 A?&tD4##gvG0s^1+(~ZxBGQ>zJtjE`(DCgzV]F94ww~Hs5*3LZJiO: !2>>j5"
         );
         let error = ServerError::deserialize(&response).unwrap();
         assert_eq!(error.1, request_id);
@@ -290,8 +1223,9 @@ mod tests {
     #[tokio::test]
     async fn player_handler_bad_data() {
         let event = event_with_body("bad_data".to_string());
-        let response = process_player_event(event, &HandlerError {}).await;
-        assert_eq!(response, "-0--,ZCq?4CjI'UrF!;$9vZ!>x?c=)>$#TOrW+p<m<GBPerVu\u{7f}[#Tm?hirY}P H1*`u:Wf pK)1>L`u)~h~%%a?/jqeZ-c}Joqn,9CUFDL4XEw 3o_ejK~");
+        let storage = MemoryStorage::new("test").await;
+        let response = process_player_event(event, &HandlerError {}, &storage, &()).await;
+        assert_eq!(response, "-0--§2 A?&tQ,|:%YB9r%Q;4prCFF-/u-RQ= D*BC`?:+=X9|MGQ'Vxi(2Oa#!)sc0PL2D&WCgzV]F147rP,*WYLTYKQLmD( ^#Il&k\u{7f}IHS:^yY%H0M_J.=Ek_Q=b2$  6e`R4xOn");
         let error = ServerError::deserialize(&response).unwrap();
         assert_eq!(error.1, 0);
         assert_eq!(
@@ -316,13 +1250,15 @@ mod tests {
             DecayQuery {}
                 .serialize(
                     request_id,
+                    &Timestamp::now(),
                     keys1.public_key.as_ref().clone(),
                     keys2.private_key.as_ref().clone(),
                 )
                 .unwrap(),
         );
-        let response = process_player_event(event, &HandlerError {}).await;
-        assert_eq!(response, "-0--(n/@J@<AJ4[hM4;r)5s;>ar#/gc+294i_o|hN9AV ^v+_Gmp\u{7f}ngnz!7qEE[CFMfgJo`zhRR!XoFCZ$An*Hy@YIi@_B9UkzEm7");
+        let storage = MemoryStorage::new("test").await;
+        let response = process_player_event(event, &HandlerError {}, &storage, &()).await;
+        assert_eq!(response, "-0--§2 A?&tQ,|:%YB9r%Q;4prCFF-/u-RQ= D*BC`?:+=X9|MGQ'Vxi(2Oa#!(+3F(LH4TdCgzV]F147SFFo1vq;=*?zJD)}fDKAu5e0Hr_J!i49 @v (H3");
         let error = ServerError::deserialize(&response).unwrap();
         assert_eq!(error.1, 0);
         assert_eq!(
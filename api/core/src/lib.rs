@@ -9,5 +9,9 @@ pub use logic::*;
 pub mod common;
 pub mod entities;
 pub mod fixtures;
+pub mod game;
 pub mod lambda;
+pub mod middleware;
+pub mod push;
+pub mod routes;
 pub mod storage;
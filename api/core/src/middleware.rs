@@ -0,0 +1,167 @@
+//! Middlewares wrap message handlers to implement cross-cutting concerns like logging, metrics or maintenance mode
+//! without changing the handlers themselves. Middlewares are composed with tuples, e.g. `(Logging, (Metrics, Limits))`
+//! runs `before` hooks from left to right and `after` hooks in the reverse order
+
+use std::sync::Arc;
+
+use aws_lambda_events::apigw::ApiGatewayWebsocketProxyRequest;
+use lambda_runtime::tracing;
+use logic::{encryption::PublicKey, server_error::ServerError};
+
+/// Middlewares which are applied by the lambda helpers and handler registry unless other ones are given
+pub type DefaultMiddleware = LoggingMiddleware;
+
+/// Information about the processed message available to middlewares
+pub struct MessageInfo<'a> {
+    /// Raw API Gateway event the message came with
+    pub event: &'a ApiGatewayWebsocketProxyRequest,
+    /// Tag of the client message
+    pub message_tag: u16,
+    /// Request id of the client message, 0 if message could not be deserialized
+    pub request_id: u32,
+    /// Public key of the player for player messages
+    pub public_key: Option<Arc<PublicKey>>,
+}
+
+/// Hooks which are called around every message handler
+pub trait Middleware {
+    /// Called after the message is deserialized and verified but before it's processed by the handler. Returning an
+    /// error skips the handler and the error is sent back instead
+    async fn before(&self, _info: &MessageInfo<'_>) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    /// Called for every message before the response is sent back, `error` is set if the message failed at any stage
+    async fn after(&self, _info: &MessageInfo<'_>, _error: Option<&ServerError>) {}
+}
+
+/// Empty middleware which does nothing
+impl Middleware for () {}
+
+impl<A: Middleware, B: Middleware> Middleware for (A, B) {
+    async fn before(&self, info: &MessageInfo<'_>) -> Result<(), ServerError> {
+        self.0.before(info).await?;
+        self.1.before(info).await
+    }
+
+    async fn after(&self, info: &MessageInfo<'_>, error: Option<&ServerError>) {
+        self.1.after(info, error).await;
+        self.0.after(info, error).await;
+    }
+}
+
+/// Logs every processed message and the error it failed with
+pub struct LoggingMiddleware {}
+
+impl Middleware for LoggingMiddleware {
+    async fn after(&self, info: &MessageInfo<'_>, error: Option<&ServerError>) {
+        match error {
+            None => tracing::info!(
+                "Processed message tag={} request_id={}",
+                info.message_tag,
+                info.request_id
+            ),
+            Some(err) => tracing::warn!(
+                "Failed message tag={} request_id={}: {:?} {}",
+                info.message_tag,
+                info.request_id,
+                err.error_code,
+                err.error_context.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use logic::{
+        messages::{
+            common::ping::{Ping, ServerStatus},
+            ClientPublicMessage,
+        },
+        server_error::ErrorCode,
+    };
+
+    use crate::{
+        common::ping::PingHandler, fixtures::event_with_body, lambda::process_public_event,
+    };
+
+    use super::*;
+
+    /// Records called hooks, rejects messages in `before` if `reject` is set
+    struct Recorder<'a> {
+        name: &'static str,
+        calls: &'a RefCell<Vec<String>>,
+        reject: bool,
+    }
+
+    impl Middleware for Recorder<'_> {
+        async fn before(&self, info: &MessageInfo<'_>) -> Result<(), ServerError> {
+            self.calls
+                .borrow_mut()
+                .push(format!("{} before {}", self.name, info.request_id));
+            if !self.reject {
+                return Ok(());
+            }
+            Err(ServerError {
+                error_code: ErrorCode::ServerError,
+                error_description: "Rejected".to_string(),
+                error_context: None,
+                request_id: info.request_id,
+                message_tag: info.message_tag,
+                recoverable: true,
+            })
+        }
+
+        async fn after(&self, info: &MessageInfo<'_>, error: Option<&ServerError>) {
+            self.calls.borrow_mut().push(format!(
+                "{} after {} {}",
+                self.name,
+                info.request_id,
+                error.is_some()
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_chain() {
+        let calls = RefCell::new(vec![]);
+        let recorder = |name, reject| Recorder {
+            name,
+            calls: &calls,
+            reject,
+        };
+        let chain = (recorder("a", false), recorder("b", false));
+        let event = event_with_body(Ping {}.serialize(1).unwrap());
+        let response = process_public_event(event, &PingHandler {}, &chain).await;
+        assert!(ServerStatus::deserialize(&response).is_ok());
+        assert_eq!(
+            calls.take(),
+            vec![
+                "a before 1",
+                "b before 1",
+                "b after 1 false",
+                "a after 1 false"
+            ]
+        );
+
+        // Rejected message is not processed and later middlewares are skipped
+        let chain = (recorder("a", true), recorder("b", false));
+        let event = event_with_body(Ping {}.serialize(2).unwrap());
+        let response = process_public_event(event, &PingHandler {}, &chain).await;
+        let (error, request_id) = ServerError::deserialize(&response).unwrap();
+        assert_eq!(error.error_description, "Rejected");
+        assert_eq!(request_id, 2);
+        assert_eq!(
+            calls.take(),
+            vec!["a before 2", "b after 2 true", "a after 2 true"]
+        );
+
+        // Messages which cannot be deserialized are not processed at all
+        let event = event_with_body("bad_data".to_string());
+        process_public_event(event, &PingHandler {}, &chain).await;
+        assert_eq!(calls.take(), vec!["b after 0 true", "a after 0 true"]);
+    }
+}
@@ -0,0 +1,80 @@
+//! Pushing server messages to connected clients - defines main "Push" trait and API Gateway/Memory implementation
+//!
+//! Pushed messages are not responses to client messages, so they are serialized with reserved push request id
+//! which clients use to tell them apart from responses
+
+pub mod push_gateway;
+pub mod push_memory;
+
+use logic::messages::ServerMessage;
+
+/// Push error types
+#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub enum PushErr {
+    /// Connection is closed and message cannot be delivered anymore
+    Gone,
+    /// Message cannot be serialized
+    SerializationError(String),
+    /// Request failed e.g. network error
+    IOError(String),
+}
+
+/// Base trait for the provider which delivers messages to connected clients
+pub trait Push {
+    /// Send already serialized server message to the connection
+    async fn send(&self, connection_id: &str, data: String) -> Result<(), PushErr>;
+
+    /// Push server message to the connection
+    async fn push<T>(&self, connection_id: &str, message: &T) -> Result<(), PushErr>
+    where
+        T: ServerMessage,
+    {
+        let data = message
+            .serialize_push()
+            .map_err(|err| PushErr::SerializationError(err.to_string()))?;
+        self.send(connection_id, data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use logic::{
+        datetime::ServerTimestamp,
+        messages::common::ping::{ServerStatus, Status},
+    };
+
+    use crate::push::push_memory::MemoryPush;
+
+    use super::*;
+
+    const CONNECTION: &str = "etAB-deZIAMCK2g=";
+
+    fn status() -> ServerStatus {
+        ServerStatus {
+            timestamp: Arc::new(ServerTimestamp::from_milliseconds_pure(1)),
+            status: Status::OK,
+            min_protocol_version: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_push() {
+        let push = MemoryPush::default();
+        push.push(CONNECTION, &status()).await.unwrap();
+        push.push(CONNECTION, &status()).await.unwrap();
+        let pushed = push.pushed(CONNECTION);
+        assert_eq!(pushed.len(), 2);
+        assert_eq!(
+            ServerStatus::deserialize_push(&pushed[0]).unwrap(),
+            status()
+        );
+        assert!(push.pushed("other").is_empty());
+
+        // Closed connections are gone
+        push.close(CONNECTION);
+        assert_eq!(push.push(CONNECTION, &status()).await, Err(PushErr::Gone));
+        assert!(push.pushed(CONNECTION).is_empty());
+    }
+}
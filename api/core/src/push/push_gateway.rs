@@ -0,0 +1,49 @@
+//! API Gateway based push which uses API Gateway Management API
+
+use aws_config::BehaviorVersion;
+use aws_sdk_apigatewaymanagement::{
+    config::Builder, error::DisplayErrorContext, primitives::Blob, Client,
+};
+
+use super::{Push, PushErr};
+
+/// API Gateway based push, sends messages to WebSocket connections using `PostToConnection` call
+pub struct GatewayPush {
+    client: Client,
+}
+
+impl GatewayPush {
+    /// Creates a new push for the given API Gateway endpoint, e.g. https://api.deusvent.com for the custom domain
+    /// or https://{api-id}.execute-api.{region}.amazonaws.com/{stage} for the default one
+    pub async fn new(endpoint: &str) -> Self {
+        let shared_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let config = Builder::from(&shared_config).endpoint_url(endpoint).build();
+        Self {
+            client: Client::from_conf(config),
+        }
+    }
+}
+
+impl Push for GatewayPush {
+    async fn send(&self, connection_id: &str, data: String) -> Result<(), PushErr> {
+        self.client
+            .post_to_connection()
+            .connection_id(connection_id)
+            .data(Blob::new(data))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| {
+                if err
+                    .as_service_error()
+                    .is_some_and(|err| err.is_gone_exception())
+                {
+                    return PushErr::Gone;
+                }
+                PushErr::IOError(format!(
+                    "Failed to push a message: {}",
+                    DisplayErrorContext(&err)
+                ))
+            })
+    }
+}
@@ -0,0 +1,46 @@
+//! Memory implementation of a Push
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use super::{Push, PushErr};
+
+/// Memory push, used only for testing and development. Stores all the pushed messages per connection
+#[derive(Default)]
+pub struct MemoryPush {
+    messages: Mutex<HashMap<String, Vec<String>>>,
+    closed: Mutex<HashSet<String>>,
+}
+
+impl MemoryPush {
+    /// Returns all the messages pushed to the connection so far
+    pub fn pushed(&self, connection_id: &str) -> Vec<String> {
+        let messages = self.messages.lock().expect("Error locking data");
+        messages.get(connection_id).cloned().unwrap_or_default()
+    }
+
+    /// Close the connection, all the pushed messages are dropped and following pushes will fail
+    pub fn close(&self, connection_id: &str) {
+        let mut closed = self.closed.lock().expect("Error locking data");
+        closed.insert(connection_id.to_string());
+        let mut messages = self.messages.lock().expect("Error locking data");
+        messages.remove(connection_id);
+    }
+}
+
+impl Push for MemoryPush {
+    async fn send(&self, connection_id: &str, data: String) -> Result<(), PushErr> {
+        let closed = self.closed.lock().expect("Error locking data");
+        if closed.contains(connection_id) {
+            return Err(PushErr::Gone);
+        }
+        let mut messages = self.messages.lock().expect("Error locking data");
+        messages
+            .entry(connection_id.to_string())
+            .or_default()
+            .push(data);
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+//! All the message handlers of the API, used by the router lambda and the development server
+
+use std::sync::Arc;
+
+use logic::messages::game::goals::{ArchiveGoal, CompleteGoal, CreateGoal, GoalsQuery, UpdateGoal};
+
+use crate::{
+    common::{
+        auth::{AuthenticateHandler, ChallengeHandler},
+        ping::PingHandler,
+        version::HandshakeHandler,
+    },
+    game::{
+        decay::DecayHandler,
+        goals::GoalsHandler,
+        identity::{IdentityHandler, IdentityQueryHandler},
+    },
+    lambda::HandlerRegistry,
+    middleware::{DefaultMiddleware, Middleware},
+    storage::Storage,
+};
+
+/// Registry with handlers for every client message and default middlewares
+pub fn all_handlers<S: Storage + 'static>(storage: Arc<S>) -> HandlerRegistry<S> {
+    all_handlers_with_middleware(storage, DefaultMiddleware {})
+}
+
+/// Registry with handlers for every client message, new messages have to be registered here
+pub fn all_handlers_with_middleware<S: Storage + 'static, M: Middleware + 'static>(
+    storage: Arc<S>,
+    middleware: M,
+) -> HandlerRegistry<S, M> {
+    HandlerRegistry::with_middleware(storage.clone(), middleware)
+        .public(PingHandler {})
+        .public(HandshakeHandler {})
+        .connection(ChallengeHandler::new(storage.clone()))
+        .connection(AuthenticateHandler::new(storage.clone()))
+        .player(DecayHandler::new(storage.clone()))
+        .player(IdentityHandler::new(storage.clone()))
+        .player(IdentityQueryHandler::new(storage.clone()))
+        .player::<CreateGoal, _>(GoalsHandler::new(storage.clone()))
+        .player::<UpdateGoal, _>(GoalsHandler::new(storage.clone()))
+        .player::<CompleteGoal, _>(GoalsHandler::new(storage.clone()))
+        .player::<ArchiveGoal, _>(GoalsHandler::new(storage.clone()))
+        .player::<GoalsQuery, _>(GoalsHandler::new(storage))
+}
@@ -0,0 +1,592 @@
+//! Conversion of entity fields to DynamoDB attributes and back, used by `#[derive(Entity)]` and
+//! `#[derive(Attribute)]` for nested structs
+
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use aws_sdk_dynamodb::{primitives::Blob, types::AttributeValue};
+pub use entity_macro::Attribute;
+use logic::{
+    datetime::{Date, Duration, ServerTimestamp},
+    decay::{DecayModifier, DecayModifierKind},
+    encryption::{EncryptedString, SafeString},
+    goal::{GoalCategory, GoalStatus},
+};
+
+use crate::entities::UserId;
+
+use super::StorageErr;
+
+/// Type which is stored as a DynamoDB attribute
+pub trait Attribute: Sized {
+    /// Converts value to the attribute
+    fn to_attribute(&self) -> AttributeValue;
+
+    /// Converts attribute with the given name back to the value
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr>;
+
+    /// Writes value to the attributes with the given name, types which are not stored as a single attribute
+    /// override it
+    fn write(&self, name: &str, attributes: &mut HashMap<String, AttributeValue>) {
+        attributes.insert(name.to_string(), self.to_attribute());
+    }
+
+    /// Returns whether value with the given name is stored in the attributes
+    fn is_stored(name: &str, attributes: &HashMap<String, AttributeValue>) -> bool {
+        attributes.contains_key(name)
+    }
+
+    /// Reads value with the given name from the attributes
+    fn read(name: &str, attributes: &HashMap<String, AttributeValue>) -> Result<Self, StorageErr> {
+        let value = attributes
+            .get(name)
+            .ok_or_else(|| StorageErr::ValidationError(format!("{} attribute not found", name)))?;
+        Self::from_attribute(name, value)
+    }
+}
+
+/// Returns attributes of the map attribute with the given name
+pub fn map_attribute<'a>(
+    name: &str,
+    value: &'a AttributeValue,
+) -> Result<&'a HashMap<String, AttributeValue>, StorageErr> {
+    value
+        .as_m()
+        .map_err(|_| StorageErr::ValidationError(format!("{} is not a map attribute", name)))
+}
+
+/// Prefixes error of a nested attribute with the name of its parent
+pub fn nested_error(name: &str, err: StorageErr) -> StorageErr {
+    match err {
+        StorageErr::ValidationError(err) => {
+            StorageErr::ValidationError(format!("{}.{}", name, err))
+        }
+        err => err,
+    }
+}
+
+fn string_attribute<'a>(name: &str, value: &'a AttributeValue) -> Result<&'a String, StorageErr> {
+    value
+        .as_s()
+        .map_err(|_| StorageErr::ValidationError(format!("{} is not a string attribute", name)))
+}
+
+fn number_attribute<T: FromStr>(name: &str, value: &AttributeValue) -> Result<T, StorageErr> {
+    value
+        .as_n()
+        .map_err(|_| StorageErr::ValidationError(format!("{} is not a number attribute", name)))?
+        .parse()
+        .map_err(|_| StorageErr::ValidationError(format!("{} cannot be parsed as number", name)))
+}
+
+macro_rules! number_attribute {
+    ($($number:ty),*) => {
+        $(
+            impl Attribute for $number {
+                fn to_attribute(&self) -> AttributeValue {
+                    AttributeValue::N(self.to_string())
+                }
+
+                fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+                    number_attribute(name, value)
+                }
+            }
+        )*
+    };
+}
+
+// Bytes are stored as binary attributes instead of lists of numbers, see `Vec<u8>`
+number_attribute!(u16, u32, u64, usize, i8, i16, i32, i64, f32, f64);
+
+/// Unit enums are stored as string attributes with the given names of their variants
+macro_rules! enum_attribute {
+    ($enum:ident { $($variant:ident => $name:literal),* $(,)? }) => {
+        impl Attribute for $enum {
+            fn to_attribute(&self) -> AttributeValue {
+                let name = match self {
+                    $($enum::$variant => $name,)*
+                };
+                AttributeValue::S(name.to_string())
+            }
+
+            fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+                match string_attribute(name, value)?.as_str() {
+                    $($name => Ok($enum::$variant),)*
+                    value => Err(StorageErr::ValidationError(format!(
+                        "{} has unknown value {}",
+                        name, value
+                    ))),
+                }
+            }
+        }
+    };
+}
+
+enum_attribute!(GoalCategory {
+    Health => "health",
+    Career => "career",
+    Relationships => "relationships",
+    Finance => "finance",
+    Learning => "learning",
+    Creativity => "creativity",
+    Other => "other",
+});
+
+enum_attribute!(GoalStatus {
+    Active => "active",
+    Completed => "completed",
+    Archived => "archived",
+});
+
+impl Attribute for bool {
+    fn to_attribute(&self) -> AttributeValue {
+        AttributeValue::Bool(*self)
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        value.as_bool().copied().map_err(|_| {
+            StorageErr::ValidationError(format!("{} is not a boolean attribute", name))
+        })
+    }
+}
+
+impl Attribute for String {
+    fn to_attribute(&self) -> AttributeValue {
+        AttributeValue::S(self.clone())
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        string_attribute(name, value).cloned()
+    }
+}
+
+impl Attribute for UserId {
+    fn to_attribute(&self) -> AttributeValue {
+        AttributeValue::S(self.as_str())
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        string_attribute(name, value)?
+            .parse()
+            .map_err(|err| StorageErr::ValidationError(format!("{} is not valid: {}", name, err)))
+    }
+}
+
+impl Attribute for ServerTimestamp {
+    fn to_attribute(&self) -> AttributeValue {
+        AttributeValue::N(self.as_string())
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        number_attribute(name, value)
+    }
+}
+
+/// Duration is stored in milliseconds
+impl Attribute for Duration {
+    fn to_attribute(&self) -> AttributeValue {
+        AttributeValue::N(self.as_milliseconds().to_string())
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        number_attribute(name, value).map(Duration::from_milliseconds_pure)
+    }
+}
+
+/// Date is stored as YYYY-MM-DD string
+impl Attribute for Date {
+    fn to_attribute(&self) -> AttributeValue {
+        AttributeValue::S(self.as_string())
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        string_attribute(name, value)?
+            .parse()
+            .map_err(|err| StorageErr::ValidationError(format!("{} is not valid: {}", name, err)))
+    }
+}
+
+/// Modifier is stored as a map with its timestamp and either `speed` or `delay` attribute depending on its kind
+impl Attribute for DecayModifier {
+    fn to_attribute(&self) -> AttributeValue {
+        let mut attributes = HashMap::new();
+        self.applied_at.write("applied_at", &mut attributes);
+        match &self.kind {
+            DecayModifierKind::Speed { percent } => percent.write("speed", &mut attributes),
+            DecayModifierKind::Delay { duration } => duration.write("delay", &mut attributes),
+        }
+        AttributeValue::M(attributes)
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        let attributes = map_attribute(name, value)?;
+        let read = || -> Result<Self, StorageErr> {
+            let kind = if attributes.contains_key("speed") {
+                DecayModifierKind::Speed {
+                    percent: Attribute::read("speed", attributes)?,
+                }
+            } else {
+                DecayModifierKind::Delay {
+                    duration: Attribute::read("delay", attributes)?,
+                }
+            };
+            Ok(DecayModifier {
+                applied_at: Attribute::read("applied_at", attributes)?,
+                kind,
+            })
+        };
+        read().map_err(|err| nested_error(name, err))
+    }
+}
+
+/// Plaintext is stored as a string attribute, while encrypted text is stored as a binary one. Entity fields keep
+/// encrypted text in a separate attribute with `_encrypted` suffix
+impl Attribute for SafeString {
+    fn to_attribute(&self) -> AttributeValue {
+        match self {
+            SafeString::Plaintext { value } => AttributeValue::S(value.clone()),
+            SafeString::Encrypted { data } => AttributeValue::B(Blob::new(data.serialize())),
+        }
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        match value {
+            AttributeValue::S(value) => Ok(SafeString::Plaintext {
+                value: value.clone(),
+            }),
+            AttributeValue::B(blob) => Ok(SafeString::Encrypted {
+                data: EncryptedString::deserialize(blob.clone().into_inner())
+                    .map_err(|_| StorageErr::ValidationError(format!("{} is not valid", name)))?,
+            }),
+            _ => Err(StorageErr::ValidationError(format!(
+                "{} is not a string or binary attribute",
+                name
+            ))),
+        }
+    }
+
+    fn write(&self, name: &str, attributes: &mut HashMap<String, AttributeValue>) {
+        let name = match self {
+            SafeString::Plaintext { .. } => name.to_string(),
+            SafeString::Encrypted { .. } => format!("{}_encrypted", name),
+        };
+        attributes.insert(name, self.to_attribute());
+    }
+
+    fn is_stored(name: &str, attributes: &HashMap<String, AttributeValue>) -> bool {
+        attributes.contains_key(name) || attributes.contains_key(&format!("{}_encrypted", name))
+    }
+
+    fn read(name: &str, attributes: &HashMap<String, AttributeValue>) -> Result<Self, StorageErr> {
+        let encrypted_name = format!("{}_encrypted", name);
+        match attributes.get(&encrypted_name) {
+            Some(value @ AttributeValue::B(_)) => Self::from_attribute(&encrypted_name, value),
+            Some(_) => Err(StorageErr::ValidationError(format!(
+                "{} is not a binary attribute",
+                encrypted_name
+            ))),
+            None => Ok(SafeString::Plaintext {
+                value: String::read(name, attributes)?,
+            }),
+        }
+    }
+}
+
+/// Bytes are stored as a binary attribute
+impl Attribute for Vec<u8> {
+    fn to_attribute(&self) -> AttributeValue {
+        AttributeValue::B(Blob::new(self.clone()))
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        value
+            .as_b()
+            .map(|blob| blob.clone().into_inner())
+            .map_err(|_| StorageErr::ValidationError(format!("{} is not a binary attribute", name)))
+    }
+}
+
+impl<T: Attribute> Attribute for Arc<T> {
+    fn to_attribute(&self) -> AttributeValue {
+        self.as_ref().to_attribute()
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        T::from_attribute(name, value).map(Arc::new)
+    }
+
+    fn write(&self, name: &str, attributes: &mut HashMap<String, AttributeValue>) {
+        self.as_ref().write(name, attributes)
+    }
+
+    fn is_stored(name: &str, attributes: &HashMap<String, AttributeValue>) -> bool {
+        T::is_stored(name, attributes)
+    }
+
+    fn read(name: &str, attributes: &HashMap<String, AttributeValue>) -> Result<Self, StorageErr> {
+        T::read(name, attributes).map(Arc::new)
+    }
+}
+
+/// Empty options are not stored at all, inside lists and maps they are stored as null attributes
+impl<T: Attribute> Attribute for Option<T> {
+    fn to_attribute(&self) -> AttributeValue {
+        match self {
+            Some(value) => value.to_attribute(),
+            None => AttributeValue::Null(true),
+        }
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        match value {
+            AttributeValue::Null(_) => Ok(None),
+            value => T::from_attribute(name, value).map(Some),
+        }
+    }
+
+    fn write(&self, name: &str, attributes: &mut HashMap<String, AttributeValue>) {
+        if let Some(value) = self {
+            value.write(name, attributes);
+        }
+    }
+
+    fn read(name: &str, attributes: &HashMap<String, AttributeValue>) -> Result<Self, StorageErr> {
+        if !T::is_stored(name, attributes) {
+            return Ok(None);
+        }
+        T::read(name, attributes).map(Some)
+    }
+}
+
+impl<T: Attribute> Attribute for Vec<T> {
+    fn to_attribute(&self) -> AttributeValue {
+        AttributeValue::L(self.iter().map(Attribute::to_attribute).collect())
+    }
+
+    fn from_attribute(name: &str, value: &AttributeValue) -> Result<Self, StorageErr> {
+        value
+            .as_l()
+            .map_err(|_| StorageErr::ValidationError(format!("{} is not a list attribute", name)))?
+            .iter()
+            .enumerate()
+            .map(|(index, value)| T::from_attribute(&format!("{}[{}]", name, index), value))
+            .collect()
+    }
+}
+
+/// Writes timestamp of an entity `#[ttl]` field, it's stored in seconds as DynamoDB TTL requires. Timestamp is
+/// rounded up, so the entity never expires earlier
+pub fn write_ttl(
+    expires_at: &ServerTimestamp,
+    name: &str,
+    attributes: &mut HashMap<String, AttributeValue>,
+) {
+    let seconds = expires_at.as_milliseconds().div_ceil(1000);
+    seconds.write(name, attributes);
+}
+
+/// Reads timestamp of an entity `#[ttl]` field written by `write_ttl`
+pub fn read_ttl(
+    name: &str,
+    attributes: &HashMap<String, AttributeValue>,
+) -> Result<ServerTimestamp, StorageErr> {
+    let seconds = u64::read(name, attributes)?;
+    Ok(ServerTimestamp::from_milliseconds_pure(seconds * 1000))
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::encryption::generate_new_keys;
+
+    use crate::storage::{Entity, Key};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Clone, Attribute)]
+    struct Reward {
+        amount: u32,
+        granted_at: ServerTimestamp,
+        note: Option<String>,
+    }
+
+    #[derive(Debug, PartialEq, Entity)]
+    #[entity_type = "test"]
+    struct TestEntity {
+        key: Key,
+        created_at: ServerTimestamp,
+        name: String,
+        title: SafeString,
+        score: f64,
+        date: Arc<Date>,
+        length: Arc<Duration>,
+        owner: Option<UserId>,
+        missing: Option<u64>,
+        rewards: Vec<Reward>,
+        best_reward: Reward,
+        avatar: Vec<u8>,
+        status: GoalStatus,
+        modifiers: Vec<DecayModifier>,
+        #[ttl]
+        expires_at: ServerTimestamp,
+    }
+
+    fn serialize(entity: &TestEntity) -> HashMap<String, AttributeValue> {
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version_latest()
+            .build();
+        let writer = aws_sdk_dynamodb::Client::from_conf(config).put_item();
+        entity
+            .serialize(writer)
+            .as_input()
+            .get_item()
+            .clone()
+            .unwrap()
+    }
+
+    #[test]
+    fn entity_attributes() {
+        let reward = Reward {
+            amount: 10,
+            granted_at: ServerTimestamp::from_milliseconds_pure(2),
+            note: None,
+        };
+        let keys = generate_new_keys();
+        let entity = TestEntity {
+            key: Key {
+                user_id: UserId::generate(),
+                entity_id: "entity".to_string(),
+            },
+            created_at: ServerTimestamp::from_milliseconds_pure(1),
+            name: "name".to_string(),
+            title: SafeString::Encrypted {
+                data: EncryptedString::new("title".to_string(), &keys.private_key),
+            },
+            score: 0.5,
+            date: Arc::new(Date::new(2030, 1, 1)),
+            length: Duration::from_milliseconds(3),
+            owner: Some(UserId::generate()),
+            missing: None,
+            rewards: vec![
+                reward.clone(),
+                Reward {
+                    note: Some("note".to_string()),
+                    ..reward.clone()
+                },
+            ],
+            best_reward: reward,
+            avatar: vec![1, 2, 3],
+            status: GoalStatus::Archived,
+            modifiers: vec![
+                DecayModifier {
+                    applied_at: ServerTimestamp::from_milliseconds(4),
+                    kind: DecayModifierKind::Speed { percent: 50 },
+                },
+                DecayModifier {
+                    applied_at: ServerTimestamp::from_milliseconds(5),
+                    kind: DecayModifierKind::Delay {
+                        duration: Duration::from_milliseconds(6),
+                    },
+                },
+            ],
+            expires_at: ServerTimestamp::from_milliseconds_pure(7000),
+        };
+        assert_eq!(TestEntity::entity_type(), "test");
+
+        // Entity is stored with the same attribute names as its fields
+        let data = serialize(&entity);
+        assert_eq!(
+            data.get("score"),
+            Some(&AttributeValue::N("0.5".to_string()))
+        );
+        assert_eq!(
+            data.get("date"),
+            Some(&AttributeValue::S("2030-01-01".to_string()))
+        );
+        assert!(data.contains_key("title_encrypted"));
+        assert!(!data.contains_key("title"));
+        assert!(!data.contains_key("missing"));
+        assert_eq!(
+            data.get("avatar"),
+            Some(&AttributeValue::B(Blob::new(vec![1, 2, 3])))
+        );
+        assert_eq!(
+            data.get("status"),
+            Some(&AttributeValue::S("archived".to_string()))
+        );
+        assert_eq!(
+            data.get("expires_at"),
+            Some(&AttributeValue::N("7".to_string()))
+        );
+        let read = TestEntity::deserialize(entity.key.clone(), data.clone()).unwrap();
+        assert_eq!(read, entity);
+
+        // Missing and mistyped attributes are reported with their full names
+        let err = |data: HashMap<String, AttributeValue>| {
+            TestEntity::deserialize(entity.key.clone(), data).unwrap_err()
+        };
+        let mut broken = data.clone();
+        broken.remove("score");
+        assert_eq!(
+            err(broken),
+            StorageErr::ValidationError("score attribute not found".to_string())
+        );
+        let mut broken = data.clone();
+        broken.insert("name".to_string(), AttributeValue::N("1".to_string()));
+        assert_eq!(
+            err(broken),
+            StorageErr::ValidationError("name is not a string attribute".to_string())
+        );
+        let mut broken = data.clone();
+        broken.insert(
+            "created_at".to_string(),
+            AttributeValue::N("soon".to_string()),
+        );
+        assert_eq!(
+            err(broken),
+            StorageErr::ValidationError("created_at cannot be parsed as number".to_string())
+        );
+        let mut broken = data.clone();
+        let AttributeValue::L(mut rewards) = broken.remove("rewards").unwrap() else {
+            panic!("rewards should be a list");
+        };
+        let AttributeValue::M(mut reward) = rewards.remove(1) else {
+            panic!("reward should be a map");
+        };
+        reward.insert("amount".to_string(), AttributeValue::S("10".to_string()));
+        rewards.push(AttributeValue::M(reward));
+        broken.insert("rewards".to_string(), AttributeValue::L(rewards));
+        assert_eq!(
+            err(broken),
+            StorageErr::ValidationError("rewards[1].amount is not a number attribute".to_string())
+        );
+        let mut broken = data.clone();
+        broken.insert("status".to_string(), AttributeValue::S("lost".to_string()));
+        assert_eq!(
+            err(broken),
+            StorageErr::ValidationError("status has unknown value lost".to_string())
+        );
+
+        // TTL is stored in seconds and rounded up, so entity never expires earlier
+        let expiring = TestEntity {
+            expires_at: ServerTimestamp::from_milliseconds_pure(7001),
+            ..TestEntity::deserialize(entity.key.clone(), data.clone()).unwrap()
+        };
+        let read = TestEntity::deserialize(entity.key.clone(), serialize(&expiring)).unwrap();
+        assert_eq!(read.expires_at.as_milliseconds(), 8000);
+
+        // Plaintext of the safe string is stored under the field name
+        let plaintext = TestEntity {
+            title: SafeString::Plaintext {
+                value: "title".to_string(),
+            },
+            ..entity
+        };
+        let data = serialize(&plaintext);
+        assert_eq!(
+            data.get("title"),
+            Some(&AttributeValue::S("title".to_string()))
+        );
+        assert!(!data.contains_key("title_encrypted"));
+        let read = TestEntity::deserialize(plaintext.key.clone(), data).unwrap();
+        assert_eq!(read, plaintext);
+    }
+}
@@ -1,17 +1,28 @@
 //! Data storage - defines main "Storage" trait and DynamoDB/Memory implementation
 
+pub mod attributes;
 pub mod storage_dynamodb;
 pub mod storage_memory;
+pub mod transaction;
 
 use std::{collections::HashMap, pin::Pin};
 
 use aws_sdk_dynamodb::{
     operation::put_item::builders::PutItemFluentBuilder, types::AttributeValue,
 };
+pub use entity_macro::Entity;
 use futures::Stream;
+use logic::server_error::{ErrorCode, ServerError};
+use transaction::Transaction;
 
 use crate::entities::UserId;
 
+/// Name of the main table which stores all the game data
+pub const GAME_DATA_TABLE: &str = "game_data";
+
+/// Attribute with the entity version which is maintained by the storage for conditional writes
+const VERSION_ATTRIBUTE: &str = "version";
+
 /// Storage error types
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
 pub enum StorageErr {
@@ -21,9 +32,78 @@ pub enum StorageErr {
     IOError(String),
     /// Requested entity not found
     NotFound,
+    /// Entity was changed by someone else since it was read, conditional write was not applied
+    Conflict,
+    /// Deletion failed after some of the entities were already deleted, trying again deletes the rest
+    PartiallyDeleted {
+        /// Amount of entities deleted before the error
+        deleted: usize,
+        /// Error which stopped the deletion
+        err: Box<StorageErr>,
+    },
 }
 
-/// Base user entity where partition key is user id
+impl StorageErr {
+    /// Converts storage error to the ServerError which can be returned to the client
+    pub fn into_server_error(self, message_tag: u16, request_id: u32) -> ServerError {
+        let (error_code, error_description, error_context, recoverable) = match self {
+            StorageErr::PartiallyDeleted { deleted, err } => {
+                let mut error = err.into_server_error(message_tag, request_id);
+                let context = format!("{} entities were deleted before the error", deleted);
+                error.error_context = Some(match error.error_context {
+                    Some(err) => format!("{}: {}", context, err),
+                    None => context,
+                });
+                return error;
+            }
+            StorageErr::ValidationError(err) => (
+                ErrorCode::InvalidData,
+                "Stored data is invalid",
+                Some(err),
+                false,
+            ),
+            StorageErr::IOError(err) => (
+                ErrorCode::IOError,
+                "Temporary error, please try again",
+                Some(err),
+                true,
+            ),
+            StorageErr::NotFound => (ErrorCode::InvalidData, "Data not found", None, false),
+            StorageErr::Conflict => (
+                ErrorCode::IOError,
+                "Data was changed at the same time, please try again",
+                None,
+                true,
+            ),
+        };
+        ServerError {
+            error_code,
+            error_description: error_description.to_string(),
+            error_context,
+            request_id,
+            message_tag,
+            recoverable,
+        }
+    }
+
+    /// Returns the error of a deletion which already deleted `deleted` entities before it happened
+    fn after_deleting(self, deleted: usize) -> Self {
+        match self {
+            _ if deleted == 0 => self,
+            StorageErr::PartiallyDeleted { deleted: more, err } => StorageErr::PartiallyDeleted {
+                deleted: deleted + more,
+                err,
+            },
+            err => StorageErr::PartiallyDeleted {
+                deleted,
+                err: Box::new(err),
+            },
+        }
+    }
+}
+
+/// Base user entity where partition key is user id. Entities with fields which implement `attributes::Attribute`
+/// can derive it with `#[derive(Entity)]` and `#[entity_type = "name"]` attribute
 pub trait Entity {
     /// Return entity type name which is used as a static prefix for the sort key
     fn entity_type() -> &'static str;
@@ -55,9 +135,30 @@ pub trait Storage {
     /// Creates a new Storage for the given table name
     async fn new(table: &'static str) -> Self;
 
-    /// Store user entity in the database
+    /// Store user entity in the database, the entity is overwritten unconditionally and its version is reset to 0
     async fn write<T: Entity>(&self, entity: &T) -> Result<(), StorageErr>;
 
+    /// Store user entity in the database only if its stored version is still `version`. Version 0 means that the
+    /// entity doesn't exist or was written unconditionally. Returns the new version of the entity or
+    /// `StorageErr::Conflict` if the entity was changed in the meantime
+    async fn write_if_version<T: Entity>(
+        &self,
+        entity: &T,
+        version: u64,
+    ) -> Result<u64, StorageErr>;
+
+    /// Starts a new transaction, its operations are applied with `commit`
+    fn transaction(&self) -> Transaction;
+
+    /// Applies all the operations of the transaction atomically. Returns `StorageErr::Conflict` if any version
+    /// check failed, in which case none of the operations is applied
+    async fn commit(&self, transaction: Transaction) -> Result<(), StorageErr>;
+
+    /// Read user entity from the database together with its version
+    async fn read_versioned<T>(&self, key: Key) -> Result<(T, u64), StorageErr>
+    where
+        T: Entity;
+
     /// Read user entity from the database
     async fn read<T>(&self, key: Key) -> Result<T, StorageErr>
     where
@@ -104,6 +205,18 @@ pub trait Storage {
     }
 }
 
+/// Returns version of the entity from its stored data, entities without version attribute have version 0
+fn read_version(data: &HashMap<String, AttributeValue>) -> Result<u64, StorageErr> {
+    let Some(version) = data.get(VERSION_ATTRIBUTE) else {
+        return Ok(0);
+    };
+    version
+        .as_n()
+        .ok()
+        .and_then(|version| version.parse().ok())
+        .ok_or_else(|| StorageErr::ValidationError("version should be a number".to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use futures::StreamExt;
@@ -111,7 +224,7 @@ mod tests {
     use logic::datetime::ServerTimestamp;
 
     use crate::{
-        entities::{Account, UserId},
+        entities::{Account, AccountKey, PlayerConnection, UserId},
         storage::{storage_dynamodb::DynamoStorage, storage_memory::MemoryStorage},
     };
 
@@ -147,6 +260,121 @@ mod tests {
         assert_ne!(acc_orig, acc_modified);
         assert_eq!(acc_modified.created_at, acc_read.created_at);
 
+        // Versioned writes are applied only if the entity wasn't changed since it was read
+        let (mut acc_versioned, version) = storage
+            .read_versioned::<Account>(acc_orig.key.clone())
+            .await
+            .unwrap();
+        assert_eq!(version, 0);
+        acc_versioned.created_at = ServerTimestamp::from_milliseconds_pure(TIME + 2);
+        assert_eq!(
+            storage.write_if_version(&acc_versioned, 0).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            storage.write_if_version(&acc_versioned, 0).await,
+            Err(StorageErr::Conflict)
+        );
+        assert_eq!(
+            storage.write_if_version(&acc_versioned, 2).await,
+            Err(StorageErr::Conflict)
+        );
+        assert_eq!(
+            storage.write_if_version(&acc_versioned, 1).await.unwrap(),
+            2
+        );
+        let (acc_read, version) = storage
+            .read_versioned::<Account>(acc_orig.key.clone())
+            .await
+            .unwrap();
+        assert_eq!((acc_read, version), (acc_versioned, 2));
+        // Unconditional writes reset the version
+        storage.write(&acc_orig).await.unwrap();
+        let (_, version) = storage
+            .read_versioned::<Account>(acc_orig.key.clone())
+            .await
+            .unwrap();
+        assert_eq!(version, 0);
+        // New entities are created with version 0
+        let acc_new = random_account(&User2);
+        assert_eq!(storage.write_if_version(&acc_new, 0).await.unwrap(), 1);
+        assert_eq!(
+            storage.write_if_version(&acc_new, 0).await,
+            Err(StorageErr::Conflict)
+        );
+        storage.delete_entity(acc_new).await.unwrap();
+
+        // Transactions
+        let connection = PlayerConnection {
+            key: Key {
+                user_id: User1.clone(),
+                entity_id: "connection_id".to_string(),
+            },
+            connected_at: ServerTimestamp::from_milliseconds_pure(TIME),
+        };
+        let acc_new = random_account(&User2);
+        storage.write(&acc_new).await.unwrap();
+        let transaction = storage
+            .transaction()
+            .put_if_version(&acc_orig, 0)
+            .put(&connection)
+            .delete::<Account>(&acc_new.key);
+        storage.commit(transaction).await.unwrap();
+        let (acc_read, version) = storage
+            .read_versioned::<Account>(acc_orig.key.clone())
+            .await
+            .unwrap();
+        assert_eq!(acc_read, acc_orig);
+        assert_eq!(version, 1);
+        assert_eq!(
+            storage
+                .read::<PlayerConnection>(connection.key.clone())
+                .await
+                .unwrap(),
+            connection
+        );
+        assert_eq!(
+            storage.read::<Account>(acc_new.key.clone()).await,
+            Err(StorageErr::NotFound)
+        );
+        // Nothing is applied if any of the checks fails
+        let transaction = storage
+            .transaction()
+            .delete::<PlayerConnection>(&connection.key)
+            .check_version::<Account>(&acc_orig.key, 0);
+        assert_eq!(storage.commit(transaction).await, Err(StorageErr::Conflict));
+        let transaction = storage
+            .transaction()
+            .delete::<PlayerConnection>(&connection.key)
+            .put_if_version(&acc_new, 1);
+        assert_eq!(storage.commit(transaction).await, Err(StorageErr::Conflict));
+        assert!(storage
+            .read::<PlayerConnection>(connection.key.clone())
+            .await
+            .is_ok());
+        assert_eq!(
+            storage.read::<Account>(acc_new.key.clone()).await,
+            Err(StorageErr::NotFound)
+        );
+        // Entity can be used only once in a transaction
+        let transaction = storage
+            .transaction()
+            .put(&connection)
+            .delete::<PlayerConnection>(&connection.key);
+        assert!(matches!(
+            storage.commit(transaction).await,
+            Err(StorageErr::ValidationError(_))
+        ));
+        let transaction = storage
+            .transaction()
+            .check_version::<Account>(&acc_orig.key, 1)
+            .delete::<PlayerConnection>(&connection.key);
+        storage.commit(transaction).await.unwrap();
+        assert!(matches!(
+            storage.read::<PlayerConnection>(connection.key).await,
+            Err(StorageErr::NotFound)
+        ));
+
         // Delete/Read
         let key = acc_orig.key.clone();
         storage.delete_entity(acc_orig).await.unwrap();
@@ -174,6 +402,52 @@ mod tests {
             .await
             .unwrap();
         assert!(storage.find::<Account>(&User2).await.next().await.is_none());
+
+        // Find and delete entities don't touch other entity types in the same partition
+        let connection = PlayerConnection {
+            key: Key {
+                user_id: User2.clone(),
+                entity_id: "connection_id".to_string(),
+            },
+            connected_at: ServerTimestamp::from_milliseconds_pure(TIME),
+        };
+        let acc = random_account(&User2);
+        // Entity types are matched by a sort key prefix, so none of them can start with another one
+        let account_key = AccountKey {
+            key: Key {
+                user_id: User2.clone(),
+                entity_id: "public_key".to_string(),
+            },
+            user_id: User2.clone(),
+        };
+        storage.write(&connection).await.unwrap();
+        storage.write(&acc).await.unwrap();
+        storage.write(&account_key).await.unwrap();
+        let found = storage
+            .find::<Account>(&User2)
+            .await
+            .map(|v| v.unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(found, vec![acc]);
+        storage
+            .delete_entities(&User2, Account::entity_type())
+            .await
+            .unwrap();
+        let found = storage
+            .find::<PlayerConnection>(&User2)
+            .await
+            .map(|v| v.unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(found, vec![connection]);
+        let found = storage
+            .find::<AccountKey>(&User2)
+            .await
+            .map(|v| v.unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(found, vec![account_key]);
     }
 
     async fn cleanup(storage: &impl Storage) {
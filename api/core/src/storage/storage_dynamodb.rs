@@ -1,11 +1,19 @@
 //! DynamoDB based storage
 
-use std::{collections::HashMap, pin::Pin};
+use std::{collections::HashMap, pin::Pin, time::Duration};
 
-use aws_config::BehaviorVersion;
+use aws_config::{retry::RetryConfig, BehaviorVersion};
 use aws_sdk_dynamodb::{
+    config::Builder,
     error::DisplayErrorContext,
-    types::{AttributeValue, DeleteRequest, Select, WriteRequest},
+    operation::{
+        get_item::builders::GetItemFluentBuilder, put_item::builders::PutItemFluentBuilder,
+        transact_write_items::TransactWriteItemsError,
+    },
+    types::{
+        AttributeValue, ConditionCheck, Delete, DeleteRequest, Put, ReturnValue, Select,
+        TransactWriteItem, WriteRequest,
+    },
     Client,
 };
 use futures::Stream;
@@ -14,7 +22,55 @@ use async_stream::stream;
 
 use crate::entities::UserId;
 
-use super::{Entity, Key, Storage, StorageErr};
+use super::{
+    read_version,
+    transaction::{Operation, Transaction},
+    Entity, Key, Storage, StorageErr, VERSION_ATTRIBUTE,
+};
+
+/// Maximum amount of keys in a single `BatchWriteItem` request
+const BATCH_WRITE_LIMIT: usize = 25;
+
+/// Retry limits for throttled requests and for unprocessed items of batch requests. Delay between attempts grows
+/// exponentially up to `max_backoff` and a random part of it is taken to spread retries of concurrent lambdas
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryLimits {
+    /// Maximum amount of attempts including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Maximum delay between attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryLimits {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryLimits {
+    /// Returns delay before the next attempt after `attempt` attempts were made
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        backoff.mul_f64(rand::random::<f64>())
+    }
+
+    /// Returns SDK retry config which retries throttled and failed requests of all the operations
+    fn sdk_config(&self) -> RetryConfig {
+        RetryConfig::standard()
+            .with_max_attempts(self.max_attempts)
+            .with_initial_backoff(self.initial_backoff)
+            .with_max_backoff(self.max_backoff)
+    }
+}
 
 /// DynamoDB based storage:
 /// pk - partition key which is user id
@@ -22,65 +78,253 @@ use super::{Entity, Key, Storage, StorageErr};
 pub struct DynamoStorage {
     client: Client,
     table: &'static str,
+    retry_limits: RetryLimits,
 }
 
 impl DynamoStorage {
-    async fn batch_delete(&self, data: Vec<(String, String)>) -> Result<(), StorageErr> {
+    /// Replaces default retry limits of the storage
+    pub fn with_retry_limits(mut self, retry_limits: RetryLimits) -> Self {
+        let config = self
+            .client
+            .config()
+            .to_builder()
+            .retry_config(retry_limits.sdk_config())
+            .build();
+        self.client = Client::from_conf(config);
+        self.retry_limits = retry_limits;
+        self
+    }
+
+    fn put_item<T: Entity>(&self, entity: &T) -> PutItemFluentBuilder {
+        let pk = AttributeValue::S(entity.key().user_id.as_str().to_string());
+        let sk = sort_key(T::entity_type(), &entity.key().entity_id);
+        let builder = self
+            .client
+            .put_item()
+            .table_name(self.table)
+            .item("pk", pk)
+            .item("sk", sk);
+        entity.serialize(builder)
+    }
+
+    fn transact_item(&self, operation: Operation) -> Result<TransactWriteItem, StorageErr> {
+        let item = match operation {
+            Operation::Put {
+                item,
+                version: None,
+                ..
+            } => TransactWriteItem::builder().put(
+                Put::builder()
+                    .table_name(self.table)
+                    .set_item(Some(item))
+                    .build()
+                    .map_err(build_error)?,
+            ),
+            Operation::Put {
+                item,
+                version: Some(version),
+                ..
+            } => {
+                let (condition, names, values) = version_condition(version);
+                TransactWriteItem::builder().put(
+                    Put::builder()
+                        .table_name(self.table)
+                        .set_item(Some(item))
+                        .condition_expression(condition)
+                        .set_expression_attribute_names(Some(names))
+                        .set_expression_attribute_values(values)
+                        .build()
+                        .map_err(build_error)?,
+                )
+            }
+            Operation::Delete { pk, sk } => TransactWriteItem::builder().delete(
+                Delete::builder()
+                    .table_name(self.table)
+                    .key("pk", AttributeValue::S(pk))
+                    .key("sk", AttributeValue::S(sk))
+                    .build()
+                    .map_err(build_error)?,
+            ),
+            Operation::CheckVersion { pk, sk, version } => {
+                let (condition, names, values) = version_condition(version);
+                TransactWriteItem::builder().condition_check(
+                    ConditionCheck::builder()
+                        .table_name(self.table)
+                        .key("pk", AttributeValue::S(pk))
+                        .key("sk", AttributeValue::S(sk))
+                        .condition_expression(condition)
+                        .set_expression_attribute_names(Some(names))
+                        .set_expression_attribute_values(values)
+                        .build()
+                        .map_err(build_error)?,
+                )
+            }
+        };
+        Ok(item.build())
+    }
+
+    fn get_item<T: Entity>(&self, key: &Key) -> GetItemFluentBuilder {
+        let pk = key.user_id.as_str().to_string();
+        let sk = sort_key(T::entity_type(), key.entity_id.as_str());
+        let keys = HashMap::from([
+            ("pk".to_string(), AttributeValue::S(pk)),
+            ("sk".to_string(), sk),
+        ]);
         self.client
-            .batch_write_item()
-            .request_items(
-                self.table,
-                data.into_iter()
-                    .map(|(pk, sk)| {
-                        WriteRequest::builder()
-                            .set_delete_request(Some(
-                                DeleteRequest::builder()
-                                    .key("pk", AttributeValue::S(pk))
-                                    .key("sk", AttributeValue::S(sk))
-                                    .build()
-                                    .expect("DeleteRequest should be always created"),
-                            ))
+            .get_item()
+            .table_name(self.table)
+            .set_key(Some(keys))
+    }
+
+    /// Deletes given keys and returns their amount. Keys which DynamoDB left unprocessed, e.g. because of throttling,
+    /// are retried with backoff. If some of them are still not deleted, `StorageErr::PartiallyDeleted` tells how many
+    /// keys were deleted
+    async fn batch_delete(&self, data: Vec<(String, String)>) -> Result<usize, StorageErr> {
+        let total = data.len();
+        let mut requests: Vec<_> = data
+            .into_iter()
+            .map(|(pk, sk)| {
+                WriteRequest::builder()
+                    .set_delete_request(Some(
+                        DeleteRequest::builder()
+                            .key("pk", AttributeValue::S(pk))
+                            .key("sk", AttributeValue::S(sk))
                             .build()
-                    })
-                    .collect(),
-            )
+                            .expect("DeleteRequest should be always created"),
+                    ))
+                    .build()
+            })
+            .collect();
+        let mut attempt = 1;
+        loop {
+            let deleted = total - requests.len();
+            let output = self
+                .client
+                .batch_write_item()
+                .request_items(self.table, requests)
+                .send()
+                .await
+                .map_err(|err| {
+                    StorageErr::IOError(format!(
+                        "Failed to delete the keys: {}",
+                        DisplayErrorContext(&err)
+                    ))
+                    .after_deleting(deleted)
+                })?;
+            requests = output
+                .unprocessed_items
+                .and_then(|mut items| items.remove(self.table))
+                .unwrap_or_default();
+            if requests.is_empty() {
+                return Ok(total);
+            }
+            if attempt >= self.retry_limits.max_attempts {
+                return Err(StorageErr::IOError(format!(
+                    "{} of {} keys were not deleted after {} attempts",
+                    requests.len(),
+                    total,
+                    attempt
+                ))
+                .after_deleting(total - requests.len()));
+            }
+            tokio::time::sleep(self.retry_limits.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+impl Storage for DynamoStorage {
+    async fn new(table: &'static str) -> Self {
+        let shared_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let retry_limits = RetryLimits::default();
+        let config = Builder::from(&shared_config)
+            .retry_config(retry_limits.sdk_config())
+            .build();
+        Self {
+            client: Client::from_conf(config),
+            table,
+            retry_limits,
+        }
+    }
+
+    async fn write<T: Entity>(&self, entity: &T) -> Result<(), StorageErr> {
+        self.put_item(entity)
             .send()
             .await
+            .map(|_| ())
             .map_err(|err| {
                 StorageErr::IOError(format!(
-                    "Failed to delete the keys: {}",
+                    "Failed to write an entity: {}",
                     DisplayErrorContext(&err)
                 ))
             })
-            .map(|_| ())
     }
-}
 
-impl Storage for DynamoStorage {
-    async fn new(table: &'static str) -> Self {
-        let shared_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
-        let client = Client::new(&shared_config);
-        Self { client, table }
+    async fn write_if_version<T: Entity>(
+        &self,
+        entity: &T,
+        version: u64,
+    ) -> Result<u64, StorageErr> {
+        let new_version = version + 1;
+        let (condition, names, values) = version_condition(version);
+        self.put_item(entity)
+            .item(
+                VERSION_ATTRIBUTE,
+                AttributeValue::N(new_version.to_string()),
+            )
+            .condition_expression(condition)
+            .set_expression_attribute_names(Some(names))
+            .set_expression_attribute_values(values)
+            .send()
+            .await
+            .map(|_| new_version)
+            .map_err(|err| {
+                if err
+                    .as_service_error()
+                    .is_some_and(|err| err.is_conditional_check_failed_exception())
+                {
+                    return StorageErr::Conflict;
+                }
+                StorageErr::IOError(format!(
+                    "Failed to write an entity: {}",
+                    DisplayErrorContext(&err)
+                ))
+            })
     }
 
-    async fn write<T: Entity>(&self, entity: &T) -> Result<(), StorageErr> {
-        let pk = AttributeValue::S(entity.key().user_id.as_str().to_string());
-        let sk = sort_key(T::entity_type(), &entity.key().entity_id);
-        let builder = self
-            .client
-            .put_item()
-            .table_name(self.table)
-            .item("pk", pk)
-            .item("sk", sk);
+    fn transaction(&self) -> Transaction {
+        Transaction::new(self.client.clone())
+    }
 
-        entity
-            .serialize(builder)
+    async fn commit(&self, transaction: Transaction) -> Result<(), StorageErr> {
+        let operations = transaction.into_operations()?;
+        if operations.is_empty() {
+            return Ok(());
+        }
+        let items = operations
+            .into_iter()
+            .map(|operation| self.transact_item(operation))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.client
+            .transact_write_items()
+            .set_transact_items(Some(items))
             .send()
             .await
             .map(|_| ())
             .map_err(|err| {
+                // Transaction is canceled as a whole, conflict is reported if any of the conditions failed
+                let conflict = match err.as_service_error() {
+                    Some(TransactWriteItemsError::TransactionCanceledException(err)) => err
+                        .cancellation_reasons()
+                        .iter()
+                        .any(|reason| reason.code() == Some("ConditionalCheckFailed")),
+                    _ => false,
+                };
+                if conflict {
+                    return StorageErr::Conflict;
+                }
                 StorageErr::IOError(format!(
-                    "Failed to write an entity: {}",
+                    "Failed to commit a transaction: {}",
                     DisplayErrorContext(&err)
                 ))
             })
@@ -90,30 +334,31 @@ impl Storage for DynamoStorage {
     where
         T: Entity,
     {
-        let pk = key.user_id.as_str().to_string();
-        let sk = sort_key(T::entity_type(), key.entity_id.as_str());
-        let keys = HashMap::from([
-            ("pk".to_string(), AttributeValue::S(pk)),
-            ("sk".to_string(), sk),
-        ]);
         let data = self
-            .client
-            .get_item()
-            .table_name(self.table)
-            .set_key(Some(keys))
+            .get_item::<T>(&key)
             .send()
             .await
-            .map_err(|err| {
-                StorageErr::IOError(format!(
-                    "Failed to read an entity: {}",
-                    DisplayErrorContext(&err)
-                ))
-            })?
+            .map_err(read_error)?
             .item
             .ok_or(StorageErr::NotFound)?;
         T::deserialize(key, data)
     }
 
+    async fn read_versioned<T>(&self, key: Key) -> Result<(T, u64), StorageErr>
+    where
+        T: Entity,
+    {
+        let data = self
+            .get_item::<T>(&key)
+            .send()
+            .await
+            .map_err(read_error)?
+            .item
+            .ok_or(StorageErr::NotFound)?;
+        let version = read_version(&data)?;
+        Ok((T::deserialize(key, data)?, version))
+    }
+
     async fn delete(
         &self,
         user_id: &UserId,
@@ -121,12 +366,24 @@ impl Storage for DynamoStorage {
         entity_id: Option<&str>,
     ) -> Result<usize, StorageErr> {
         let pk = user_id.as_str().to_string();
-        // Delete a concrete entity by pk, entity name and sk
+        // Delete a concrete entity by pk, entity name and sk, it's counted only if it existed
         if let (Some(name), Some(sk)) = (entity_type, entity_id) {
             return self
-                .batch_delete(vec![(pk, format!("{}_{}", name, sk))])
+                .client
+                .delete_item()
+                .table_name(self.table)
+                .key("pk", AttributeValue::S(pk))
+                .key("sk", AttributeValue::S(format!("{}_{}", name, sk)))
+                .return_values(ReturnValue::AllOld)
+                .send()
                 .await
-                .map(|_| 1);
+                .map(|output| output.attributes.map_or(0, |_| 1))
+                .map_err(|err| {
+                    StorageErr::IOError(format!(
+                        "Failed to delete an entity: {}",
+                        DisplayErrorContext(&err)
+                    ))
+                });
         }
 
         // DynamoDB doesn't provide a simple way to delete records by partition or by sort key prefix
@@ -137,7 +394,7 @@ impl Storage for DynamoStorage {
         let filter = match entity_type {
             Some(name) => {
                 attribute_values.insert(":sk".to_string(), AttributeValue::S(format!("{}_", name)));
-                "pk = :pk AND sk > :sk"
+                "pk = :pk AND begins_with(sk, :sk)"
             }
             None => "pk = :pk",
         };
@@ -161,11 +418,12 @@ impl Storage for DynamoStorage {
                     "Failed to fetch keys for deletion: {}",
                     DisplayErrorContext(&err)
                 ))
+                .after_deleting(deleted)
             })?;
 
-            if delete_chunk.len() == 25 {
-                deleted += delete_chunk.len();
-                self.batch_delete(delete_chunk).await?;
+            if delete_chunk.len() == BATCH_WRITE_LIMIT {
+                let chunk = self.batch_delete(delete_chunk).await;
+                deleted += chunk.map_err(|err| err.after_deleting(deleted))?;
                 delete_chunk = vec![];
             }
 
@@ -176,8 +434,8 @@ impl Storage for DynamoStorage {
             delete_chunk.push((pk, sk))
         }
         if !delete_chunk.is_empty() {
-            deleted += delete_chunk.len();
-            self.batch_delete(delete_chunk).await?;
+            let chunk = self.batch_delete(delete_chunk).await;
+            deleted += chunk.map_err(|err| err.after_deleting(deleted))?;
         }
         Ok(deleted)
     }
@@ -199,7 +457,7 @@ impl Storage for DynamoStorage {
             .client
             .query()
             .table_name(self.table)
-            .key_condition_expression("pk = :pk AND sk > :sk")
+            .key_condition_expression("pk = :pk AND begins_with(sk, :sk)")
             .set_expression_attribute_values(Some(attributes));
         let mut paginator = res.into_paginator().items().send();
         let stream = stream! {
@@ -223,6 +481,43 @@ fn sort_key(entity_name: &str, sk: &str) -> AttributeValue {
     AttributeValue::S(format!("{}_{}", entity_name, sk))
 }
 
+/// Returns condition expression with its attribute names and values which checks that the stored entity has the
+/// given version. Entities written unconditionally have no version attribute, same as the ones which don't exist yet
+fn version_condition(
+    version: u64,
+) -> (
+    &'static str,
+    HashMap<String, String>,
+    Option<HashMap<String, AttributeValue>>,
+) {
+    let names = HashMap::from([("#version".to_string(), VERSION_ATTRIBUTE.to_string())]);
+    match version {
+        0 => ("attribute_not_exists(#version)", names, None),
+        _ => (
+            "#version = :version",
+            names,
+            Some(HashMap::from([(
+                ":version".to_string(),
+                AttributeValue::N(version.to_string()),
+            )])),
+        ),
+    }
+}
+
+fn build_error(err: impl std::error::Error) -> StorageErr {
+    StorageErr::ValidationError(format!(
+        "Failed to build a transaction: {}",
+        DisplayErrorContext(&err)
+    ))
+}
+
+fn read_error(err: impl std::error::Error) -> StorageErr {
+    StorageErr::IOError(format!(
+        "Failed to read an entity: {}",
+        DisplayErrorContext(&err)
+    ))
+}
+
 fn read_string_attribute(
     key: &str,
     data: &HashMap<String, AttributeValue>,
@@ -239,3 +534,131 @@ fn read_string_attribute(
         })
         .cloned()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use aws_sdk_dynamodb::config::{
+        http::{HttpRequest, HttpResponse},
+        Credentials, Region, RuntimeComponents,
+    };
+    use aws_smithy_runtime_api::{
+        client::http::{
+            HttpClient, HttpConnector, HttpConnectorFuture, HttpConnectorSettings,
+            SharedHttpConnector,
+        },
+        http::StatusCode,
+    };
+    use aws_smithy_types::body::SdkBody;
+
+    use super::*;
+
+    /// HTTP client which answers requests with the given response bodies in order and counts the requests
+    #[derive(Debug, Clone)]
+    struct StubClient {
+        responses: Arc<Mutex<Vec<&'static str>>>,
+        requests: Arc<Mutex<usize>>,
+    }
+
+    impl StubClient {
+        fn new(responses: &[&'static str]) -> Self {
+            Self {
+                responses: Arc::new(Mutex::new(responses.iter().rev().copied().collect())),
+                requests: Arc::new(Mutex::new(0)),
+            }
+        }
+
+        fn storage(&self, max_attempts: u32) -> DynamoStorage {
+            let config = Builder::new()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new("eu-west-1"))
+                .credentials_provider(Credentials::new("key", "secret", None, None, "test"))
+                .http_client(self.clone())
+                .build();
+            DynamoStorage {
+                client: Client::from_conf(config),
+                table: "test",
+                retry_limits: RetryLimits {
+                    max_attempts,
+                    initial_backoff: Duration::from_millis(1),
+                    max_backoff: Duration::from_millis(1),
+                },
+            }
+        }
+    }
+
+    impl HttpConnector for StubClient {
+        fn call(&self, _request: HttpRequest) -> HttpConnectorFuture {
+            *self.requests.lock().unwrap() += 1;
+            let body = self
+                .responses
+                .lock()
+                .unwrap()
+                .pop()
+                .expect("Unexpected request");
+            let response =
+                HttpResponse::new(StatusCode::try_from(200).unwrap(), SdkBody::from(body));
+            HttpConnectorFuture::ready(Ok(response))
+        }
+    }
+
+    impl HttpClient for StubClient {
+        fn http_connector(
+            &self,
+            _settings: &HttpConnectorSettings,
+            _components: &RuntimeComponents,
+        ) -> SharedHttpConnector {
+            SharedHttpConnector::new(self.clone())
+        }
+    }
+
+    const UNPROCESSED: &str = r#"{"UnprocessedItems":{"test":[{"DeleteRequest":{"Key":{"pk":{"S":"a"},"sk":{"S":"b"}}}}]}}"#;
+    const PROCESSED: &str = r#"{"UnprocessedItems":{}}"#;
+
+    #[tokio::test]
+    async fn batch_delete_unprocessed_items() {
+        let keys = || {
+            vec![
+                ("a".to_string(), "b".to_string()),
+                ("c".to_string(), "d".to_string()),
+            ]
+        };
+
+        // Unprocessed items are retried until they are deleted
+        let client = StubClient::new(&[UNPROCESSED, PROCESSED]);
+        assert_eq!(client.storage(5).batch_delete(keys()).await, Ok(2));
+        assert_eq!(*client.requests.lock().unwrap(), 2);
+
+        // Keys which were deleted before the attempts ran out are reported with the error
+        let client = StubClient::new(&[UNPROCESSED]);
+        match client.storage(1).batch_delete(keys()).await {
+            Err(StorageErr::PartiallyDeleted { deleted, err }) => {
+                assert_eq!(deleted, 1);
+                assert!(matches!(*err, StorageErr::IOError(_)));
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let limits = RetryLimits {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+        };
+        for (attempt, max_backoff) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1000),
+            (30, 1000),
+        ] {
+            for _ in 0..100 {
+                assert!(limits.backoff(attempt) <= Duration::from_millis(max_backoff));
+            }
+        }
+    }
+}
@@ -14,7 +14,11 @@ use futures::{stream, Stream};
 
 use crate::entities::UserId;
 
-use super::{Entity, Key, Storage, StorageErr};
+use super::{
+    read_version,
+    transaction::{Operation, Transaction},
+    Entity, Key, Storage, StorageErr, VERSION_ATTRIBUTE,
+};
 
 /// Memory storage, used only for testing and development. In case of errors, it panics most of the time
 /// to highlight mistakes early in the development process
@@ -23,6 +27,38 @@ pub struct MemoryStorage {
     client: Client,
 }
 
+impl MemoryStorage {
+    /// Returns storage key and the item for the given entity
+    fn put_item<T: Entity>(&self, entity: &T) -> (String, PutItemFluentBuilder) {
+        let pk = entity.key().user_id.as_str().to_string();
+        let sk = format!("{}_{}", T::entity_type(), entity.key().entity_id);
+        let storage_key = format!("{}_{}", pk, sk);
+        let item = entity.serialize(
+            self.client
+                .put_item()
+                .item("pk", AttributeValue::S(pk))
+                .item("sk", AttributeValue::S(sk)),
+        );
+        (storage_key, item)
+    }
+
+    /// Returns stored data of the entity with the given key
+    fn get_item<T: Entity>(
+        &self,
+        key: &Key,
+    ) -> Result<HashMap<String, AttributeValue>, StorageErr> {
+        let pk = key.user_id.as_str().to_string();
+        let sk = format!("{}_{}", T::entity_type(), key.entity_id);
+        let storage_key = format!("{}_{}", pk, sk);
+        let data = self.data.lock().expect("Error locking data");
+        let item = match data.get(&storage_key) {
+            Some(item) => item,
+            None => return Err(StorageErr::NotFound),
+        };
+        Ok(item.as_input().clone().build().unwrap().item.unwrap())
+    }
+}
+
 impl Storage for MemoryStorage {
     async fn new(_: &'static str) -> Self {
         let shared_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
@@ -34,19 +70,72 @@ impl Storage for MemoryStorage {
     }
 
     async fn write<T: Entity>(&self, entity: &T) -> Result<(), StorageErr> {
-        let pk = entity.key().user_id.as_str().to_string();
-        let sk = format!("{}_{}", T::entity_type(), entity.key().entity_id);
-        let storage_key = format!("{}_{}", pk, sk);
+        let (storage_key, item) = self.put_item(entity);
+        let mut data = self.data.lock().expect("Error locking data");
+        data.insert(storage_key, item);
+        Ok(())
+    }
+
+    async fn write_if_version<T: Entity>(
+        &self,
+        entity: &T,
+        version: u64,
+    ) -> Result<u64, StorageErr> {
+        let (storage_key, item) = self.put_item(entity);
+        // Lock is held between the version check and the write, the same as DynamoDB conditional writes
         let mut data = self.data.lock().expect("Error locking data");
+        if stored_version(&data, &storage_key)? != version {
+            return Err(StorageErr::Conflict);
+        }
+        let new_version = version + 1;
         data.insert(
             storage_key,
-            entity.serialize(
-                self.client
-                    .put_item()
-                    .item("pk", AttributeValue::S(pk))
-                    .item("sk", AttributeValue::S(sk)),
+            item.item(
+                VERSION_ATTRIBUTE,
+                AttributeValue::N(new_version.to_string()),
             ),
         );
+        Ok(new_version)
+    }
+
+    fn transaction(&self) -> Transaction {
+        Transaction::new(self.client.clone())
+    }
+
+    async fn commit(&self, transaction: Transaction) -> Result<(), StorageErr> {
+        let operations = transaction.into_operations()?;
+        // All the versions are checked before any change is applied while the lock is held, so the transaction is
+        // either applied completely or not at all
+        let mut data = self.data.lock().expect("Error locking data");
+        for operation in &operations {
+            let (pk, sk, version) = match operation {
+                Operation::Put {
+                    pk,
+                    sk,
+                    version: Some(version),
+                    ..
+                }
+                | Operation::CheckVersion { pk, sk, version } => (pk, sk, *version),
+                _ => continue,
+            };
+            if stored_version(&data, &format!("{}_{}", pk, sk))? != version {
+                return Err(StorageErr::Conflict);
+            }
+        }
+        for operation in operations {
+            match operation {
+                Operation::Put { pk, sk, item, .. } => {
+                    data.insert(
+                        format!("{}_{}", pk, sk),
+                        self.client.put_item().set_item(Some(item)),
+                    );
+                }
+                Operation::Delete { pk, sk } => {
+                    data.remove(&format!("{}_{}", pk, sk));
+                }
+                Operation::CheckVersion { .. } => {}
+            }
+        }
         Ok(())
     }
 
@@ -54,18 +143,19 @@ impl Storage for MemoryStorage {
     where
         T: Entity,
     {
-        let pk = key.user_id.as_str().to_string();
-        let sk = format!("{}_{}", T::entity_type(), key.entity_id);
-        let storage_key = format!("{}_{}", pk, sk);
-        let data = self.data.lock().expect("Error locking data");
-        let item = match data.get(&storage_key) {
-            Some(item) => item,
-            None => return Err(StorageErr::NotFound),
-        };
-        let data = item.as_input().clone().build().unwrap().item.unwrap();
+        let data = self.get_item::<T>(&key)?;
         T::deserialize(key, data)
     }
 
+    async fn read_versioned<T>(&self, key: Key) -> Result<(T, u64), StorageErr>
+    where
+        T: Entity,
+    {
+        let data = self.get_item::<T>(&key)?;
+        let version = read_version(&data)?;
+        Ok((T::deserialize(key, data)?, version))
+    }
+
     async fn delete(
         &self,
         user_id: &UserId,
@@ -93,11 +183,11 @@ impl Storage for MemoryStorage {
         T: Entity + 'static,
     {
         let entity_type = T::entity_type();
-        let prefix = format!("{}_{}", user_id.as_str(), entity_type);
+        let prefix = format!("{}_{}_", user_id.as_str(), entity_type);
         let data = self.data.lock().expect("Error locking data");
         let mut results = vec![];
-        for (key, value) in data.range(prefix..) {
-            if !key.starts_with(key) {
+        for (key, value) in data.range(prefix.clone()..) {
+            if !key.starts_with(&prefix) {
                 break;
             }
             let data = value.as_input().clone().build().unwrap().item.unwrap();
@@ -116,6 +206,17 @@ impl Storage for MemoryStorage {
     }
 }
 
+/// Returns version of the stored entity, entities which don't exist have version 0
+fn stored_version(
+    data: &BTreeMap<String, PutItemFluentBuilder>,
+    storage_key: &str,
+) -> Result<u64, StorageErr> {
+    match data.get(storage_key) {
+        Some(stored) => read_version(&stored.as_input().clone().build().unwrap().item.unwrap()),
+        None => Ok(0),
+    }
+}
+
 fn read_string_attribute(key: &str, data: &HashMap<String, AttributeValue>) -> String {
     data.get(key)
         .unwrap_or_else(|| panic!("{} attribute should exists", key))
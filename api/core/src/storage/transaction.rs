@@ -0,0 +1,137 @@
+//! Transactions which change several entities atomically, all the operations are either applied together or none
+//! of them is applied
+
+use std::collections::{HashMap, HashSet};
+
+use aws_sdk_dynamodb::{types::AttributeValue, Client};
+
+use super::{Entity, Key, StorageErr, VERSION_ATTRIBUTE};
+
+/// Maximum amount of operations in a single transaction, the same as the DynamoDB limit
+pub const MAX_TRANSACTION_OPERATIONS: usize = 100;
+
+/// Single operation of a transaction, entities are addressed by their partition and full sort keys
+#[derive(Debug)]
+pub(super) enum Operation {
+    /// Store the entity, it's written only if its stored version matches when the version is set
+    Put {
+        pk: String,
+        sk: String,
+        item: HashMap<String, AttributeValue>,
+        version: Option<u64>,
+    },
+    /// Delete the entity
+    Delete { pk: String, sk: String },
+    /// Check that the stored version of the entity matches without changing it
+    CheckVersion {
+        pk: String,
+        sk: String,
+        version: u64,
+    },
+}
+
+impl Operation {
+    fn keys(&self) -> (&str, &str) {
+        match self {
+            Operation::Put { pk, sk, .. }
+            | Operation::Delete { pk, sk }
+            | Operation::CheckVersion { pk, sk, .. } => (pk, sk),
+        }
+    }
+}
+
+/// Builder of a transaction which is created by `Storage::transaction` and applied by `Storage::commit`. Every entity
+/// can be used only once in a transaction. Versions follow the same rules as `Storage::write_if_version`
+pub struct Transaction {
+    client: Client,
+    operations: Vec<Operation>,
+}
+
+impl Transaction {
+    pub(super) fn new(client: Client) -> Self {
+        Self {
+            client,
+            operations: vec![],
+        }
+    }
+
+    /// Stores the entity unconditionally, its version is reset to 0
+    pub fn put<T: Entity>(self, entity: &T) -> Self {
+        self.put_item(entity, None)
+    }
+
+    /// Stores the entity only if its stored version is still `version`, the version is incremented on commit
+    pub fn put_if_version<T: Entity>(self, entity: &T, version: u64) -> Self {
+        self.put_item(entity, Some(version))
+    }
+
+    /// Deletes the entity with the given key
+    pub fn delete<T: Entity>(mut self, key: &Key) -> Self {
+        let (pk, sk) = keys::<T>(key);
+        self.operations.push(Operation::Delete { pk, sk });
+        self
+    }
+
+    /// Commits the transaction only if the stored version of the entity with the given key is still `version`
+    pub fn check_version<T: Entity>(mut self, key: &Key, version: u64) -> Self {
+        let (pk, sk) = keys::<T>(key);
+        self.operations
+            .push(Operation::CheckVersion { pk, sk, version });
+        self
+    }
+
+    /// Returns operations of the transaction, fails if there are too many of them or if an entity is used twice
+    pub(super) fn into_operations(self) -> Result<Vec<Operation>, StorageErr> {
+        if self.operations.len() > MAX_TRANSACTION_OPERATIONS {
+            return Err(StorageErr::ValidationError(format!(
+                "Transaction has {} operations, maximum is {}",
+                self.operations.len(),
+                MAX_TRANSACTION_OPERATIONS
+            )));
+        }
+        let mut seen = HashSet::new();
+        for operation in &self.operations {
+            let (pk, sk) = operation.keys();
+            if !seen.insert((pk, sk)) {
+                return Err(StorageErr::ValidationError(format!(
+                    "Entity {} of {} is used in the transaction more than once",
+                    sk, pk
+                )));
+            }
+        }
+        Ok(self.operations)
+    }
+
+    fn put_item<T: Entity>(mut self, entity: &T, version: Option<u64>) -> Self {
+        let (pk, sk) = keys::<T>(entity.key());
+        let mut item = entity
+            .serialize(self.client.put_item())
+            .as_input()
+            .get_item()
+            .clone()
+            .unwrap_or_default();
+        item.insert("pk".to_string(), AttributeValue::S(pk.clone()));
+        item.insert("sk".to_string(), AttributeValue::S(sk.clone()));
+        if let Some(version) = version {
+            item.insert(
+                VERSION_ATTRIBUTE.to_string(),
+                AttributeValue::N((version + 1).to_string()),
+            );
+        }
+        self.operations.push(Operation::Put {
+            pk,
+            sk,
+            item,
+            version,
+        });
+        self
+    }
+}
+
+/// Returns partition and sort keys of the entity with the given key
+fn keys<T: Entity>(key: &Key) -> (String, String) {
+    (
+        key.user_id.as_str(),
+        format!("{}_{}", T::entity_type(), key.entity_id),
+    )
+}
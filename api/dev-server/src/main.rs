@@ -0,0 +1,277 @@
+//! Local development server which hosts all the API handlers in a single process. It accepts WebSocket
+//! connections and mimics AWS API Gateway: the route is selected by the `k` field of the JSON body
+//! (same as `$request.body.k` route selection expression), the matching handler is called with an API
+//! Gateway like event and the response is written back to the socket.
+//!
+//! Run it with `cargo run -p dev-server`, listening address can be changed with `DEV_SERVER_ADDR`
+
+use std::{
+    net::SocketAddr,
+    rc::Rc,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use api_core::{
+    common::connection::{ConnectHandler, DisconnectHandler},
+    lambda::{process_connection_event, HandlerRegistry},
+    routes::all_handlers,
+    storage::{storage_memory::MemoryStorage, Storage, GAME_DATA_TABLE},
+};
+use aws_lambda_events::apigw::{
+    ApiGatewayRequestIdentity, ApiGatewayWebsocketProxyRequest,
+    ApiGatewayWebsocketProxyRequestContext,
+};
+use futures::{SinkExt, StreamExt};
+use lambda_runtime::{tracing, Context, LambdaEvent};
+use serde_json::json;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::LocalSet,
+};
+use tokio_tungstenite::tungstenite::Message;
+use ulid::Ulid;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+const STAGE: &str = "dev";
+
+type Event = LambdaEvent<ApiGatewayWebsocketProxyRequest>;
+type Router = HandlerRegistry<MemoryStorage>;
+
+/// Creates an event similar to the one which API Gateway sends to lambdas for WebSocket events like
+/// CONNECT, MESSAGE or DISCONNECT
+fn websocket_event(
+    event_type: &str,
+    body: String,
+    connection_id: &str,
+    connected_at: i64,
+    addr: &SocketAddr,
+) -> Event {
+    let request_context = ApiGatewayWebsocketProxyRequestContext {
+        stage: Some(STAGE.to_string()),
+        request_id: Some(Ulid::new().to_string()),
+        identity: ApiGatewayRequestIdentity {
+            source_ip: Some(addr.ip().to_string()),
+            ..Default::default()
+        },
+        connected_at,
+        connection_id: Some(connection_id.to_string()),
+        event_type: Some(event_type.to_string()),
+        message_direction: Some("IN".to_string()),
+        request_time_epoch: now_ms(),
+        ..Default::default()
+    };
+    let request = ApiGatewayWebsocketProxyRequest {
+        body: Some(body),
+        request_context,
+        ..Default::default()
+    };
+    LambdaEvent::new(request, Context::default())
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+async fn handle_connection(
+    router: Rc<Router>,
+    storage: Arc<MemoryStorage>,
+    stream: TcpStream,
+    addr: SocketAddr,
+) {
+    let mut socket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            tracing::warn!("WebSocket handshake with {} failed: {}", addr, err);
+            return;
+        }
+    };
+    let connection_id = Ulid::new().to_string();
+    let connected_at = now_ms();
+    let event = websocket_event(
+        "CONNECT",
+        String::new(),
+        &connection_id,
+        connected_at,
+        &addr,
+    );
+    let response = process_connection_event(event, &ConnectHandler::new(storage.clone())).await;
+    if response["statusCode"] != 200 {
+        tracing::warn!("Connection {} from {} rejected", connection_id, addr);
+        let _ = socket.close(None).await;
+        return;
+    }
+    tracing::info!("Connected {} from {}", connection_id, addr);
+
+    let (mut writer, mut reader) = socket.split();
+    while let Some(message) = reader.next().await {
+        let body = match message {
+            Ok(Message::Text(body)) => body,
+            Ok(Message::Close(_)) => break,
+            Ok(_) => continue, // Ping/pong are handled by tungstenite and binary data is not supported by API Gateway
+            Err(err) => {
+                tracing::warn!("Connection {} failed: {}", connection_id, err);
+                break;
+            }
+        };
+        let event = websocket_event("MESSAGE", body, &connection_id, connected_at, &addr);
+        let request_id = event.payload.request_context.request_id.clone();
+        let response = match router.dispatch(event).await {
+            Some(response) => response,
+            None => {
+                // Same response API Gateway returns when no route matches and there is no $default route
+                tracing::warn!("No route found for a message from {}", connection_id);
+                json!({
+                    "message": "Forbidden",
+                    "connectionId": connection_id,
+                    "requestId": request_id,
+                })
+                .to_string()
+            }
+        };
+        if let Err(err) = writer.send(Message::Text(response)).await {
+            tracing::warn!("Cannot send a response to {}: {}", connection_id, err);
+            break;
+        }
+    }
+    let event = websocket_event(
+        "DISCONNECT",
+        String::new(),
+        &connection_id,
+        connected_at,
+        &addr,
+    );
+    process_connection_event(event, &DisconnectHandler::new(storage)).await;
+    tracing::info!("Disconnected {}", connection_id);
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), std::io::Error> {
+    tracing::init_default_subscriber();
+    let addr = std::env::var("DEV_SERVER_ADDR").unwrap_or_else(|_| DEFAULT_ADDR.to_string());
+    let listener = TcpListener::bind(&addr).await?;
+    tracing::info!("Dev server is listening on ws://{}", addr);
+
+    // Handler traits use async functions which futures are not `Send`, so all the connections
+    // are served on a single thread which is more than enough for the development
+    LocalSet::new()
+        .run_until(async move {
+            let storage = Arc::new(MemoryStorage::new(GAME_DATA_TABLE).await);
+            let router = Rc::new(all_handlers(storage.clone()));
+            loop {
+                let (stream, addr) = listener.accept().await?;
+                tokio::task::spawn_local(handle_connection(
+                    router.clone(),
+                    storage.clone(),
+                    stream,
+                    addr,
+                ));
+            }
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use api_core::{
+        datetime::Timestamp,
+        encryption,
+        messages::{
+            common::{
+                auth::{Challenge, ChallengeQuery},
+                ping::{Ping, ServerStatus},
+            },
+            game::decay::{Decay, DecayQuery},
+            ClientPlayerMessage, ClientPublicMessage,
+        },
+        server_error::ServerError,
+    };
+
+    use super::*;
+
+    async fn test_router() -> Router {
+        all_handlers(Arc::new(MemoryStorage::new(GAME_DATA_TABLE).await))
+    }
+
+    fn event(body: String) -> Event {
+        websocket_event(
+            "MESSAGE",
+            body,
+            "connection",
+            0,
+            &"127.0.0.1:1".parse().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn dispatch_public_message() {
+        let response = test_router()
+            .await
+            .dispatch(event(Ping {}.serialize(1).unwrap()))
+            .await
+            .unwrap();
+        let (_, request_id) = ServerStatus::deserialize(&response).unwrap();
+        assert_eq!(request_id, 1);
+    }
+
+    #[tokio::test]
+    async fn dispatch_player_message() {
+        let keys = encryption::generate_new_keys();
+        let body = DecayQuery {}
+            .serialize(
+                2,
+                &Timestamp::now(),
+                keys.public_key.as_ref().clone(),
+                keys.private_key.as_ref().clone(),
+            )
+            .unwrap();
+        let response = test_router().await.dispatch(event(body)).await.unwrap();
+        let (_, request_id) = Decay::deserialize(&response).unwrap();
+        assert_eq!(request_id, 2);
+    }
+
+    #[tokio::test]
+    async fn dispatch_connection_message() {
+        let storage = Arc::new(MemoryStorage::new(GAME_DATA_TABLE).await);
+        let mut connect = event(String::new());
+        connect.payload.request_context.event_type = Some("CONNECT".to_string());
+        process_connection_event(connect, &ConnectHandler::new(storage.clone())).await;
+        let response = all_handlers(storage)
+            .dispatch(event(ChallengeQuery {}.serialize(3).unwrap()))
+            .await
+            .unwrap();
+        let (_, request_id) = Challenge::deserialize(&response).unwrap();
+        assert_eq!(request_id, 3);
+    }
+
+    #[tokio::test]
+    async fn dispatch_bad_data() {
+        // Known route but broken payload is processed by the handler itself
+        let response = test_router()
+            .await
+            .dispatch(event(r#"{"k":"-.","v":""}"#.to_string()))
+            .await
+            .unwrap();
+        assert!(ServerError::deserialize(&response).is_ok());
+    }
+
+    #[tokio::test]
+    async fn dispatch_unknown_route() {
+        let router = test_router().await;
+        assert!(router
+            .dispatch(event("not json".to_string()))
+            .await
+            .is_none());
+        assert!(router
+            .dispatch(event(r#"{"v":""}"#.to_string()))
+            .await
+            .is_none());
+        assert!(router
+            .dispatch(event(r#"{"k":"zz","v":""}"#.to_string()))
+            .await
+            .is_none());
+    }
+}
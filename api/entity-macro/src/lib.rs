@@ -0,0 +1,159 @@
+//! Derive macros for `api-core` entities. Macros are internal to `api-core`, generated code refers to its items with
+//! `crate::storage` paths and expects `aws_sdk_dynamodb` dependency, so they cannot be used from other crates
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, ExprLit, Field, Fields, Ident, Lit, Meta};
+
+/// Procedural macro which implements `Entity` trait for a struct with named fields. Struct has to have a `key` field
+/// of `Key` type and `#[entity_type = "name"]` attribute, every other field is stored as a DynamoDB attribute with
+/// the same name and its type has to implement `Attribute` trait. `ServerTimestamp` fields marked with `#[ttl]` are
+/// stored in seconds as DynamoDB TTL requires
+#[proc_macro_derive(Entity, attributes(entity_type, ttl))]
+pub fn derive_entity(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    let struct_name_ident = &input.ident;
+    let entity_type = entity_type(&input);
+    let fields = struct_fields(&input);
+    if !fields
+        .iter()
+        .any(|field| field.ident.as_ref().is_some_and(|ident| ident == "key"))
+    {
+        panic!("Entity {} has no key field", struct_name_ident);
+    }
+    let (ttl_fields, fields): (Vec<_>, Vec<_>) = fields
+        .into_iter()
+        .filter(|field| field.ident.as_ref().is_some_and(|ident| ident != "key"))
+        .partition(|field| field.attrs.iter().any(|attr| attr.path().is_ident("ttl")));
+    let fields = field_names(&fields);
+    let ttl_fields = field_names(&ttl_fields);
+    let ttl_names: Vec<_> = ttl_fields.iter().map(|field| field.to_string()).collect();
+    let write_fields = write_fields(&fields);
+    let read_fields = read_fields(&fields);
+
+    let expanded = quote! {
+        impl crate::storage::Entity for #struct_name_ident {
+            fn entity_type() -> &'static str {
+                #entity_type
+            }
+
+            fn key(&self) -> &crate::storage::Key {
+                &self.key
+            }
+
+            fn serialize(
+                &self,
+                writer: aws_sdk_dynamodb::operation::put_item::builders::PutItemFluentBuilder,
+            ) -> aws_sdk_dynamodb::operation::put_item::builders::PutItemFluentBuilder {
+                let mut attributes = std::collections::HashMap::new();
+                #write_fields
+                #(crate::storage::attributes::write_ttl(&self.#ttl_fields, #ttl_names, &mut attributes);)*
+                attributes
+                    .into_iter()
+                    .fold(writer, |writer, (name, value)| writer.item(name, value))
+            }
+
+            fn deserialize(
+                key: crate::storage::Key,
+                data: std::collections::HashMap<String, aws_sdk_dynamodb::types::AttributeValue>,
+            ) -> Result<Self, crate::storage::StorageErr> {
+                let attributes = &data;
+                Ok(Self {
+                    key,
+                    #read_fields
+                    #(#ttl_fields: crate::storage::attributes::read_ttl(#ttl_names, attributes)?,)*
+                })
+            }
+        }
+    };
+    TokenStream::from(expanded)
+}
+
+/// Procedural macro which implements `Attribute` trait for a struct with named fields, so it can be nested in
+/// entities. Struct is stored as a map attribute where every field is stored with the same name
+#[proc_macro_derive(Attribute)]
+pub fn derive_attribute(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    let struct_name_ident = &input.ident;
+    let fields = field_names(&struct_fields(&input));
+    let write_fields = write_fields(&fields);
+    let read_fields = read_fields(&fields);
+
+    let expanded = quote! {
+        impl crate::storage::attributes::Attribute for #struct_name_ident {
+            fn to_attribute(&self) -> aws_sdk_dynamodb::types::AttributeValue {
+                let mut attributes = std::collections::HashMap::new();
+                #write_fields
+                aws_sdk_dynamodb::types::AttributeValue::M(attributes)
+            }
+
+            fn from_attribute(
+                name: &str,
+                value: &aws_sdk_dynamodb::types::AttributeValue,
+            ) -> Result<Self, crate::storage::StorageErr> {
+                let attributes = crate::storage::attributes::map_attribute(name, value)?;
+                let read = || -> Result<Self, crate::storage::StorageErr> {
+                    Ok(Self {
+                        #read_fields
+                    })
+                };
+                read().map_err(|err| crate::storage::attributes::nested_error(name, err))
+            }
+        }
+    };
+    TokenStream::from(expanded)
+}
+
+fn entity_type(input: &DeriveInput) -> String {
+    let attr = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("entity_type"))
+        .unwrap_or_else(|| panic!("Entity {} has no #[entity_type = \"name\"]", input.ident));
+    match &attr.meta {
+        Meta::NameValue(meta) => match &meta.value {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(value),
+                ..
+            }) => value.value(),
+            _ => panic!("Entity type of {} has to be a string", input.ident),
+        },
+        _ => panic!(
+            "Entity type of {} has to be #[entity_type = \"name\"]",
+            input.ident
+        ),
+    }
+}
+
+fn struct_fields(input: &DeriveInput) -> Vec<Field> {
+    match &input.data {
+        Data::Struct(data_struct) => match &data_struct.fields {
+            Fields::Named(fields) => fields.named.iter().cloned().collect(),
+            _ => panic!("{} has to be a struct with named fields", input.ident),
+        },
+        _ => panic!("{} has to be a struct", input.ident),
+    }
+}
+
+fn field_names(fields: &[Field]) -> Vec<Ident> {
+    fields
+        .iter()
+        .map(|field| field.ident.clone().expect("Named field has a name"))
+        .collect()
+}
+
+fn write_fields(fields: &[Ident]) -> proc_macro2::TokenStream {
+    let names = fields.iter().map(|field| field.to_string());
+    quote! {
+        #(crate::storage::attributes::Attribute::write(&self.#fields, #names, &mut attributes);)*
+    }
+}
+
+fn read_fields(fields: &[Ident]) -> proc_macro2::TokenStream {
+    let names = fields.iter().map(|field| field.to_string());
+    quote! {
+        #(#fields: crate::storage::attributes::Attribute::read(#names, attributes)?,)*
+    }
+}
@@ -0,0 +1,15 @@
+//! Lambda which accepts `Authenticate` message with a signed challenge and binds the connection to the player
+//! public key, so player messages can be sent over it unsigned
+
+use std::sync::Arc;
+
+use api_core::common::auth::AuthenticateHandler;
+use api_core::lambda::run_connection_message_handler;
+use api_core::storage::{storage_dynamodb::DynamoStorage, Storage, GAME_DATA_TABLE};
+use lambda_runtime::Error;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let storage = Arc::new(DynamoStorage::new(GAME_DATA_TABLE).await);
+    run_connection_message_handler(AuthenticateHandler::new(storage.clone()), storage).await
+}
@@ -0,0 +1,14 @@
+//! Lambda which accepts `ChallengeQuery` message and returns a new `Challenge` for the connection to authenticate
+
+use std::sync::Arc;
+
+use api_core::common::auth::ChallengeHandler;
+use api_core::lambda::run_connection_message_handler;
+use api_core::storage::{storage_dynamodb::DynamoStorage, Storage, GAME_DATA_TABLE};
+use lambda_runtime::Error;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let storage = Arc::new(DynamoStorage::new(GAME_DATA_TABLE).await);
+    run_connection_message_handler(ChallengeHandler::new(storage.clone()), storage).await
+}
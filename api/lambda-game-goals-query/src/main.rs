@@ -0,0 +1,15 @@
+//! Lambda which accepts `GoalsQuery` message and returns all the player's goals
+
+use std::sync::Arc;
+
+use api_core::game::goals::GoalsHandler;
+use api_core::lambda::run_player_handler;
+use api_core::messages::game::goals::GoalsQuery;
+use api_core::storage::{storage_dynamodb::DynamoStorage, Storage, GAME_DATA_TABLE};
+use lambda_runtime::Error;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let storage = Arc::new(DynamoStorage::new(GAME_DATA_TABLE).await);
+    run_player_handler::<GoalsQuery, _>(GoalsHandler::new(storage.clone()), storage).await
+}
@@ -0,0 +1,14 @@
+//! Lambda which accepts `Identity` message, stores it and returns the player's `IdentityState`
+
+use std::sync::Arc;
+
+use api_core::game::identity::IdentityHandler;
+use api_core::lambda::run_player_handler;
+use api_core::storage::{storage_dynamodb::DynamoStorage, Storage, GAME_DATA_TABLE};
+use lambda_runtime::Error;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let storage = Arc::new(DynamoStorage::new(GAME_DATA_TABLE).await);
+    run_player_handler(IdentityHandler::new(storage.clone()), storage).await
+}
@@ -0,0 +1,18 @@
+//! Lambda which accepts every client message and dispatches it to the handler registered for its tag
+
+use std::sync::Arc;
+
+use api_core::lambda::{lambda_middleware, run_router};
+use api_core::routes::all_handlers_with_middleware;
+use api_core::storage::{storage_dynamodb::DynamoStorage, Storage, GAME_DATA_TABLE};
+use lambda_runtime::Error;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let storage = Arc::new(DynamoStorage::new(GAME_DATA_TABLE).await);
+    run_router(&all_handlers_with_middleware(
+        storage.clone(),
+        lambda_middleware(storage),
+    ))
+    .await
+}
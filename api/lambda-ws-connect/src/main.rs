@@ -1,14 +1,14 @@
-use lambda_http::{run, service_fn, Error, Request, Response};
+//! Lambda which is called by API Gateway on `$connect` and stores a new connection
 
-async fn handler(_: Request) -> Result<Response<String>, Error> {
-    Response::builder()
-        .status(200)
-        .header("content-type", "text/plain")
-        .body("connect".to_string())
-        .map_err(Error::from)
-}
+use std::sync::Arc;
+
+use api_core::common::connection::ConnectHandler;
+use api_core::lambda::run_connection_handler;
+use api_core::storage::{storage_dynamodb::DynamoStorage, Storage, GAME_DATA_TABLE};
+use lambda_runtime::Error;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(service_fn(handler)).await
+    let storage = DynamoStorage::new(GAME_DATA_TABLE).await;
+    run_connection_handler(&ConnectHandler::new(Arc::new(storage))).await
 }
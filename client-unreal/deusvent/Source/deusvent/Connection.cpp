@@ -54,10 +54,18 @@ void UConnection::Init(const char *ServerAddress, const logic::Keys &PlayerKeys)
     Connection->OnMessage().AddLambda([this](const FString &Message) {
         UE_LOGFMT(LogConnection, Display, "Message received: {0}", Message);
         auto TagPrefix = Message.Mid(0, 2);
-        auto RequestIDPrefix = Message.Mid(2, 2);
-        auto MessageRequestId = logic::parse_request_id(TCHAR_TO_UTF8(*RequestIDPrefix));
+        if (logic::is_push_message(TCHAR_TO_UTF8(*Message))) {
+            // Here we would need to add processing of pushed message - warnings for now if any
+            UE_LOGFMT(LogConnection,
+                      Warning,
+                      "Unknown message tag for pushed message: Tag={0}",
+                      TagPrefix);
+            return;
+        }
+        // Request id takes 2 characters after the tag, or more for large ids - parse it from the rest of the message
+        auto MessageRequestId = logic::parse_request_id(TCHAR_TO_UTF8(*Message.Mid(2)));
         if (MessageRequestId == 0) {
-            // No request id available, so it's either pushed message or an error
+            // No request id available, so it's an error for the message which server couldn't parse
             if (Message.StartsWith(FString(logic::server_error_message_tag().c_str()))) {
                 auto Error = logic::ServerError::deserialize(TCHAR_TO_UTF8(*Message));
                 UE_LOGFMT(LogConnection,
@@ -65,10 +73,9 @@ void UConnection::Init(const char *ServerAddress, const logic::Keys &PlayerKeys)
                           "Server error: {0}",
                           FString(Error->debug_string().c_str()));
             } else {
-                // Here we would need to add processing of pushed message - warnings for now if any
                 UE_LOGFMT(LogConnection,
                           Warning,
-                          "Unknown message tag for pushed message: Tag={0}",
+                          "Unknown message without request id: Tag={0}",
                           TagPrefix);
             }
         } else {
@@ -128,10 +135,9 @@ void UConnection::Reconnect(float DelaySeconds) {
         false);
 }
 
-uint8 UConnection::NextRequestId() {
+uint32 UConnection::NextRequestId() {
     auto Val = this->RequestId.fetch_add(1);
     // 0 is a special request id which API may return when request_id cannot be parsed from the
-    // incoming message or when server message was pushed by the server and there is no
-    // corresponding request message. Skip it from generating to avoid any confusion
+    // incoming message. Skip it from generating to avoid any confusion
     return Val == 0 ? this->NextRequestId() : Val;
 }
@@ -30,18 +30,18 @@ class DEUSVENT_API UConnection : public UObject {
     void Reconnect(float DelaySeconds);
 
     // Request id to callbacks - to map server messages responses to sent client messages
-    TMap<uint8, TFunction<void(FString)>> Callbacks;
+    TMap<uint32, TFunction<void(FString)>> Callbacks;
 
     // Queue for all outgoing messages - contains serialized payload to be sent
     TMpscQueue<FString> OutgoingMessages;
 
     logic::Keys Keys;
     TSharedPtr<IWebSocket> Connection;
-    std::atomic<uint8> RequestId;
+    std::atomic<uint32> RequestId;
     FDateTime LastHealthCheck;
     FTimerHandle ReconnectTimerHandle;
 
-    uint8 NextRequestId();
+    uint32 NextRequestId();
 };
 
 template <typename ResponseType, typename MessageType>
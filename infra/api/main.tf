@@ -48,11 +48,23 @@ locals {
   // - route: API Gateway routing key, required
   // - iam_policies: Array of IAM policies to be attached to the lambda
   // - env_variables: Map of environment variables for the lambda
+  // Messages without a dedicated route are processed by the router lambda on the "$default" route, so new
+  // messages only need to be registered in `api_core::routes`
   lambdas = [
-    { name = "common-ping", route = "-." },
-    { name = "ws-connect", route = "$connect" },
-    { name = "ws-disconnect", route = "$disconnect" },
-    { name = "game-decay", route = "-/" },
+    { name = "common-ping", route = "-.", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "common-challenge", route = "-1", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "common-authenticate", route = "-2", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "ws-connect", route = "$connect", iam_policies = [var.storage-iam-writer] },
+    { name = "ws-disconnect", route = "$disconnect", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "game-decay", route = "-/", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "game-identity", route = "-0", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "game-identity-query", route = "-3", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "game-goal-create", route = "-4", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "game-goal-update", route = "-5", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "game-goal-complete", route = "-6", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "game-goal-archive", route = "-7", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "game-goals-query", route = "-8", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
+    { name = "router", route = "$default", iam_policies = [var.storage-iam-reader, var.storage-iam-writer] },
   ]
 }
 
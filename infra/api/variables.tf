@@ -1,3 +1,11 @@
 variable "certificate-arn" {
   description = "ARN of a certificate to be attached to custom domain"
 }
+
+variable "storage-iam-reader" {
+  description = "ARN of IAM policy for reading game data storage"
+}
+
+variable "storage-iam-writer" {
+  description = "ARN of IAM policy for writing game data storage"
+}
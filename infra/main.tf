@@ -21,8 +21,10 @@ module "domain" {
 }
 
 module "api" {
-  source          = "./api"
-  certificate-arn = module.domain.certificate_arn
+  source             = "./api"
+  certificate-arn    = module.domain.certificate_arn
+  storage-iam-reader = module.storage.iam_reader
+  storage-iam-writer = module.storage.iam_writer
 }
 
 module "storage" {
@@ -14,6 +14,12 @@ resource "aws_dynamodb_table" "game_data" {
     name = "sk"
     type = "S"
   }
+
+  // Short living entities like message nonces are removed automatically once expired
+  ttl {
+    attribute_name = "expires_at"
+    enabled        = true
+  }
 }
 
 resource "aws_iam_policy" "game_data_reader" {
@@ -43,6 +49,8 @@ resource "aws_iam_policy" "game_data_writer" {
           "dynamodb:UpdateItem",
           "dynamodb:DeleteItem",
           "dynamodb:BatchWriteItem",
+          # Transactions are authorized per item action, version checks are ConditionCheck items
+          "dynamodb:ConditionCheckItem",
         ]
         Effect   = "Allow"
         Resource = aws_dynamodb_table.game_data.arn
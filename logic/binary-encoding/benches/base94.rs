@@ -0,0 +1,32 @@
+//! Compares Base94 codecs on payloads of different sizes, run with `cargo bench -p binary-encoding`
+
+use binary_encoding::Base94Codec;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const PAYLOAD_SIZES: [usize; 4] = [1024, 8 * 1024, 32 * 1024, 128 * 1024];
+
+fn payload(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i * 31 + 7) as u8).collect()
+}
+
+fn base94(c: &mut Criterion) {
+    for codec in [Base94Codec::BigInt, Base94Codec::Chunked] {
+        let mut group = c.benchmark_group(format!("base94_{codec:?}"));
+        group.sample_size(10);
+        for size in PAYLOAD_SIZES {
+            let data = payload(size);
+            let encoded = codec.encode(&data);
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::new("encode", size), &data, |b, data| {
+                b.iter(|| codec.encode(data))
+            });
+            group.bench_with_input(BenchmarkId::new("decode", size), &encoded, |b, encoded| {
+                b.iter(|| codec.decode(encoded).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, base94);
+criterion_main!(benches);
@@ -8,10 +8,15 @@
 //! To support client message routing, we provide a way to encode numerical `u16` client message tags into a JSON-safe
 //! string with char set which allowed by API Gateway. In this encoding we are using the most efficient encoding possible
 //! while remaining valid UTF-8 and safe to use inside JSON field that is acceptable as route. Message tag are encoded as
-//! two bytes for any tag up to 4_356 which should be enough for our cases.
+//! two bytes for any tag up to 4_356 which should be enough for our cases. Request ids of server messages are encoded
+//! the same way, while large ones take 6 more characters after a reserved prefix.
 //!
 //! Additionally, there is functionality to encode an arbitrary array of bytes using Base94 encoding for entire server
 //! messages. Using the more common Base64 adds 33% space overhead, while this Base94 adds only 22%.
+//!
+//! Base94 comes in two versions described by `Base94Codec`. The original one treats the whole input as a single big
+//! integer which takes quadratic time in the input size. The chunked one splits input into 9 bytes blocks, each block
+//! is encoded into 11 characters independently (similar to Ascii85), so it takes linear time with the same overhead.
 
 /// All printable characters, excluding quote and slash so that the encoded string can be safely embedded in a JSON string
 const CHAR_SET_JSON_STRING: [u8; 94] = [
@@ -33,14 +38,86 @@ const CHAR_SET_API_GATEWAY_ROUTE: [u8; 66] = [
 /// Maximum possible message tag that we can encode
 pub const MAX_VALID_TAG: u16 = (CHAR_SET_API_GATEWAY_ROUTE.len() as u16).pow(2) - 1;
 
-/// How much request id takes in a serialized string
+/// How much request id takes in a serialized string, ids starting from `EXTENDED_REQUEST_ID` take
+/// `EXTENDED_REQUEST_ID_LEN` instead
 pub const REQUEST_ID_LEN: usize = 2;
 
+/// How much extended request id takes in a serialized string: reserved prefix followed by 6 characters which fit
+/// any `u32` value
+pub const EXTENDED_REQUEST_ID_LEN: usize = REQUEST_ID_LEN + 6;
+
+/// Reserved request id for messages pushed by the server without a client request. It's the largest two characters
+/// value, so shorter request ids never clash with it
+pub const PUSH_REQUEST_ID: u16 = MAX_VALID_TAG;
+
+/// Reserved two characters prefix of extended request ids, request ids below it are encoded in two characters the
+/// same way as message tags, so headers of the short ids are unchanged
+pub const EXTENDED_REQUEST_ID: u16 = MAX_VALID_TAG - 1;
+
+/// Size of a full block of the chunked Base94 encoding in bytes
+const BASE94_BLOCK_SIZE: usize = 9;
+
+/// Number of characters for every block size of the chunked Base94 encoding, it's the smallest N where
+/// 94^N >= 256^block_size. Full block takes 11 characters which gives the same 22% overhead as plain Base94
+const BASE94_BLOCK_CHARS: [usize; BASE94_BLOCK_SIZE + 1] = [0, 2, 3, 4, 5, 7, 8, 9, 10, 11];
+
+/// Reverse lookup table for `CHAR_SET_JSON_STRING`, u8::MAX marks characters outside of the char set
+const CHAR_SET_JSON_STRING_INDEX: [u8; 256] = {
+    let mut index = [u8::MAX; 256];
+    let mut i = 0;
+    while i < CHAR_SET_JSON_STRING.len() {
+        index[CHAR_SET_JSON_STRING[i] as usize] = i as u8;
+        i += 1;
+    }
+    index
+};
+
 #[derive(Debug)]
 pub enum EncodingError {
     BadData(&'static str),
 }
 
+/// Version of the Base94 encoding, both versions use the same JSON safe char set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Base94Codec {
+    /// Whole input is encoded as a single big integer, takes quadratic time in the input size
+    BigInt = 1,
+    /// Input is encoded by fixed size blocks, takes linear time in the input size
+    Chunked = 2,
+}
+
+impl Base94Codec {
+    /// Returns version number of the codec, messages carry it so decoders know which codec to use
+    pub fn version(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns codec with the given version number, if there is one
+    pub fn from_version(version: u8) -> Option<Self> {
+        match version {
+            1 => Some(Base94Codec::BigInt),
+            2 => Some(Base94Codec::Chunked),
+            _ => None,
+        }
+    }
+
+    /// Encode binary data with this version of Base94 encoding
+    pub fn encode(self, data: &[u8]) -> String {
+        match self {
+            Base94Codec::BigInt => encode_base94(data),
+            Base94Codec::Chunked => encode_base94_chunked(data),
+        }
+    }
+
+    /// Decode data encoded with this version of Base94 encoding back into bytes
+    pub fn decode(self, encoded: &str) -> Result<Vec<u8>, EncodingError> {
+        match self {
+            Base94Codec::BigInt => decode_base94(encoded),
+            Base94Codec::Chunked => decode_base94_chunked(encoded),
+        }
+    }
+}
+
 /// Encode binary data using Base94 encoding
 pub fn encode_base94(data: &[u8]) -> String {
     base_x::encode(CHAR_SET_JSON_STRING.as_ref(), data)
@@ -53,6 +130,51 @@ pub fn decode_base94(encoded: &str) -> Result<Vec<u8>, EncodingError> {
     Ok(data)
 }
 
+/// Encode binary data using chunked Base94 encoding
+pub fn encode_base94_chunked(data: &[u8]) -> String {
+    let base = CHAR_SET_JSON_STRING.len() as u128;
+    let mut output = Vec::with_capacity(data.len().div_ceil(BASE94_BLOCK_SIZE) * 11);
+    for block in data.chunks(BASE94_BLOCK_SIZE) {
+        let mut value = block
+            .iter()
+            .fold(0u128, |value, &byte| (value << 8) | byte as u128);
+        let start = output.len();
+        output.resize(start + BASE94_BLOCK_CHARS[block.len()], 0);
+        for c in output[start..].iter_mut().rev() {
+            *c = CHAR_SET_JSON_STRING[(value % base) as usize];
+            value /= base;
+        }
+    }
+    String::from_utf8(output).expect("Our custom charset should always be convertible to String")
+}
+
+/// Decode data from a chunked Base94 string back into bytes
+pub fn decode_base94_chunked(encoded: &str) -> Result<Vec<u8>, EncodingError> {
+    let base = CHAR_SET_JSON_STRING.len() as u128;
+    let full_block_chars = BASE94_BLOCK_CHARS[BASE94_BLOCK_SIZE];
+    let mut output = Vec::with_capacity(encoded.len() / full_block_chars * BASE94_BLOCK_SIZE + 8);
+    for chars in encoded.as_bytes().chunks(full_block_chars) {
+        let block_size = BASE94_BLOCK_CHARS
+            .iter()
+            .position(|&len| len == chars.len())
+            .filter(|&size| size > 0)
+            .ok_or(EncodingError::BadData("Invalid length of the last block"))?;
+        let mut value = 0u128;
+        for &c in chars {
+            let pos = CHAR_SET_JSON_STRING_INDEX[c as usize];
+            if pos == u8::MAX {
+                return Err(EncodingError::BadData("Invalid byte in encoded string"));
+            }
+            value = value * base + pos as u128;
+        }
+        if value >> (block_size * 8) != 0 {
+            return Err(EncodingError::BadData("Block value is out of range"));
+        }
+        output.extend_from_slice(&value.to_be_bytes()[16 - block_size..]);
+    }
+    Ok(output)
+}
+
 /// Encodes a message tag (u16) to a string of exactly 2 characters which can be used as a routing key in messages
 /// sent to API Gateway. Panics if the tag exceeds MAX_VALID_TAG.
 pub fn encode_message_tag(mut tag: u16) -> String {
@@ -79,17 +201,63 @@ pub fn encode_message_tag(mut tag: u16) -> String {
     String::from_utf8(result).expect("Our custom charset should always be convertible to String")
 }
 
-/// Encode request id to the string of 2 bytes
-pub fn encode_request_id(id: u8) -> String {
+/// Encode request id to the string of `REQUEST_ID_LEN` bytes, or `EXTENDED_REQUEST_ID_LEN` bytes for large ids
+pub fn encode_request_id(id: u32) -> String {
     // We can't encode u8 as a valid UTF8 string if we want to represent it as a 1 byte length string
     // So we simply encode it in the same way as tag which is guaranteed to fit into 2 bytes
-    encode_message_tag(id as u16)
+    if id < EXTENDED_REQUEST_ID as u32 {
+        return encode_message_tag(id as u16);
+    }
+    let base = CHAR_SET_API_GATEWAY_ROUTE.len() as u32;
+    let mut output = encode_message_tag(EXTENDED_REQUEST_ID).into_bytes();
+    let mut digits = [CHAR_SET_API_GATEWAY_ROUTE[0]; EXTENDED_REQUEST_ID_LEN - REQUEST_ID_LEN];
+    let mut value = id;
+    for digit in digits.iter_mut().rev() {
+        *digit = CHAR_SET_API_GATEWAY_ROUTE[(value % base) as usize];
+        value /= base;
+    }
+    output.extend_from_slice(&digits);
+    String::from_utf8(output).expect("Our custom charset should always be convertible to String")
+}
+
+/// Decode request id at the start of the data back to the original value, returns the value and how many bytes
+/// it takes. Data after the request id is ignored
+pub fn decode_request_id(data: &[u8]) -> Result<(u32, usize), EncodingError> {
+    let prefix = data
+        .get(..REQUEST_ID_LEN)
+        .ok_or(EncodingError::BadData("Not valid request id"))?;
+    match decode_message_tag(prefix)? {
+        PUSH_REQUEST_ID => Err(EncodingError::BadData("Not valid request id")),
+        EXTENDED_REQUEST_ID => {
+            let digits = data
+                .get(REQUEST_ID_LEN..EXTENDED_REQUEST_ID_LEN)
+                .ok_or(EncodingError::BadData("Extended request id is too short"))?;
+            let base = CHAR_SET_API_GATEWAY_ROUTE.len() as u64;
+            let mut value = 0u64;
+            for c in digits {
+                let pos = CHAR_SET_API_GATEWAY_ROUTE
+                    .iter()
+                    .position(|&x| x == *c)
+                    .ok_or(EncodingError::BadData("Invalid character in request id"))?;
+                value = value * base + pos as u64;
+            }
+            let id =
+                u32::try_from(value).map_err(|_| EncodingError::BadData("Not valid request id"))?;
+            Ok((id, EXTENDED_REQUEST_ID_LEN))
+        }
+        id => Ok((id as u32, REQUEST_ID_LEN)),
+    }
+}
+
+/// Encode reserved push request id to the string of 2 bytes
+pub fn encode_push_request_id() -> String {
+    encode_message_tag(PUSH_REQUEST_ID)
 }
 
-/// Decode request id back to the original value
-pub fn decode_request_id(data: &[u8]) -> Result<u8, EncodingError> {
-    let v = decode_message_tag(data)?;
-    u8::try_from(v).map_err(|_| EncodingError::BadData("Not valid request id"))
+/// Returns true if encoded request id at the start of the data is the reserved push request id
+pub fn is_push_request_id(data: &[u8]) -> bool {
+    data.get(..REQUEST_ID_LEN)
+        .is_some_and(|prefix| matches!(decode_message_tag(prefix), Ok(PUSH_REQUEST_ID)))
 }
 
 /// Decodes encoded tag string back into its original tag value (u16). Expects a string of exactly 2
@@ -234,13 +402,64 @@ mod tests {
         assert_eq!(overhead_base94, 22);
     }
 
+    #[test]
+    fn test_chunked_encoding() {
+        for len in 0..=100 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 + len) as u8).collect();
+            for codec in [Base94Codec::BigInt, Base94Codec::Chunked] {
+                let encoded = codec.encode(&data);
+                assert_eq!(codec.decode(&encoded).unwrap(), data);
+                assert_eq!(Base94Codec::from_version(codec.version()), Some(codec));
+            }
+            // Corner values of every block have to survive the round trip as well
+            for byte in [0, u8::MAX] {
+                let data = vec![byte; len];
+                let encoded = encode_base94_chunked(&data);
+                assert_eq!(decode_base94_chunked(&encoded).unwrap(), data);
+            }
+        }
+
+        // Full blocks have the same 22% overhead as plain Base94
+        assert_eq!(encode_base94_chunked(&[7; 9 * 100]).len(), 11 * 100);
+
+        // Block sizes which don't exist, unknown characters and values out of range are rejected
+        assert!(decode_base94_chunked("a").is_err());
+        assert!(decode_base94_chunked("abcdef").is_err());
+        assert!(decode_base94_chunked("a\"\"").is_err());
+        assert!(decode_base94_chunked("\u{7f}\u{7f}").is_err());
+        assert!(decode_base94_chunked(&encode_base94_chunked(&[u8::MAX])).is_ok());
+    }
+
     #[test]
     fn request_id_encode_decode() {
-        for i in 0..=u8::MAX {
+        for i in (0..EXTENDED_REQUEST_ID as u32).chain([4354, 4355, 1 << 20, u32::MAX]) {
             let encoded = encode_request_id(i);
-            assert_eq!(encoded.len(), REQUEST_ID_LEN);
-            let decoded = decode_request_id(encoded.as_bytes()).unwrap();
-            assert_eq!(decoded, i);
+            let expected_len = if i < EXTENDED_REQUEST_ID as u32 {
+                REQUEST_ID_LEN
+            } else {
+                EXTENDED_REQUEST_ID_LEN
+            };
+            assert_eq!(encoded.len(), expected_len);
+            let decoded = decode_request_id(format!("{}payload", encoded).as_bytes()).unwrap();
+            assert_eq!(decoded, (i, expected_len));
+            assert!(!is_push_request_id(encoded.as_bytes()));
         }
+
+        // Short ids keep the two characters tag encoding
+        assert_eq!(encode_request_id(1), encode_message_tag(1));
+        assert!(decode_request_id(encode_message_tag(EXTENDED_REQUEST_ID).as_bytes()).is_err());
+        assert!(decode_request_id(b"-").is_err());
+        assert!(decode_request_id(
+            format!("{}zzzzzz", encode_message_tag(EXTENDED_REQUEST_ID)).as_bytes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn push_request_id_encode_decode() {
+        let encoded = encode_push_request_id();
+        assert_eq!(encoded.len(), REQUEST_ID_LEN);
+        assert!(is_push_request_id(encoded.as_bytes()));
+        assert!(decode_request_id(encoded.as_bytes()).is_err());
     }
 }
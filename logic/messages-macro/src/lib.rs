@@ -1,8 +1,11 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt};
+use quote::{format_ident, quote};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, Data, DeriveInput, Fields, Ident, LitInt, Token, Type,
+};
 
 use lazy_static::lazy_static;
 use std::collections::HashMap;
@@ -23,14 +26,190 @@ fn lock_mutex<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
     }
 }
 
-/// Procedural macros that creates a custom serialization logic for the given public client message
+/// Arguments of client message macros in a form of `(TAG, response = ServerMessageType)` or
+/// `(TAG, response = ServerMessageType, tagged)` for messages with tagged fields encoding
+struct ClientMessageArgs {
+    message_tag: u16,
+    response: Type,
+    tagged: bool,
+}
+
+impl Parse for ClientMessageArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let message_tag = input.parse::<LitInt>()?.base10_parse::<u16>()?;
+        input.parse::<Token![,]>()?;
+        let key: Ident = input.parse()?;
+        if key != "response" {
+            return Err(syn::Error::new(key.span(), "Expected `response = Type`"));
+        }
+        input.parse::<Token![=]>()?;
+        let response = input.parse()?;
+        Ok(Self {
+            message_tag,
+            response,
+            tagged: parse_tagged(input)?,
+        })
+    }
+}
+
+/// Arguments of server message macro in a form of `(TAG)` or `(TAG, tagged)` for messages with tagged fields encoding
+struct ServerMessageArgs {
+    message_tag: u16,
+    tagged: bool,
+}
+
+impl Parse for ServerMessageArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let message_tag = input.parse::<LitInt>()?.base10_parse::<u16>()?;
+        Ok(Self {
+            message_tag,
+            tagged: parse_tagged(input)?,
+        })
+    }
+}
+
+/// Parses optional trailing `, tagged` argument
+fn parse_tagged(input: ParseStream) -> syn::Result<bool> {
+    if input.is_empty() {
+        return Ok(false);
+    }
+    input.parse::<Token![,]>()?;
+    let key: Ident = input.parse()?;
+    if key != "tagged" {
+        return Err(syn::Error::new(key.span(), "Expected `tagged`"));
+    }
+    Ok(true)
+}
+
+/// Arguments of the field attribute of tagged messages in a form of `#[field(ID)]` or `#[field(ID, default)]` for
+/// fields which fall back to their default value when missing
+struct FieldArgs {
+    field_id: u16,
+    default: bool,
+}
+
+impl Parse for FieldArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let field_id = input.parse::<LitInt>()?.base10_parse::<u16>()?;
+        if field_id == 0 {
+            return Err(syn::Error::new(input.span(), "Field id 0 is reserved"));
+        }
+        let default = !input.is_empty();
+        if default {
+            input.parse::<Token![,]>()?;
+            let key: Ident = input.parse()?;
+            if key != "default" {
+                return Err(syn::Error::new(key.span(), "Expected `default`"));
+            }
+        }
+        Ok(Self { field_id, default })
+    }
+}
+
+/// Returns bincode encoding of the message. Plain messages derive bincode traits, while tagged ones get the tagged
+/// fields encoding implemented and their `#[field]` attributes are removed from the struct
+fn message_encoding(
+    input: &mut DeriveInput,
+    tagged: bool,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    if !tagged {
+        return (
+            quote! { #[derive(bincode::Decode, bincode::Encode)] },
+            quote! {},
+        );
+    }
+    let struct_name_ident = input.ident.clone();
+    let fields = match &mut input.data {
+        Data::Struct(data_struct) => match &mut data_struct.fields {
+            Fields::Named(fields) => fields,
+            _ => panic!("Tagged messages can only be structs with named fields"),
+        },
+        _ => panic!("Tagged messages can only be structs"),
+    };
+    let mut field_ids = HashMap::new();
+    let mut encode_fields = vec![];
+    let mut decode_vars = vec![];
+    let mut decode_arms = vec![];
+    let mut decode_values = vec![];
+    for field in fields.named.iter_mut() {
+        let field_ident = field.ident.clone().expect("Named field has a name");
+        let field_name = field_ident.to_string();
+        let position = field
+            .attrs
+            .iter()
+            .position(|attr| attr.path().is_ident("field"))
+            .unwrap_or_else(|| {
+                panic!(
+                    "Field {} of a tagged message has no #[field(ID)]",
+                    field_name
+                )
+            });
+        let FieldArgs { field_id, default } = field
+            .attrs
+            .remove(position)
+            .parse_args()
+            .unwrap_or_else(|err| panic!("Bad #[field] attribute of {}: {}", field_name, err));
+        if let Some(existing_name) = field_ids.insert(field_id, field_name.clone()) {
+            panic!(
+                "Duplicate field id={} for fields {} and {} of {}",
+                field_id, field_name, existing_name, struct_name_ident
+            );
+        }
+        let var = format_ident!("field_{}", field_ident);
+        encode_fields.push(quote! {
+            crate::messages::tagged::encode_field(encoder, #field_id, &self.#field_ident)?;
+        });
+        decode_vars.push(quote! { let mut #var = None; });
+        decode_arms.push(quote! {
+            #field_id => #var = Some(crate::messages::tagged::decode_field_value(decoder, len)?),
+        });
+        decode_values.push(if default {
+            quote! { #field_ident: #var.unwrap_or_default(), }
+        } else {
+            quote! {
+                #field_ident: #var.ok_or_else(|| crate::messages::tagged::missing_field(#field_name, #field_id))?,
+            }
+        });
+    }
+    let impls = quote! {
+        impl bincode::Encode for #struct_name_ident {
+            fn encode<E: bincode::enc::Encoder>(&self, encoder: &mut E) -> Result<(), bincode::error::EncodeError> {
+                #(#encode_fields)*
+                crate::messages::tagged::encode_end(encoder)
+            }
+        }
+
+        impl bincode::Decode for #struct_name_ident {
+            fn decode<D: bincode::de::Decoder>(decoder: &mut D) -> Result<Self, bincode::error::DecodeError> {
+                #(#decode_vars)*
+                while let Some((field_id, len)) = crate::messages::tagged::decode_field_header(decoder)? {
+                    match field_id {
+                        #(#decode_arms)*
+                        _ => crate::messages::tagged::skip_field(decoder, len)?,
+                    }
+                }
+                Ok(Self {
+                    #(#decode_values)*
+                })
+            }
+        }
+
+        bincode::impl_borrow_decode!(#struct_name_ident);
+    };
+    (quote! {}, impls)
+}
+
+/// Procedural macros that creates a custom serialization logic for the given public client message, `response` is
+/// the server message which handlers reply with. Optional `tagged` argument enables tagged fields encoding
 #[proc_macro_attribute]
 pub fn client_public_message(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let message_tag_lit = parse_macro_input!(attr as LitInt);
-    let message_tag = message_tag_lit
-        .base10_parse::<u16>()
-        .expect("Message tag has to be a u16 number");
-    let input = parse_macro_input!(item as DeriveInput);
+    let ClientMessageArgs {
+        message_tag,
+        response,
+        tagged,
+    } = parse_macro_input!(attr as ClientMessageArgs);
+    let mut input = parse_macro_input!(item as DeriveInput);
+    let (encoding_derive, encoding_impls) = message_encoding(&mut input, tagged);
     let struct_name_ident = &input.ident;
     let struct_name = struct_name_ident.to_string();
     let mut message_tags = lock_mutex(&CLIENT_MESSAGE_TAGS);
@@ -51,20 +230,25 @@ pub fn client_public_message(attr: TokenStream, item: TokenStream) -> TokenStrea
 
     let expanded = quote! {
         #[doc = concat!("Message tag = ", #message_tag_encoded)]
-        #[derive(std::cmp::PartialEq, std::fmt::Debug, bincode::Decode, bincode::Encode, uniffi::Object, Clone)]
+        #[derive(std::cmp::PartialEq, std::fmt::Debug, uniffi::Object, Clone)]
+        #encoding_derive
         #input
 
+        #encoding_impls
+
         #[cfg(feature = "server")]
         impl crate::messages::ClientPublicMessage for #struct_name_ident {
+            type Response = #response;
+
             #[doc = "Serialize underlying message to string"]
-            fn serialize(&self, request_id: u8) -> Result<String, crate::messages::serializers::SerializationError> {
+            fn serialize(&self, request_id: u32) -> Result<String, crate::messages::serializers::SerializationError> {
                 crate::messages::serializers::ClientPublicMessage::serialize(&self, #message_tag, request_id)
             }
 
             #[doc = "Deserialize string to the underlying message type"]
             #[uniffi::constructor]
-            fn deserialize(data: String) -> Result<(Self, u8), crate::messages::serializers::SerializationError> {
-                let data: (#struct_name_ident, u8) = crate::messages::serializers::ClientPublicMessage::deserialize(&data, #message_tag)?;
+            fn deserialize(data: String) -> Result<(Self, u32), crate::messages::serializers::SerializationError> {
+                let data: (#struct_name_ident, u32) = crate::messages::serializers::ClientPublicMessage::deserialize(&data, #message_tag)?;
                 Ok(data)
             }
 
@@ -78,7 +262,7 @@ pub fn client_public_message(attr: TokenStream, item: TokenStream) -> TokenStrea
         #[uniffi::export]
         impl #struct_name_ident {
             #[doc = "Serialize underlying message to string"]
-            pub fn serialize(&self, request_id: u8) -> Result<String, crate::messages::serializers::SerializationError> {
+            pub fn serialize(&self, request_id: u32) -> Result<String, crate::messages::serializers::SerializationError> {
                 crate::messages::serializers::ClientPublicMessage::serialize(&self, #message_tag, request_id)
             }
 
@@ -91,14 +275,17 @@ pub fn client_public_message(attr: TokenStream, item: TokenStream) -> TokenStrea
     TokenStream::from(expanded)
 }
 
-/// Procedural macros that creates a custom serialization logic for the given player client message
+/// Procedural macros that creates a custom serialization logic for the given player client message, `response` is
+/// the server message which handlers reply with. Optional `tagged` argument enables tagged fields encoding
 #[proc_macro_attribute]
 pub fn client_player_message(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let message_tag_lit = parse_macro_input!(attr as LitInt);
-    let message_tag = message_tag_lit
-        .base10_parse::<u16>()
-        .expect("Message tag has to be a u16 number");
-    let input = parse_macro_input!(item as DeriveInput);
+    let ClientMessageArgs {
+        message_tag,
+        response,
+        tagged,
+    } = parse_macro_input!(attr as ClientMessageArgs);
+    let mut input = parse_macro_input!(item as DeriveInput);
+    let (encoding_derive, encoding_impls) = message_encoding(&mut input, tagged);
     let struct_name_ident = &input.ident;
     let struct_name = struct_name_ident.to_string();
     let mut message_tags = lock_mutex(&CLIENT_MESSAGE_TAGS);
@@ -119,22 +306,43 @@ pub fn client_player_message(attr: TokenStream, item: TokenStream) -> TokenStrea
 
     let expanded = quote! {
         #[doc = concat!("Message tag = ", #message_tag_encoded)]
-        #[derive(std::cmp::PartialEq, std::fmt::Debug, bincode::Decode, bincode::Encode, uniffi::Object, Clone)]
+        #[derive(std::cmp::PartialEq, std::fmt::Debug, uniffi::Object, Clone)]
+        #encoding_derive
         #input
 
+        #encoding_impls
+
         #[cfg(feature = "server")]
         impl crate::messages::ClientPlayerMessage for #struct_name_ident {
-            #[doc = "Serialize underlying message to string which will include player public key and will be signed to proof it's validity"]
-            fn serialize(&self, request_id: u8, public_key: crate::encryption::PublicKey, private_key: crate::encryption::PrivateKey) -> Result<String, crate::messages::serializers::SerializationError> {
-                crate::messages::serializers::ClientPlayerMessage::serialize(&self, #message_tag, request_id, &public_key, &private_key)
+            type Response = #response;
+
+            #[doc = "Serialize underlying message to string which will include player public key, timestamp and nonce and will be signed to proof it's validity"]
+            fn serialize(&self, request_id: u32, timestamp: &crate::datetime::Timestamp, public_key: crate::encryption::PublicKey, private_key: crate::encryption::PrivateKey) -> Result<String, crate::messages::serializers::SerializationError> {
+                crate::messages::serializers::ClientPlayerMessage::serialize(&self, #message_tag, request_id, timestamp, &public_key, &private_key)
+            }
+
+            #[doc = "Deserialize string to the underlying message type and verified signature. Returns error if signature is not valid or message is too old"]
+            fn deserialize(data: String, now: &crate::datetime::Timestamp, max_age: &crate::datetime::Duration) -> Result<(Self, crate::messages::serializers::MessageSignature, u32), crate::messages::serializers::SerializationError> {
+                let data: (#struct_name_ident, crate::messages::serializers::MessageSignature, u32) = crate::messages::serializers::ClientPlayerMessage::deserialize(&data, #message_tag, now, max_age)?;
+                Ok(data)
             }
 
-            #[doc = "Deserialize string to the underlying message type and included public_key as a string. Returns error if signature is not valid"]
-            fn deserialize(data: String) -> Result<(Self, std::sync::Arc<crate::encryption::PublicKey>, u8), crate::messages::serializers::SerializationError> {
-                let data: (#struct_name_ident, std::sync::Arc<crate::encryption::PublicKey>, u8) = crate::messages::serializers::ClientPlayerMessage::deserialize(&data, #message_tag)?;
+            #[doc = "Serialize underlying message to string without public key and signature, connection it's sent over has to be authenticated"]
+            fn serialize_unsigned(&self, request_id: u32) -> Result<String, crate::messages::serializers::SerializationError> {
+                crate::messages::serializers::ClientPlayerMessage::serialize_unsigned(&self, #message_tag, request_id)
+            }
+
+            #[doc = "Deserialize unsigned string to the underlying message type"]
+            fn deserialize_unsigned(data: String) -> Result<(Self, u32), crate::messages::serializers::SerializationError> {
+                let data: (#struct_name_ident, u32) = crate::messages::serializers::ClientPlayerMessage::deserialize_unsigned(&data, #message_tag)?;
                 Ok(data)
             }
 
+            #[doc = "Return true if the string is an unsigned message"]
+            fn is_unsigned(data: &str) -> bool {
+                crate::messages::serializers::ClientPlayerMessage::is_unsigned(data, #message_tag)
+            }
+
             #[doc = "Return message tag"]
             fn tag() -> u16 {
                 #message_tag
@@ -145,8 +353,13 @@ pub fn client_player_message(attr: TokenStream, item: TokenStream) -> TokenStrea
         #[uniffi::export]
         impl #struct_name_ident {
             #[doc = "Serialize underlying message to string which will include player public key and will be signed to proof it's validity"]
-            pub fn serialize(&self, request_id: u8, keys: crate::encryption::Keys) -> Result<String, crate::messages::serializers::SerializationError> {
-                crate::messages::serializers::ClientPlayerMessage::serialize(&self, #message_tag, request_id, keys.public_key.as_ref(), keys.private_key.as_ref())
+            pub fn serialize(&self, request_id: u32, timestamp: &crate::datetime::SyncedTimestamp, keys: crate::encryption::Keys) -> Result<String, crate::messages::serializers::SerializationError> {
+                crate::messages::serializers::ClientPlayerMessage::serialize(&self, #message_tag, request_id, &timestamp.now(), keys.public_key.as_ref(), keys.private_key.as_ref())
+            }
+
+            #[doc = "Serialize underlying message to string without public key and signature, connection it's sent over has to be authenticated"]
+            pub fn serialize_unsigned(&self, request_id: u32) -> Result<String, crate::messages::serializers::SerializationError> {
+                crate::messages::serializers::ClientPlayerMessage::serialize_unsigned(&self, #message_tag, request_id)
             }
 
             #[doc = "Easy way to quickly output underlying message to the string for debugging purposes"]
@@ -158,14 +371,16 @@ pub fn client_player_message(attr: TokenStream, item: TokenStream) -> TokenStrea
     TokenStream::from(expanded)
 }
 
-// Procedural macros that creates a custom serialization logic for the given server message
+// Procedural macros that creates a custom serialization logic for the given server message, optional `tagged`
+// argument enables tagged fields encoding
 #[proc_macro_attribute]
 pub fn server_message(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let message_tag_lit = parse_macro_input!(attr as LitInt);
-    let message_tag = message_tag_lit
-        .base10_parse::<u16>()
-        .expect("Message tag has to be a u16 number");
-    let input = parse_macro_input!(item as DeriveInput);
+    let ServerMessageArgs {
+        message_tag,
+        tagged,
+    } = parse_macro_input!(attr as ServerMessageArgs);
+    let mut input = parse_macro_input!(item as DeriveInput);
+    let (encoding_derive, encoding_impls) = message_encoding(&mut input, tagged);
     let struct_name_ident = &input.ident;
     let struct_name = struct_name_ident.to_string();
     let mut message_tags = lock_mutex(&SERVER_MESSAGE_TAGS);
@@ -219,20 +434,54 @@ pub fn server_message(attr: TokenStream, item: TokenStream) -> TokenStream {
 
     let expanded = quote! {
         #[doc = concat!("Message tag = ", #message_tag_encoded)]
-        #[derive(std::cmp::PartialEq, std::fmt::Debug, bincode::Decode, bincode::Encode, uniffi::Object, Clone)]
+        #[derive(std::cmp::PartialEq, std::fmt::Debug, uniffi::Object, Clone)]
+        #encoding_derive
         #input
 
+        #encoding_impls
+
         // For server we can have serializing logic on a struct itself, it makes things easier to work with
         #[cfg(feature = "server")]
         impl #struct_name_ident {
             #[doc = "Serialize message to string"]
-            pub fn serialize(&self, request_id: u8) -> Result<String, crate::messages::serializers::SerializationError> {
+            pub fn serialize(&self, request_id: u32) -> Result<String, crate::messages::serializers::SerializationError> {
                 crate::messages::serializers::ServerMessage::serialize(self, #message_tag, request_id)
             }
             #[doc = "Deserialize string back to the message type"]
-            pub fn deserialize(data: &str) -> Result<(Self, u8), crate::messages::serializers::SerializationError> {
+            pub fn deserialize(data: &str) -> Result<(Self, u32), crate::messages::serializers::SerializationError> {
                 crate::messages::serializers::ServerMessage::deserialize(data, #message_tag)
             }
+            #[doc = "Serialize message to string as a pushed message without request_id"]
+            pub fn serialize_push(&self) -> Result<String, crate::messages::serializers::SerializationError> {
+                crate::messages::serializers::ServerMessage::serialize_push(self, #message_tag)
+            }
+            #[doc = "Deserialize string of a pushed message back to the message type"]
+            pub fn deserialize_push(data: &str) -> Result<Self, crate::messages::serializers::SerializationError> {
+                crate::messages::serializers::ServerMessage::deserialize_push(data, #message_tag)
+            }
+        }
+
+        #[cfg(feature = "server")]
+        impl crate::messages::ServerMessage for #struct_name_ident {
+            #[doc = "Return message tag"]
+            fn tag() -> u16 {
+                #message_tag
+            }
+
+            #[doc = "Return true if message uses tagged fields encoding"]
+            fn is_tagged() -> bool {
+                #tagged
+            }
+
+            #[doc = "Serialize message to string"]
+            fn serialize(&self, request_id: u32) -> Result<String, crate::messages::serializers::SerializationError> {
+                crate::messages::serializers::ServerMessage::serialize(self, #message_tag, request_id)
+            }
+
+            #[doc = "Serialize message to string as a pushed message without request_id"]
+            fn serialize_push(&self) -> Result<String, crate::messages::serializers::SerializationError> {
+                crate::messages::serializers::ServerMessage::serialize_push(self, #message_tag)
+            }
         }
 
         // To avoid conflict with duplicated "serialize" function enable it only when server is turned off
@@ -240,10 +489,10 @@ pub fn server_message(attr: TokenStream, item: TokenStream) -> TokenStream {
         #[cfg(all(feature = "uniffi", not(feature = "server")))]
         #[uniffi::export]
         impl #struct_name_ident {
-            #[doc = "Deserialize string to the underlying message type"]
+            #[doc = "Deserialize string to the underlying message type, works for both responses and pushed messages"]
             #[uniffi::constructor]
             pub fn deserialize(data: String) -> Result<std::sync::Arc<Self>, crate::messages::serializers::SerializationError> {
-                let data: (#struct_name_ident, u8) = crate::messages::serializers::ServerMessage::deserialize(&data, #message_tag)?;
+                let data: (#struct_name_ident, Option<u32>) = crate::messages::serializers::ServerMessage::deserialize_any(&data, #message_tag)?;
                 Ok(std::sync::Arc::new(data.0))
             }
 
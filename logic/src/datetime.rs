@@ -6,10 +6,52 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+use crate::messages::serializers::SerializationError;
+
 /// Date, string format YYYY-MM-DD
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, uniffi::Object)]
 pub struct Date(time::Date);
 
+#[uniffi::export]
+impl Date {
+    /// Parse date from the YYYY-MM-DD string
+    #[uniffi::constructor]
+    pub fn from_string(value: String) -> Result<Arc<Self>, SerializationError> {
+        value
+            .parse()
+            .map(Arc::new)
+            .map_err(|msg| SerializationError::BadData { msg })
+    }
+
+    /// Returns date as YYYY-MM-DD string
+    pub fn as_string(&self) -> String {
+        self.to_string()
+    }
+}
+
+// Date is encoded as a Julian day number, so it takes only a few bytes in messages
+impl bincode::Encode for Date {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        self.0.to_julian_day().encode(encoder)
+    }
+}
+
+impl bincode::Decode for Date {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let julian_day = i32::decode(decoder)?;
+        time::Date::from_julian_day(julian_day)
+            .map(Date)
+            .map_err(|err| bincode::error::DecodeError::OtherString(err.to_string()))
+    }
+}
+
+bincode::impl_borrow_decode!(Date);
+
 impl Date {
     /// Create new date object from given parameters where month and day starts with 1
     pub fn new(year: u32, month: u8, day: u8) -> Self {
@@ -108,7 +150,12 @@ impl FromStr for Date {
 #[derive(Debug, PartialEq, uniffi::Object, bincode::Decode, bincode::Encode)]
 pub struct Timestamp(u64);
 
-impl Timestamp {}
+impl Timestamp {
+    /// Returns timestamp value in milliseconds
+    pub fn as_milliseconds(&self) -> u64 {
+        self.0
+    }
+}
 
 #[uniffi::export]
 impl Timestamp {
@@ -162,6 +209,11 @@ impl ServerTimestamp {
     pub fn from_milliseconds_pure(milliseconds: u64) -> Self {
         Self(Timestamp::from_milliseconds(milliseconds))
     }
+
+    /// Returns server timestamp value in milliseconds
+    pub fn as_milliseconds(&self) -> u64 {
+        self.0.as_milliseconds()
+    }
 }
 
 #[uniffi::export]
@@ -191,7 +243,17 @@ impl FromStr for ServerTimestamp {
 #[derive(Debug, PartialEq, uniffi::Object, bincode::Encode, bincode::Decode)]
 pub struct Duration(u64);
 
-impl Duration {}
+impl Duration {
+    /// Create new duration from passed amount of milliseconds without wrapping it into Arc
+    pub fn from_milliseconds_pure(milliseconds: u64) -> Self {
+        Self(milliseconds)
+    }
+
+    /// Returns duration value in milliseconds
+    pub fn as_milliseconds(&self) -> u64 {
+        self.0
+    }
+}
 
 #[uniffi::export]
 impl Duration {
@@ -359,6 +421,22 @@ mod tests {
         assert!("2022-09-32".parse::<Date>().is_err());
     }
 
+    #[test]
+    fn date_encoding() {
+        let config = bincode::config::standard();
+        let date = Date::new(2024, 2, 29);
+        let encoded = bincode::encode_to_vec(&date, config).unwrap();
+        let decoded: Date = bincode::decode_from_slice(&encoded, config).unwrap().0;
+        assert_eq!(decoded, date);
+        assert_eq!(
+            Date::from_string("2024-02-29".to_string())
+                .unwrap()
+                .as_string(),
+            "2024-02-29"
+        );
+        assert!(Date::from_string("2024-02-30".to_string()).is_err());
+    }
+
     #[test]
     fn date_diff() {
         let d1 = Date::new(2022, 5, 9);
@@ -0,0 +1,227 @@
+//! Deterministic Decay calculation. Decay starts with a fixed length and goes at the normal speed, while modifiers
+//! which are caused by the player progress or neglect change its speed or push its end back. Both server and
+//! clients use the same calculation, so given the same start and modifiers they always agree on the Decay end
+
+use std::sync::Arc;
+
+use crate::datetime::{Duration, ServerTimestamp, Timestamp};
+
+/// Normal Decay speed in percents
+pub const DECAY_NORMAL_SPEED_PERCENT: u32 = 100;
+
+/// Change of the Decay caused by the player
+#[derive(Debug, Clone, PartialEq, bincode::Decode, bincode::Encode, uniffi::Enum)]
+pub enum DecayModifierKind {
+    /// Decay goes with the given speed until the next speed change. 100 is the normal speed, lower values slow
+    /// Decay down, 0 pauses it and higher values accelerate it
+    Speed {
+        /// Speed in percents of the normal speed
+        percent: u32,
+    },
+    /// Decay end is pushed back by the given duration
+    Delay {
+        /// How much Decay is extended
+        duration: Arc<Duration>,
+    },
+}
+
+/// Decay modifier applied at the given time
+#[derive(Debug, Clone, PartialEq, bincode::Decode, bincode::Encode, uniffi::Record)]
+pub struct DecayModifier {
+    /// Timestamp when modifier was applied
+    pub applied_at: Arc<ServerTimestamp>,
+    /// Modifier kind
+    pub kind: DecayModifierKind,
+}
+
+/// Returns the effective end of the Decay with all the modifiers applied or None if Decay is paused forever.
+/// Modifiers are expected to be ordered by their timestamps, modifiers applied after the Decay end are ignored
+pub fn decay_ends_at(
+    started_at: &ServerTimestamp,
+    length: &Duration,
+    modifiers: &[DecayModifier],
+) -> Option<Arc<ServerTimestamp>> {
+    let state = DecayState::simulate(started_at, length, modifiers, u64::MAX);
+    state.ends_at().map(ServerTimestamp::from_milliseconds)
+}
+
+/// Returns the remaining Decay at the normal speed as of `now`, only modifiers applied before `now` are counted.
+/// Actual time left may differ if Decay speed is changed, use `decay_ends_at` to find it
+pub fn decay_remaining(
+    started_at: &ServerTimestamp,
+    length: &Duration,
+    modifiers: &[DecayModifier],
+    now: &Timestamp,
+) -> Arc<Duration> {
+    let now = now.as_milliseconds();
+    let mut state = DecayState::simulate(started_at, length, modifiers, now);
+    state.advance(now);
+    Duration::from_milliseconds(state.length.saturating_sub(state.passed))
+}
+
+/// Decay progress at a given time, all the values are in milliseconds
+struct DecayState {
+    /// Time up to which progress is calculated
+    at: u64,
+    /// Decay which already passed, measured at the normal speed
+    passed: u64,
+    /// Total Decay length including delays
+    length: u64,
+    /// Current speed in percents
+    speed: u32,
+}
+
+impl DecayState {
+    /// Applies modifiers which happened no later than `until` and before the Decay end
+    fn simulate(
+        started_at: &ServerTimestamp,
+        length: &Duration,
+        modifiers: &[DecayModifier],
+        until: u64,
+    ) -> Self {
+        let mut state = Self {
+            at: started_at.as_milliseconds(),
+            passed: 0,
+            length: length.as_milliseconds(),
+            speed: DECAY_NORMAL_SPEED_PERCENT,
+        };
+        for modifier in modifiers {
+            // Modifiers out of order are applied right away, so the result stays deterministic
+            let applied_at = modifier.applied_at.as_milliseconds().max(state.at);
+            if applied_at > until {
+                break;
+            }
+            if state.ends_at().is_some_and(|ends_at| ends_at <= applied_at) {
+                break;
+            }
+            state.advance(applied_at);
+            match &modifier.kind {
+                DecayModifierKind::Speed { percent } => state.speed = *percent,
+                DecayModifierKind::Delay { duration } => {
+                    state.length = state.length.saturating_add(duration.as_milliseconds())
+                }
+            }
+        }
+        state
+    }
+
+    /// Moves the state forward to the given time with the current speed
+    fn advance(&mut self, to: u64) {
+        if to <= self.at {
+            return;
+        }
+        let passed =
+            (to - self.at) as u128 * self.speed as u128 / DECAY_NORMAL_SPEED_PERCENT as u128;
+        self.passed = self
+            .passed
+            .saturating_add(passed.min(u64::MAX as u128) as u64)
+            .min(self.length);
+        self.at = to;
+    }
+
+    /// Returns when Decay ends with the current speed, None if it's paused
+    fn ends_at(&self) -> Option<u64> {
+        let left = self.length.saturating_sub(self.passed);
+        if left == 0 {
+            return Some(self.at);
+        }
+        if self.speed == 0 {
+            return None;
+        }
+        let time_left =
+            (left as u128 * DECAY_NORMAL_SPEED_PERCENT as u128).div_ceil(self.speed as u128);
+        Some(
+            self.at
+                .saturating_add(time_left.min(u64::MAX as u128) as u64),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60 * 1000;
+
+    fn speed(day: u64, percent: u32) -> DecayModifier {
+        DecayModifier {
+            applied_at: ServerTimestamp::from_milliseconds(day * DAY),
+            kind: DecayModifierKind::Speed { percent },
+        }
+    }
+
+    fn delay(day: u64, days: u64) -> DecayModifier {
+        DecayModifier {
+            applied_at: ServerTimestamp::from_milliseconds(day * DAY),
+            kind: DecayModifierKind::Delay {
+                duration: Duration::from_milliseconds(days * DAY),
+            },
+        }
+    }
+
+    fn ends_at_day(modifiers: &[DecayModifier]) -> Option<u64> {
+        let started_at = ServerTimestamp::from_milliseconds_pure(0);
+        let length = Duration::from_milliseconds(10 * DAY);
+        decay_ends_at(&started_at, &length, modifiers)
+            .map(|ends_at| ends_at.as_milliseconds() / DAY)
+    }
+
+    fn remaining_days(modifiers: &[DecayModifier], day: u64) -> u64 {
+        let started_at = ServerTimestamp::from_milliseconds_pure(0);
+        let length = Duration::from_milliseconds(10 * DAY);
+        let now = Timestamp::from_milliseconds(day * DAY);
+        decay_remaining(&started_at, &length, modifiers, &now).whole_days()
+    }
+
+    #[test]
+    fn decay_without_modifiers() {
+        assert_eq!(ends_at_day(&[]), Some(10));
+        assert_eq!(remaining_days(&[], 0), 10);
+        assert_eq!(remaining_days(&[], 4), 6);
+        assert_eq!(remaining_days(&[], 20), 0);
+    }
+
+    #[test]
+    fn decay_speed_changes() {
+        // Half speed from the 2nd day makes remaining 8 days last for 16
+        let modifiers = [speed(2, 50)];
+        assert_eq!(ends_at_day(&modifiers), Some(18));
+        assert_eq!(remaining_days(&modifiers, 4), 7);
+
+        // Pause stops the Decay until the speed is restored
+        let modifiers = [speed(2, 0)];
+        assert_eq!(ends_at_day(&modifiers), None);
+        assert_eq!(remaining_days(&modifiers, 100), 8);
+        let modifiers = [speed(2, 0), speed(5, 100)];
+        assert_eq!(ends_at_day(&modifiers), Some(13));
+
+        // Acceleration brings the end closer
+        let modifiers = [speed(2, 200)];
+        assert_eq!(ends_at_day(&modifiers), Some(6));
+        assert_eq!(remaining_days(&modifiers, 4), 4);
+    }
+
+    #[test]
+    fn decay_delays() {
+        let modifiers = [delay(2, 3), speed(4, 50)];
+        assert_eq!(ends_at_day(&modifiers), Some(22));
+        // Future modifiers are not counted yet
+        assert_eq!(remaining_days(&modifiers, 1), 9);
+        assert_eq!(remaining_days(&modifiers, 2), 11);
+    }
+
+    #[test]
+    fn decay_modifiers_after_end() {
+        // Decay already ended by the time modifiers were applied
+        let modifiers = [speed(5, 200), delay(9, 5)];
+        assert_eq!(ends_at_day(&modifiers), Some(7));
+        assert_eq!(remaining_days(&modifiers, 10), 0);
+    }
+
+    #[test]
+    fn decay_modifiers_out_of_order() {
+        // Modifier with an earlier timestamp applies at the time of the previous one
+        let modifiers = [speed(4, 50), delay(2, 2)];
+        assert_eq!(ends_at_day(&modifiers), Some(20));
+    }
+}
@@ -11,6 +11,9 @@ pub const AES_KEY_SIZE: usize = 32;
 /// AES nonces size in bytes
 pub const AES_NONCE_SIZE: usize = 12;
 
+/// Size of the authentication tag appended to the encrypted data
+pub const AES_TAG_SIZE: usize = 16;
+
 /// Encrypt data using AES with provided key and nonce.
 /// Panics when key or nonce are of invalid size or when encryption fails: those cases are sign of
 /// development error and are not expected to happen in runtime
@@ -7,10 +7,11 @@
 
 use std::sync::Arc;
 
+use aes::AES_TAG_SIZE;
 use binary_encoding::encode_base94;
 use ecc::{
     ecdsa_sign, ecdsa_verify, generate_ecc_keys, EccPrivateKey, EccPublicKey, EncryptedData,
-    ECC_PRIVATE_KEY_SIZE, ECC_PUBLIC_KEY_SIZE, ECC_SIGNATURE_SIZE,
+    ECC_PRIVATE_KEY_SIZE, ECC_PUBLIC_KEY_SIZE, ECC_SALT_SIZE, ECC_SIGNATURE_SIZE,
 };
 use thiserror::Error;
 
@@ -153,6 +154,32 @@ impl EncryptedString {
     }
 }
 
+impl EncryptedString {
+    /// Returns size of the encrypted data without salt
+    pub fn data_size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Serialize encrypted string to the array of bytes, salt goes first as it has a fixed size
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.salt.len() + self.data.len());
+        data.extend_from_slice(&self.salt);
+        data.extend_from_slice(&self.data);
+        data
+    }
+
+    /// Deserialize array of bytes back to the encrypted string, data can be decrypted only with a private key
+    pub fn deserialize(data: Vec<u8>) -> Result<Arc<Self>, EncryptionError> {
+        if data.len() <= ECC_SALT_SIZE {
+            return Err(EncryptionError::InvalidData);
+        }
+        Ok(Arc::new(Self {
+            salt: data[..ECC_SALT_SIZE].to_vec(),
+            data: data[ECC_SALT_SIZE..].to_vec(),
+        }))
+    }
+}
+
 /// Safe strings which users may decide to encrypt if that contains sensitive data
 #[derive(PartialEq, Debug, Clone, uniffi::Enum, bincode::Encode, bincode::Decode)]
 pub enum SafeString {
@@ -168,10 +195,47 @@ pub enum SafeString {
     },
 }
 
+impl SafeString {
+    /// Checks that text is not blank, has at most `max_length` characters and no control characters. Encrypted
+    /// text can be decrypted only by its owner, so it's checked only by size
+    pub fn validate(&self, max_length: usize) -> Result<(), SerializationError> {
+        match self {
+            SafeString::Plaintext { value } => {
+                let length = value.chars().count();
+                if value.trim().is_empty() || length > max_length {
+                    return Err(SerializationError::BadData {
+                        msg: format!(
+                            "Text should have 1..{} characters, got {}",
+                            max_length, length
+                        ),
+                    });
+                }
+                if value.chars().any(char::is_control) {
+                    return Err(SerializationError::BadData {
+                        msg: "Text should not contain control characters".to_string(),
+                    });
+                }
+            }
+            SafeString::Encrypted { data } => {
+                // Every character takes up to 4 bytes in UTF-8
+                let max_size = max_length * 4 + AES_TAG_SIZE;
+                if data.data_size() > max_size {
+                    return Err(SerializationError::BadData {
+                        msg: format!(
+                            "Encrypted text should be at most {} bytes, got {}",
+                            max_size,
+                            data.data_size()
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use ecc::ECC_SALT_SIZE;
-
     use super::*;
 
     #[test]
@@ -182,5 +246,10 @@ mod tests {
         assert_eq!(encrypted.salt.len(), ECC_SALT_SIZE); // Check that salt was created, so as encrypted payload
         let decrypted = encrypted.decrypt(&keys.private_key).unwrap();
         assert_eq!(plaintext, decrypted);
+
+        let deserialized = EncryptedString::deserialize(encrypted.serialize()).unwrap();
+        assert_eq!(deserialized, encrypted);
+        assert_eq!(deserialized.decrypt(&keys.private_key).unwrap(), plaintext);
+        assert!(EncryptedString::deserialize(encrypted.salt.clone()).is_err());
     }
 }
@@ -0,0 +1,188 @@
+//! Lifetime goals of the player. Progress on goals is what slows the Decay down
+
+use std::sync::Arc;
+
+use crate::{
+    datetime::{Date, ServerTimestamp},
+    encryption::SafeString,
+    messages::serializers::SerializationError,
+};
+
+/// Maximum length of a goal title in characters
+pub const GOAL_TITLE_MAX_LENGTH: usize = 256;
+
+/// Area of life the goal belongs to
+#[derive(Debug, Clone, PartialEq, bincode::Decode, bincode::Encode, uniffi::Enum)]
+pub enum GoalCategory {
+    /// Physical and mental health
+    Health,
+    /// Work and career
+    Career,
+    /// Family, friends and other relationships
+    Relationships,
+    /// Money and possessions
+    Finance,
+    /// Knowledge and skills
+    Learning,
+    /// Art and any other creative work
+    Creativity,
+    /// Anything else
+    Other,
+}
+
+/// Goal lifecycle status
+#[derive(Debug, Clone, PartialEq, bincode::Decode, bincode::Encode, uniffi::Enum)]
+pub enum GoalStatus {
+    /// Goal is in progress
+    Active,
+    /// Goal was reached
+    Completed,
+    /// Goal is no longer pursued, archived goals cannot be changed
+    Archived,
+}
+
+/// Errors of goal changes
+#[derive(Debug, thiserror::Error)]
+pub enum GoalError {
+    /// Goal details are not valid, e.g. title is too long
+    #[error(transparent)]
+    InvalidData(#[from] SerializationError),
+    /// Goal status doesn't allow the change
+    #[error("Goal with status {status:?} cannot be changed")]
+    InvalidState {
+        /// Current goal status
+        status: GoalStatus,
+    },
+}
+
+/// Lifetime goal of the player
+#[derive(Debug, Clone, PartialEq, bincode::Decode, bincode::Encode, uniffi::Record)]
+pub struct Goal {
+    /// Goal identifier assigned by the server
+    pub goal_id: String,
+    /// Goal title, could be encrypted so only the player can read it
+    pub title: SafeString,
+    /// Goal category
+    pub category: GoalCategory,
+    /// Date by which the player wants to reach the goal
+    pub target_date: Arc<Date>,
+    /// Goal status
+    pub status: GoalStatus,
+    /// Timestamp when goal was created
+    pub created_at: Arc<ServerTimestamp>,
+    /// Timestamp when goal was completed
+    pub completed_at: Option<Arc<ServerTimestamp>>,
+}
+
+impl Goal {
+    /// Checks that goal title could be stored
+    pub fn validate_title(title: &SafeString) -> Result<(), SerializationError> {
+        title.validate(GOAL_TITLE_MAX_LENGTH)
+    }
+
+    /// Applies new goal details, only active goals can be changed
+    pub fn update(
+        &mut self,
+        title: SafeString,
+        category: GoalCategory,
+        target_date: Arc<Date>,
+    ) -> Result<(), GoalError> {
+        self.ensure_status(&[GoalStatus::Active])?;
+        Self::validate_title(&title)?;
+        self.title = title;
+        self.category = category;
+        self.target_date = target_date;
+        Ok(())
+    }
+
+    /// Marks active goal as completed
+    pub fn complete(&mut self, now: ServerTimestamp) -> Result<(), GoalError> {
+        self.ensure_status(&[GoalStatus::Active])?;
+        self.status = GoalStatus::Completed;
+        self.completed_at = Some(Arc::new(now));
+        Ok(())
+    }
+
+    /// Archives goal, already archived goals cannot be archived again
+    pub fn archive(&mut self) -> Result<(), GoalError> {
+        self.ensure_status(&[GoalStatus::Active, GoalStatus::Completed])?;
+        self.status = GoalStatus::Archived;
+        Ok(())
+    }
+
+    fn ensure_status(&self, allowed: &[GoalStatus]) -> Result<(), GoalError> {
+        if allowed.contains(&self.status) {
+            return Ok(());
+        }
+        Err(GoalError::InvalidState {
+            status: self.status.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn goal() -> Goal {
+        Goal {
+            goal_id: "goal".to_string(),
+            title: SafeString::Plaintext {
+                value: "Run a marathon".to_string(),
+            },
+            category: GoalCategory::Health,
+            target_date: Arc::new(Date::new(2030, 1, 1)),
+            status: GoalStatus::Active,
+            created_at: ServerTimestamp::from_milliseconds(1),
+            completed_at: None,
+        }
+    }
+
+    #[test]
+    fn goal_lifecycle() {
+        let mut goal = goal();
+        let title = SafeString::Plaintext {
+            value: "Run two marathons".to_string(),
+        };
+        goal.update(
+            title.clone(),
+            GoalCategory::Health,
+            Arc::new(Date::new(2031, 1, 1)),
+        )
+        .unwrap();
+        assert_eq!(goal.title, title);
+        assert!(matches!(
+            goal.update(
+                SafeString::Plaintext {
+                    value: String::new()
+                },
+                GoalCategory::Health,
+                Arc::new(Date::new(2031, 1, 1))
+            ),
+            Err(GoalError::InvalidData(_))
+        ));
+
+        goal.complete(ServerTimestamp::from_milliseconds_pure(2))
+            .unwrap();
+        assert_eq!(goal.status, GoalStatus::Completed);
+        assert_eq!(goal.completed_at.as_ref().unwrap().as_milliseconds(), 2);
+        assert!(matches!(
+            goal.complete(ServerTimestamp::from_milliseconds_pure(3)),
+            Err(GoalError::InvalidState {
+                status: GoalStatus::Completed
+            })
+        ));
+
+        goal.archive().unwrap();
+        assert_eq!(goal.status, GoalStatus::Archived);
+        assert!(matches!(
+            goal.archive(),
+            Err(GoalError::InvalidState {
+                status: GoalStatus::Archived
+            })
+        ));
+        assert!(goal
+            .update(title, GoalCategory::Other, Arc::new(Date::new(2031, 1, 1)))
+            .is_err());
+    }
+}
@@ -3,7 +3,9 @@
 #![deny(missing_docs)] // Logic is a main shared library - require docs for all public interfaces
 
 pub mod datetime;
+pub mod decay;
 pub mod encryption;
+pub mod goal;
 pub mod messages;
 pub mod server_error;
 
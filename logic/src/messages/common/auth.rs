@@ -0,0 +1,127 @@
+//! Challenge-response authentication of a WebSocket connection. Client requests a challenge with `ChallengeQuery`,
+//! signs the received random nonce with its private key and sends the signature back with `Authenticate`. Once
+//! the signature is verified the connection is bound to the player public key, so player messages can be sent
+//! unsigned over that connection
+
+use std::sync::Arc;
+
+use messages_macro::{client_public_message, server_message};
+use rand::{rngs::OsRng, RngCore};
+
+use crate::{
+    encryption::{self, Keys, PublicKey},
+    messages::serializers::SerializationError,
+};
+
+/// Size of a challenge nonce in bytes
+pub const CHALLENGE_NONCE_SIZE: usize = 32;
+
+/// Signed along with the nonce so that the signature cannot be reused in any other context
+const CHALLENGE_CONTEXT: &[u8] = b"deusvent-connection-challenge";
+
+/// Challenge which client has to sign to authenticate the connection
+#[server_message(4)]
+pub struct Challenge {
+    /// Random nonce which is valid only for the connection it was issued for
+    pub nonce: Vec<u8>,
+}
+
+impl Challenge {
+    /// Creates a new challenge with a random nonce
+    pub fn generate() -> Self {
+        let mut nonce = vec![0; CHALLENGE_NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        Self { nonce }
+    }
+}
+
+/// Confirmation that the connection is authenticated
+#[server_message(5)]
+pub struct Authenticated {}
+
+/// Client query for a new challenge
+#[client_public_message(4, response = Challenge)]
+pub struct ChallengeQuery {}
+
+#[uniffi::export]
+impl ChallengeQuery {
+    /// Create new ChallengeQuery message
+    #[uniffi::constructor]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {})
+    }
+}
+
+/// Client response to the challenge with a player public key and a signed nonce
+#[client_public_message(5, response = Authenticated)]
+pub struct Authenticate {
+    /// Serialized player public key
+    pub public_key: Vec<u8>,
+    /// Signature of the challenge nonce
+    pub signature: Vec<u8>,
+}
+
+#[uniffi::export]
+impl Authenticate {
+    /// Create new Authenticate message by signing the challenge nonce with player keys
+    #[uniffi::constructor]
+    pub fn new(nonce: Vec<u8>, keys: Keys) -> Arc<Self> {
+        Arc::new(Self {
+            public_key: keys.public_key.serialize(),
+            signature: encryption::sign(&challenge_payload(&nonce), &keys.private_key),
+        })
+    }
+}
+
+impl Authenticate {
+    /// Verifies that the nonce was signed by the included public key and returns the key
+    pub fn verify(&self, nonce: &[u8]) -> Result<Arc<PublicKey>, SerializationError> {
+        let public_key = PublicKey::deserialize(self.public_key.clone())?;
+        if !encryption::verify(&challenge_payload(nonce), &public_key, &self.signature) {
+            return Err(SerializationError::BadData {
+                msg: "Cannot verify the challenge".to_string(),
+            });
+        }
+        Ok(public_key)
+    }
+}
+
+fn challenge_payload(nonce: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(CHALLENGE_CONTEXT.len() + nonce.len());
+    payload.extend_from_slice(CHALLENGE_CONTEXT);
+    payload.extend_from_slice(nonce);
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authenticate(nonce: &[u8], keys: &Keys) -> Arc<Authenticate> {
+        Authenticate::new(
+            nonce.to_vec(),
+            Keys {
+                public_key: keys.public_key.clone(),
+                private_key: keys.private_key.clone(),
+            },
+        )
+    }
+
+    #[test]
+    fn sign_verify_challenge() {
+        let challenge = Challenge::generate();
+        assert_eq!(challenge.nonce.len(), CHALLENGE_NONCE_SIZE);
+        assert_ne!(challenge.nonce, Challenge::generate().nonce);
+
+        let keys = encryption::generate_new_keys();
+        let msg = authenticate(&challenge.nonce, &keys);
+        let public_key = msg.verify(&challenge.nonce).unwrap();
+        assert_eq!(public_key.as_string(), keys.public_key.as_string());
+
+        // Signature is valid only for the signed nonce and included public key
+        assert!(msg.verify(&Challenge::generate().nonce).is_err());
+        let mut msg = authenticate(&challenge.nonce, &keys).as_ref().clone();
+        msg.public_key = encryption::generate_new_keys().public_key.serialize();
+        assert!(msg.verify(&challenge.nonce).is_err());
+    }
+}
@@ -1,3 +1,5 @@
 //! Common messages like connect, disconnect, health
 
+pub mod auth;
 pub mod ping;
+pub mod version;
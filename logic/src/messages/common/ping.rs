@@ -18,16 +18,21 @@ pub enum Status {
 pub struct MyTimestamp(u64);
 
 /// Server status message with common info like current time for time synchronization
-#[server_message(1)]
+#[server_message(1, tagged)]
 pub struct ServerStatus {
     /// Current server timestamp, UTC
+    #[field(1)]
     pub timestamp: Arc<ServerTimestamp>,
     /// Current server status
+    #[field(2)]
     pub status: Status,
+    /// Minimum protocol version of clients which server supports, older clients have to be updated
+    #[field(3, default)]
+    pub min_protocol_version: u16,
 }
 
 /// Client ping message
-#[client_public_message(1)]
+#[client_public_message(1, response = ServerStatus)]
 pub struct Ping {}
 
 #[uniffi::export]
@@ -0,0 +1,52 @@
+//! Protocol version handshake. Client sends the protocol version it was built with right after connecting and the
+//! server replies with the range of versions it supports, so outdated clients can ask players to update instead of
+//! failing on messages they cannot read
+
+use std::sync::Arc;
+
+use messages_macro::{client_public_message, server_message};
+
+use crate::messages::serializers::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+/// Server reply to the handshake with the range of supported protocol versions
+#[server_message(8)]
+pub struct HandshakeResult {
+    /// True if the server supports the client protocol version
+    pub accepted: bool,
+    /// Minimum protocol version supported by the server
+    pub min_protocol_version: u16,
+    /// Maximum protocol version supported by the server
+    pub max_protocol_version: u16,
+}
+
+impl HandshakeResult {
+    /// Returns handshake result for the client with given protocol version
+    pub fn for_version(protocol_version: u16) -> Self {
+        Self {
+            accepted: (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version),
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            max_protocol_version: PROTOCOL_VERSION,
+        }
+    }
+}
+
+/// Client handshake with its protocol version and build
+#[client_public_message(12, response = HandshakeResult)]
+pub struct Handshake {
+    /// Protocol version the client was built with
+    pub protocol_version: u16,
+    /// Client build identifier, used only for diagnostics
+    pub client_build: String,
+}
+
+#[uniffi::export]
+impl Handshake {
+    /// Create new Handshake message with the current protocol version
+    #[uniffi::constructor]
+    pub fn new(client_build: String) -> Arc<Self> {
+        Arc::new(Self {
+            protocol_version: PROTOCOL_VERSION,
+            client_build,
+        })
+    }
+}
@@ -7,16 +7,34 @@ use messages_macro::server_message;
 
 use crate::datetime::Duration;
 use crate::datetime::ServerTimestamp;
+use crate::datetime::Timestamp;
+use crate::decay::{decay_remaining, DecayModifier};
 
-#[server_message(2)]
+#[server_message(2, tagged)]
 pub struct Decay {
     /// Starting timestamp of a Decay
+    #[field(1)]
     pub started_at: Arc<ServerTimestamp>,
     /// How long Decay takes time
+    #[field(2)]
     pub length: Arc<Duration>,
+    /// Effective end of the Decay with all the modifiers applied, none if Decay is paused
+    #[field(3)]
+    pub ends_at: Option<Arc<ServerTimestamp>>,
+    /// Modifiers applied to the Decay ordered by their timestamps
+    #[field(4)]
+    pub modifiers: Vec<DecayModifier>,
 }
 
-#[client_player_message(2)]
+#[uniffi::export]
+impl Decay {
+    /// Returns the remaining Decay at the normal speed as of `now`
+    pub fn remaining(&self, now: &Timestamp) -> Arc<Duration> {
+        decay_remaining(&self.started_at, &self.length, &self.modifiers, now)
+    }
+}
+
+#[client_player_message(2, response = Decay)]
 pub struct DecayQuery {}
 
 #[uniffi::export]
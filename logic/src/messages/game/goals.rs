@@ -0,0 +1,69 @@
+//! Goal messages, every change of goals is replied with the full list of the player's goals
+
+use std::sync::Arc;
+
+use messages_macro::{client_player_message, server_message};
+
+use crate::{
+    datetime::Date,
+    encryption::SafeString,
+    goal::{Goal, GoalCategory},
+};
+
+/// Client request to create a new goal
+#[client_player_message(7, response = Goals)]
+pub struct CreateGoal {
+    /// Goal title
+    pub title: SafeString,
+    /// Goal category
+    pub category: GoalCategory,
+    /// Date by which the player wants to reach the goal
+    pub target_date: Arc<Date>,
+}
+
+/// Client request to change details of an active goal
+#[client_player_message(8, response = Goals)]
+pub struct UpdateGoal {
+    /// Identifier of the goal to update
+    pub goal_id: String,
+    /// New goal title
+    pub title: SafeString,
+    /// New goal category
+    pub category: GoalCategory,
+    /// New target date
+    pub target_date: Arc<Date>,
+}
+
+/// Client request to mark an active goal as completed
+#[client_player_message(9, response = Goals)]
+pub struct CompleteGoal {
+    /// Identifier of the goal to complete
+    pub goal_id: String,
+}
+
+/// Client request to archive a goal
+#[client_player_message(10, response = Goals)]
+pub struct ArchiveGoal {
+    /// Identifier of the goal to archive
+    pub goal_id: String,
+}
+
+/// Client query for all the player's goals
+#[client_player_message(11, response = Goals)]
+pub struct GoalsQuery {}
+
+#[uniffi::export]
+impl GoalsQuery {
+    /// Create new GoalsQuery message
+    #[uniffi::constructor]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {})
+    }
+}
+
+/// All the player's goals ordered by their creation time
+#[server_message(7)]
+pub struct Goals {
+    /// Player goals
+    pub goals: Vec<Goal>,
+}
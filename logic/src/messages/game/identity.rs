@@ -1,11 +1,85 @@
-//! Identity game message
+//! Identity game message and corresponding query for it
 
-use messages_macro::client_player_message;
+use std::sync::Arc;
 
-use crate::encryption::SafeString;
+use messages_macro::{client_player_message, server_message};
 
-#[client_player_message(3)]
+use crate::{encryption::SafeString, messages::serializers::SerializationError};
+
+/// Maximum length of an identity name in characters
+pub const IDENTITY_NAME_MAX_LENGTH: usize = 64;
+
+#[client_player_message(3, response = IdentityState)]
 pub struct Identity {
     /// Identity name
     pub name: SafeString,
 }
+
+impl Identity {
+    /// Checks that identity could be stored
+    pub fn validate(&self) -> Result<(), SerializationError> {
+        self.name.validate(IDENTITY_NAME_MAX_LENGTH)
+    }
+}
+
+/// Client query for the current player identity
+#[client_player_message(6, response = IdentityState)]
+pub struct IdentityQuery {}
+
+#[uniffi::export]
+impl IdentityQuery {
+    /// Create new IdentityQuery message
+    #[uniffi::constructor]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {})
+    }
+}
+
+/// Current player identity
+#[server_message(6)]
+pub struct IdentityState {
+    /// Identity name, none if player has not set it yet
+    pub name: Option<SafeString>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encryption::{generate_new_keys, EncryptedString};
+
+    use super::*;
+
+    fn plaintext(value: &str) -> Identity {
+        Identity {
+            name: SafeString::Plaintext {
+                value: value.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn validate_identity() {
+        assert!(plaintext("Player").validate().is_ok());
+        assert!(plaintext("").validate().is_err());
+        assert!(plaintext("  ").validate().is_err());
+        assert!(plaintext("new\nline").validate().is_err());
+        assert!(plaintext(&"a".repeat(IDENTITY_NAME_MAX_LENGTH))
+            .validate()
+            .is_ok());
+        assert!(plaintext(&"a".repeat(IDENTITY_NAME_MAX_LENGTH + 1))
+            .validate()
+            .is_err());
+
+        let keys = generate_new_keys();
+        let encrypted = |value: &str| Identity {
+            name: SafeString::Encrypted {
+                data: EncryptedString::new(value.to_string(), &keys.private_key),
+            },
+        };
+        assert!(encrypted(&"ы".repeat(IDENTITY_NAME_MAX_LENGTH))
+            .validate()
+            .is_ok());
+        assert!(encrypted(&"a".repeat(IDENTITY_NAME_MAX_LENGTH * 4 + 1))
+            .validate()
+            .is_err());
+    }
+}
@@ -1,4 +1,5 @@
 //! Game specific messages
 
 pub mod decay;
+pub mod goals;
 pub mod identity;
@@ -11,60 +11,108 @@
 //! in the future, we may move away from API Gateway and use raw binary data.
 //!
 //! For client messages sent to the API, we need JSON with a routing key, so messages are in the form:
-//! {"k":"[MESSAGE_TAG]","v":"[SERIALIZED_DATA]"}, where `SERIALIZED_DATA` is bincode data encoded in Base94
-//! and [MESSAGE_TAG] is the u16 tag assigned to the message also encoded in custom binary encoding.
+//! {"k":"[MESSAGE_TAG]","e":"[CODEC]","v":"[SERIALIZED_DATA]"}, where `SERIALIZED_DATA` is bincode data encoded in
+//! Base94, [CODEC] is the version of Base94 codec and [MESSAGE_TAG] is the u16 tag assigned to the message also
+//! encoded in custom binary encoding.
 //!
 //! For server messages, they are encoded strings in a form:
 //! - The first two string bytes represent the message tag
+//! - The next two string bytes represent the request id, or reserved push request id for messages pushed by the
+//!   server without a client request
+//! - The next two chars are a `§` marker followed by the version of Base94 codec
 //! - The remaining bytes are Base94 bincode-serialized data
 //!
+//! Messages without the codec version come from clients and servers which still use the original Base94 codec.
+//!
 //! Having prefix with message tag allows clients to efficiently determine which message it received via
 //! web socket and use correct deserialize logic.
 //!
 //! Encoding should be used only for message serialization for client/backend communication and should not
-//! be used in long term storages as bincode is not backward or forward compatible.
-
-use std::sync::Arc;
+//! be used in long term storages as bincode is not backward or forward compatible. Messages which have to evolve
+//! without a coordinated client release use tagged fields encoding instead, see `tagged` module.
 
-use serializers::SerializationError;
+use serializers::{MessageSignature, SerializationError};
 
-use crate::encryption::{PrivateKey, PublicKey};
+use crate::{
+    datetime::{Duration, Timestamp},
+    encryption::{PrivateKey, PublicKey},
+};
 
 pub mod common;
 pub mod game;
 pub mod serializers;
+pub mod tagged;
 
 /// Trait for all public client messages, public meaning no authentication context is needed
 pub trait ClientPublicMessage {
+    /// Server message which is sent back as a response to this message
+    type Response: ServerMessage;
+
     /// Returns message tag
     fn tag() -> u16;
 
     /// Serialize message and request_id to string
-    fn serialize(&self, request_id: u8) -> Result<String, SerializationError>;
+    fn serialize(&self, request_id: u32) -> Result<String, SerializationError>;
 
     /// Deserialize string to message itself and request_id
-    fn deserialize(input: String) -> Result<(Self, u8), SerializationError>
+    fn deserialize(input: String) -> Result<(Self, u32), SerializationError>
     where
         Self: std::marker::Sized;
 }
 
 /// Trait for all client player messages, meaning it comes with authentication context
 pub trait ClientPlayerMessage {
+    /// Server message which is sent back as a response to this message
+    type Response: ServerMessage;
+
     /// Returns message tag
     fn tag() -> u16;
 
-    /// Serialize message and request_id to string
+    /// Serialize message and request_id to string signed at the given client timestamp
     fn serialize(
         &self,
-        request_id: u8,
+        request_id: u32,
+        timestamp: &Timestamp,
         public_key: PublicKey,
         private_key: PrivateKey,
     ) -> Result<String, SerializationError>;
 
-    /// Deserialize string to message itself and request_id
-    fn deserialize(input: String) -> Result<(Self, Arc<PublicKey>, u8), SerializationError>
+    /// Deserialize string to message itself, verified signature and request_id. Messages which timestamp differs
+    /// from `now` more than `max_age` are rejected
+    fn deserialize(
+        input: String,
+        now: &Timestamp,
+        max_age: &Duration,
+    ) -> Result<(Self, MessageSignature, u32), SerializationError>
+    where
+        Self: std::marker::Sized;
+
+    /// Serialize message and request_id to string without public key and signature, such messages are
+    /// authenticated by the connection they are sent over
+    fn serialize_unsigned(&self, request_id: u32) -> Result<String, SerializationError>;
+
+    /// Deserialize unsigned string to message itself and request_id
+    fn deserialize_unsigned(input: String) -> Result<(Self, u32), SerializationError>
     where
         Self: std::marker::Sized;
+
+    /// Returns true if the string is an unsigned message
+    fn is_unsigned(input: &str) -> bool;
+}
+
+/// Trait for all server messages, which are sent to the client either as a response or pushed without a request
+pub trait ServerMessage {
+    /// Returns message tag
+    fn tag() -> u16;
+
+    /// Returns true if message uses tagged fields encoding
+    fn is_tagged() -> bool;
+
+    /// Serialize message as a response to the client message with given request_id
+    fn serialize(&self, request_id: u32) -> Result<String, SerializationError>;
+
+    /// Serialize message as a pushed message which is not a response to any client message
+    fn serialize_push(&self) -> Result<String, SerializationError>;
 }
 
 #[cfg(test)]
@@ -6,13 +6,59 @@
 //! 2) Public client messages which are sent from clients to server. They don't have any player specific information and
 //!    don't contain any authentication tokens. They are encoded as JSON like {"k":[MESSAGE_TAG],"v":[MESSAGE_PAYLOAD]}
 //! 3) Player signed messages. Such messages are player specific and includes player identifier (public_key) and also
-//!    a signature for the payload and as a proof that player identifier is correct one
+//!    a signature for the payload and as a proof that player identifier is correct one. Player messages may also be
+//!    sent unsigned as {"k":[MESSAGE_TAG],"c":[MESSAGE_PAYLOAD]} over the connection which was authenticated before
+//!
+//! Player messages may also carry a client idempotency key as the last "i" field, so retried messages are applied by
+//! the server only once
+//!
+//! Server messages are either responses to client messages and then include request_id of the client message, or
+//! they are pushed by the server without any client request and then include reserved push request id instead
 
 use std::sync::Arc;
 
-use binary_encoding::{decode_request_id, encode_message_tag, encode_request_id, REQUEST_ID_LEN};
+use binary_encoding::{
+    decode_request_id, encode_message_tag, encode_push_request_id, encode_request_id,
+    is_push_request_id, Base94Codec, REQUEST_ID_LEN,
+};
+
+use rand::{rngs::OsRng, RngCore};
+
+use crate::{
+    datetime::{Duration, Timestamp},
+    encryption::{self, PrivateKey, PublicKey, PUBLIC_KEY_SIZE, SIGNATURE_SIZE},
+};
+
+/// Size of a random nonce which is included in every signed player message
+pub const MESSAGE_NONCE_SIZE: usize = 16;
+
+/// Default maximum difference between signed player message timestamp and the server time
+pub const DEFAULT_REPLAY_WINDOW_MS: u64 = 60_000;
 
-use crate::encryption::{self, PrivateKey, PublicKey, PUBLIC_KEY_SIZE, SIGNATURE_SIZE};
+/// Version of Base94 encoding used for new message payloads. Messages carry the version of their codec, so both
+/// `Base94Codec::BigInt` and `Base94Codec::Chunked` payloads are decoded while older clients and servers are updated
+pub const MESSAGE_CODEC: Base94Codec = Base94Codec::Chunked;
+
+/// Server message payloads start with this marker followed by the codec version digit. It's outside of the Base94
+/// char set, so payloads without it are told apart and decoded as `Base94Codec::BigInt`. Every ASCII char outside of
+/// the char set has to be escaped in JSON, so the marker is a non-ASCII one which is embedded into JSON as is
+const SERVER_CODEC_MARKER: char = '§';
+
+/// Version of the messages wire format, it's bumped on every change which older clients cannot read. Version 2
+/// widened request ids from `u8` to `u32`, version 3 added the minimum supported version to `ServerStatus`, version 4
+/// switched `ServerStatus`, `Decay` and `ServerError` to tagged fields
+pub const PROTOCOL_VERSION: u16 = 4;
+
+/// Minimum protocol version of clients which server still supports, it's the oldest version which can decode every
+/// message the server sends. Version 4 changed layout of existing server messages, so older clients are rejected.
+/// Switching an existing server message to `tagged` changes its layout as well and has to raise this version
+pub const MIN_PROTOCOL_VERSION: u16 = 4;
+
+/// Maximum length of a client idempotency key
+pub const IDEMPOTENCY_KEY_MAX_LEN: usize = 64;
+
+/// Size of a timestamp included in every signed player message
+const TIMESTAMP_SIZE: usize = 8;
 
 /// Errors that may happen during data serializations
 #[derive(Debug, uniffi::Error, thiserror::Error)]
@@ -23,6 +69,12 @@ pub enum SerializationError {
         /// Error message
         msg: String,
     },
+    /// Message was already processed or it's too old to be processed
+    #[error("Replay error: {msg}")]
+    Replay {
+        /// Error message
+        msg: String,
+    },
 }
 
 impl From<bincode::error::DecodeError> for SerializationError {
@@ -50,8 +102,18 @@ impl From<binary_encoding::EncodingError> for SerializationError {
     }
 }
 
+/// Verified signature details of a signed player message
+pub struct MessageSignature {
+    /// Public key of the player who signed the message
+    pub public_key: Arc<PublicKey>,
+    /// Client timestamp at which the message was signed
+    pub timestamp: Arc<Timestamp>,
+    /// Random nonce which is unique for every signed message
+    pub nonce: [u8; MESSAGE_NONCE_SIZE],
+}
+
 /// Request id for the client messages. Server messages includes that so we can match it to the correct client requests
-type RequestId = u8;
+type RequestId = u32;
 
 /// Unique player identifier equal to it's generated public key
 #[derive(Debug, PartialEq, uniffi::Object)]
@@ -61,47 +123,78 @@ pub struct PlayerId(pub String);
 pub struct ClientPublicMessage;
 impl ClientPublicMessage {
     const JSON_PREFIX_START: &'static str = r#"{"k":""#;
+    const JSON_CODEC_START: &'static str = r#"","e":""#;
     const JSON_PREFIX_END: &'static str = r#"","v":""#;
     const JSON_SUFFIX: &'static str = r#""}"#;
 
-    fn json_prefix(tag: u16) -> String {
+    fn json_prefix(tag: u16, prefix_end: &str) -> String {
         format!(
-            "{}{}{}",
+            "{}{}{}{}{}",
             ClientPublicMessage::JSON_PREFIX_START,
             encode_message_tag(tag),
-            ClientPublicMessage::JSON_PREFIX_END
+            ClientPublicMessage::JSON_CODEC_START,
+            MESSAGE_CODEC.version(),
+            prefix_end
         )
     }
 
-    fn encode_to_string(data: &[u8], tag: u16) -> String {
-        let mut output = ClientPublicMessage::json_prefix(tag);
-        output.push_str(&binary_encoding::encode_base94(data));
+    /// Returns codec of the message and the rest of it after the prefix. Messages without the "e" field are sent by
+    /// clients which still use `Base94Codec::BigInt`
+    fn split_prefix<'a>(
+        data: &'a str,
+        tag: u16,
+        prefix_end: &str,
+    ) -> Option<(Base94Codec, &'a str)> {
+        let rest = data
+            .strip_prefix(ClientPublicMessage::JSON_PREFIX_START)?
+            .strip_prefix(encode_message_tag(tag).as_str())?;
+        let (codec, rest) = match rest.strip_prefix(ClientPublicMessage::JSON_CODEC_START) {
+            Some(rest) => {
+                let end = rest.find('"')?;
+                let codec = Base94Codec::from_version(rest[..end].parse().ok()?)?;
+                (codec, &rest[end..])
+            }
+            None => (Base94Codec::BigInt, rest),
+        };
+        rest.strip_prefix(prefix_end).map(|rest| (codec, rest))
+    }
+
+    fn encode_to_string(data: &[u8], tag: u16, prefix_end: &str) -> String {
+        let mut output = ClientPublicMessage::json_prefix(tag, prefix_end);
+        output.push_str(&MESSAGE_CODEC.encode(data));
         output.push_str(ClientPublicMessage::JSON_SUFFIX);
         output
     }
 
-    fn decode_from_string(data: &str, tag: u16) -> Result<Vec<u8>, SerializationError> {
-        let json_prefix = ClientPublicMessage::json_prefix(tag);
-        if !data.starts_with(&json_prefix) || !data.ends_with(ClientPublicMessage::JSON_SUFFIX) {
-            return Err(SerializationError::BadData {
+    fn decode_from_string(
+        data: &str,
+        tag: u16,
+        prefix_end: &str,
+    ) -> Result<Vec<u8>, SerializationError> {
+        let (codec, payload) = ClientPublicMessage::split_prefix(data, tag, prefix_end)
+            .and_then(|(codec, rest)| {
+                let payload = rest.strip_suffix(ClientPublicMessage::JSON_SUFFIX)?;
+                Some((codec, payload))
+            })
+            .ok_or_else(|| SerializationError::BadData {
                 msg: "No json_prefix and json_suffix found".to_string(),
-            });
-        }
-        let base64_data =
-            &data[json_prefix.len()..data.len() - ClientPublicMessage::JSON_SUFFIX.len()];
-        let decoded_data = binary_encoding::decode_base94(base64_data)?;
-        Ok(decoded_data)
+            })?;
+        Ok(codec.decode(payload)?)
     }
 
     /// Serialize client message using bincode, base94 and returns JSON string where "k" field has an
-    /// encoded tag and "v" has an encoded payload
+    /// encoded tag, "e" has the codec version and "v" has an encoded payload
     pub fn serialize(
         msg: &impl bincode::Encode,
         tag: u16,
         request_id: RequestId,
     ) -> Result<String, SerializationError> {
         let data = encode_to_binary(msg, request_id)?;
-        Ok(ClientPublicMessage::encode_to_string(&data, tag))
+        Ok(ClientPublicMessage::encode_to_string(
+            &data,
+            tag,
+            ClientPublicMessage::JSON_PREFIX_END,
+        ))
     }
 
     /// Deserialize JSON string back to the client message type
@@ -109,7 +202,11 @@ impl ClientPublicMessage {
     where
         T: bincode::Decode,
     {
-        let data = ClientPublicMessage::decode_from_string(data, tag)?;
+        let data = ClientPublicMessage::decode_from_string(
+            data,
+            tag,
+            ClientPublicMessage::JSON_PREFIX_END,
+        )?;
         decode_from_binary(&data)
     }
 }
@@ -117,53 +214,173 @@ impl ClientPublicMessage {
 /// Serializer for signed client messages which includes signature and player public_key identifier
 pub struct ClientPlayerMessage;
 impl ClientPlayerMessage {
+    const JSON_UNSIGNED_PREFIX_END: &'static str = r#"","c":""#;
+    const JSON_IDEMPOTENCY_KEY_START: &'static str = r#"","i":""#;
+
     /// Serialize client message using bincode, base94 and returns JSON string where "k" field has an
-    /// encoded tag and "v" has an encoded payload. Payload also includes public_key so that API
-    /// can identify the player and signature to proof the public_key validity
+    /// encoded tag and "v" has an encoded payload. Payload also includes client timestamp and random nonce to
+    /// protect from replays, public_key so that API can identify the player and signature to proof the public_key
+    /// validity. Signature covers the message tag as well, so message cannot be re-routed to another tag
     pub fn serialize(
         msg: &impl bincode::Encode,
         tag: u16,
         request_id: RequestId,
+        timestamp: &Timestamp,
         public_key: &PublicKey,
         private_key: &PrivateKey,
     ) -> Result<String, SerializationError> {
+        let mut nonce = [0; MESSAGE_NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
         let encoded_message = encode_to_binary(msg, request_id)?;
-        let mut data = Vec::with_capacity(encoded_message.len() + PUBLIC_KEY_SIZE + SIGNATURE_SIZE);
+        let mut data = Vec::with_capacity(
+            encoded_message.len()
+                + TIMESTAMP_SIZE
+                + MESSAGE_NONCE_SIZE
+                + PUBLIC_KEY_SIZE
+                + SIGNATURE_SIZE,
+        );
         data.extend_from_slice(&encoded_message);
+        data.extend_from_slice(&timestamp.as_milliseconds().to_be_bytes());
+        data.extend_from_slice(&nonce);
         data.extend_from_slice(&public_key.serialize());
-        let signature = encryption::sign(&data, private_key);
+        let signature = encryption::sign(&signed_payload(tag, &data), private_key);
         data.extend_from_slice(&signature);
-        Ok(ClientPublicMessage::encode_to_string(&data, tag))
+        Ok(ClientPublicMessage::encode_to_string(
+            &data,
+            tag,
+            ClientPublicMessage::JSON_PREFIX_END,
+        ))
     }
 
-    /// Deserialize JSON string back to the pair of client message type and a player identifier string. Returns error if
-    /// payload cannot be verified and signature is wrong
+    /// Deserialize JSON string back to the client message type and a signature details. Returns error if payload
+    /// cannot be verified and signature is wrong or if message timestamp differs from `now` more than `max_age`.
+    /// Nonce uniqueness has to be checked by the caller
     pub fn deserialize<T>(
         data: &str,
         tag: u16,
-    ) -> Result<(T, Arc<PublicKey>, RequestId), SerializationError>
+        now: &Timestamp,
+        max_age: &Duration,
+    ) -> Result<(T, MessageSignature, RequestId), SerializationError>
     where
         T: bincode::Decode,
     {
-        let decoded_data = ClientPublicMessage::decode_from_string(data, tag)?;
-        if decoded_data.len() < PUBLIC_KEY_SIZE + SIGNATURE_SIZE {
+        let decoded_data = ClientPublicMessage::decode_from_string(
+            data,
+            tag,
+            ClientPublicMessage::JSON_PREFIX_END,
+        )?;
+        let suffix_size = TIMESTAMP_SIZE + MESSAGE_NONCE_SIZE + PUBLIC_KEY_SIZE + SIGNATURE_SIZE;
+        if decoded_data.len() < suffix_size {
             return Err(SerializationError::BadData {
                 msg: "Too short message".to_string(),
             });
         }
-        let signature = &&decoded_data[decoded_data.len() - SIGNATURE_SIZE..];
-        let public_key_data = &decoded_data[decoded_data.len() - SIGNATURE_SIZE - PUBLIC_KEY_SIZE
-            ..decoded_data.len() - SIGNATURE_SIZE];
+        let (payload, signature) = decoded_data.split_at(decoded_data.len() - SIGNATURE_SIZE);
+        let (payload_rest, public_key_data) = payload.split_at(payload.len() - PUBLIC_KEY_SIZE);
+        let (payload_rest, nonce) = payload_rest.split_at(payload_rest.len() - MESSAGE_NONCE_SIZE);
+        let (msg_data, timestamp_data) = payload_rest.split_at(payload_rest.len() - TIMESTAMP_SIZE);
         let public_key = PublicKey::deserialize(public_key_data.to_vec())?;
-        let signed_payload = &decoded_data[..decoded_data.len() - SIGNATURE_SIZE];
-        if !encryption::verify(signed_payload, &public_key, signature) {
+        if !encryption::verify(&signed_payload(tag, payload), &public_key, signature) {
             return Err(SerializationError::BadData {
                 msg: "Cannot verify the data".to_string(),
             });
         }
-        let msg_data = &decoded_data[..decoded_data.len() - SIGNATURE_SIZE - PUBLIC_KEY_SIZE];
+        let timestamp = Timestamp::from_milliseconds(u64::from_be_bytes(
+            timestamp_data
+                .try_into()
+                .expect("Timestamp slice has a fixed size"),
+        ));
+        if now.diff(&timestamp).as_milliseconds() > max_age.as_milliseconds() {
+            return Err(SerializationError::Replay {
+                msg: "Message timestamp is outside of the allowed window".to_string(),
+            });
+        }
         let (instance, request_id) = decode_from_binary(msg_data)?;
-        Ok((instance, public_key, request_id))
+        let signature = MessageSignature {
+            public_key,
+            timestamp,
+            nonce: nonce.try_into().expect("Nonce slice has a fixed size"),
+        };
+        Ok((instance, signature, request_id))
+    }
+
+    /// Serialize client message without public_key and signature. Payload is stored in "c" field instead of "v",
+    /// so API can tell such messages apart and authenticate them by the connection they were sent over
+    pub fn serialize_unsigned(
+        msg: &impl bincode::Encode,
+        tag: u16,
+        request_id: RequestId,
+    ) -> Result<String, SerializationError> {
+        let data = encode_to_binary(msg, request_id)?;
+        Ok(ClientPublicMessage::encode_to_string(
+            &data,
+            tag,
+            ClientPlayerMessage::JSON_UNSIGNED_PREFIX_END,
+        ))
+    }
+
+    /// Deserialize unsigned JSON string back to the client message type. Player has to be identified by the
+    /// authenticated connection instead
+    pub fn deserialize_unsigned<T>(
+        data: &str,
+        tag: u16,
+    ) -> Result<(T, RequestId), SerializationError>
+    where
+        T: bincode::Decode,
+    {
+        let data = ClientPublicMessage::decode_from_string(
+            data,
+            tag,
+            ClientPlayerMessage::JSON_UNSIGNED_PREFIX_END,
+        )?;
+        decode_from_binary(&data)
+    }
+
+    /// Returns true if the message was serialized without public_key and signature
+    pub fn is_unsigned(data: &str, tag: u16) -> bool {
+        ClientPublicMessage::split_prefix(data, tag, ClientPlayerMessage::JSON_UNSIGNED_PREFIX_END)
+            .is_some()
+    }
+
+    /// Adds idempotency key to the serialized signed or unsigned message as the last "i" field. The key is not
+    /// covered by the signature, it only tells the server that the message is a retry of an already sent one
+    pub fn add_idempotency_key(data: &str, key: &str) -> Result<String, SerializationError> {
+        validate_idempotency_key(key)?;
+        let message = data
+            .strip_suffix(ClientPublicMessage::JSON_SUFFIX)
+            .ok_or_else(|| SerializationError::BadData {
+                msg: "No json_suffix found".to_string(),
+            })?;
+        if message.contains(ClientPlayerMessage::JSON_IDEMPOTENCY_KEY_START) {
+            return Err(SerializationError::BadData {
+                msg: "Message already has an idempotency key".to_string(),
+            });
+        }
+        Ok(format!(
+            "{}{}{}{}",
+            message,
+            ClientPlayerMessage::JSON_IDEMPOTENCY_KEY_START,
+            key,
+            ClientPublicMessage::JSON_SUFFIX
+        ))
+    }
+
+    /// Splits serialized message into the message without idempotency key and the key if the message has one
+    pub fn split_idempotency_key(
+        data: &str,
+    ) -> Result<(String, Option<String>), SerializationError> {
+        // Payload is Base94 encoded without quotes, so the last "i" field cannot be confused with the payload
+        let Some(start) = data.rfind(ClientPlayerMessage::JSON_IDEMPOTENCY_KEY_START) else {
+            return Ok((data.to_string(), None));
+        };
+        let key = data[start + ClientPlayerMessage::JSON_IDEMPOTENCY_KEY_START.len()..]
+            .strip_suffix(ClientPublicMessage::JSON_SUFFIX)
+            .ok_or_else(|| SerializationError::BadData {
+                msg: "No json_suffix found".to_string(),
+            })?;
+        validate_idempotency_key(key)?;
+        let message = format!("{}{}", &data[..start], ClientPublicMessage::JSON_SUFFIX);
+        Ok((message, Some(key.to_string())))
     }
 }
 
@@ -171,26 +388,58 @@ impl ClientPlayerMessage {
 pub struct ServerMessage;
 impl ServerMessage {
     /// Serialize server message using bincode and Base94. First 2 bytes are message tag, then next 2 bytes are
-    /// request id. Having those prefixes allows clients efficiently check what kind of message it receive and
-    /// process it appropriately
+    /// request id followed by the codec marker. Having those prefixes allows clients efficiently check what kind of
+    /// message it receive and process it appropriately
     pub fn serialize(
         msg: &impl bincode::Encode,
         tag: u16,
         request_id: RequestId,
     ) -> Result<String, SerializationError> {
-        let data = bincode::encode_to_vec(msg, bincode::config::standard())?;
-        let serialized = binary_encoding::encode_base94(&data);
-        Ok(format!(
-            "{}{}{}",
-            encode_message_tag(tag),
-            encode_request_id(request_id),
-            serialized
-        ))
+        ServerMessage::encode_to_string(msg, tag, &encode_request_id(request_id))
+    }
+
+    /// Serialize server message which is pushed to the client without any client request. Same as `serialize`, but
+    /// reserved push request id is used instead of the actual one
+    pub fn serialize_push(
+        msg: &impl bincode::Encode,
+        tag: u16,
+    ) -> Result<String, SerializationError> {
+        ServerMessage::encode_to_string(msg, tag, &encode_push_request_id())
     }
 
     /// Deserialize string to the server message, it will return an error if supplied message tag
-    /// doesn't match first two bytes of a message
+    /// doesn't match first two bytes of a message or if the message was pushed and has no request id
     pub fn deserialize<T>(data: &str, tag: u16) -> Result<(T, RequestId), SerializationError>
+    where
+        T: bincode::Decode,
+    {
+        match ServerMessage::deserialize_any(data, tag)? {
+            (instance, Some(request_id)) => Ok((instance, request_id)),
+            (_, None) => Err(SerializationError::BadData {
+                msg: "Pushed message has no request id".to_string(),
+            }),
+        }
+    }
+
+    /// Deserialize string to the pushed server message, it will return an error if the message is a response
+    /// to a client request
+    pub fn deserialize_push<T>(data: &str, tag: u16) -> Result<T, SerializationError>
+    where
+        T: bincode::Decode,
+    {
+        match ServerMessage::deserialize_any(data, tag)? {
+            (instance, None) => Ok(instance),
+            (_, Some(_)) => Err(SerializationError::BadData {
+                msg: "Message is not a pushed message".to_string(),
+            }),
+        }
+    }
+
+    /// Deserialize string to the server message, returned request id is None if the message was pushed
+    pub fn deserialize_any<T>(
+        data: &str,
+        tag: u16,
+    ) -> Result<(T, Option<RequestId>), SerializationError>
     where
         T: bincode::Decode,
     {
@@ -206,14 +455,79 @@ impl ServerMessage {
                 msg: "Bad message tag".to_string(),
             });
         }
-        let request_id = decode_request_id(
-            data[message_tag.len()..message_tag.len() + REQUEST_ID_LEN].as_bytes(),
-        )?;
-        let input = &data[message_tag.len() + REQUEST_ID_LEN..];
-        let decoded = binary_encoding::decode_base94(input)?;
+        let request_id_data = &data.as_bytes()[message_tag.len()..];
+        let (request_id, request_id_len) = if is_push_request_id(request_id_data) {
+            (None, REQUEST_ID_LEN)
+        } else {
+            let (request_id, request_id_len) = decode_request_id(request_id_data)?;
+            (Some(request_id), request_id_len)
+        };
+        let (codec, input) = split_server_payload(&data[message_tag.len() + request_id_len..])?;
+        let decoded = codec.decode(input)?;
         let instance: T = bincode::decode_from_slice(&decoded, bincode::config::standard())?.0;
         Ok((instance, request_id))
     }
+
+    /// Replaces request id of the serialized server message, so a stored response can be sent back to another
+    /// request. Pushed messages have no request id and cannot be changed
+    pub fn replace_request_id(
+        data: &str,
+        request_id: RequestId,
+    ) -> Result<String, SerializationError> {
+        let request_id_data = data.as_bytes().get(2..).unwrap_or_default();
+        if is_push_request_id(request_id_data) {
+            return Err(SerializationError::BadData {
+                msg: "Message has no request id".to_string(),
+            });
+        }
+        let (_, request_id_len) = decode_request_id(request_id_data)?;
+        Ok(format!(
+            "{}{}{}",
+            &data[..2],
+            encode_request_id(request_id),
+            &data[2 + request_id_len..]
+        ))
+    }
+
+    fn encode_to_string(
+        msg: &impl bincode::Encode,
+        tag: u16,
+        encoded_request_id: &str,
+    ) -> Result<String, SerializationError> {
+        let data = bincode::encode_to_vec(msg, bincode::config::standard())?;
+        let serialized = MESSAGE_CODEC.encode(&data);
+        Ok(format!(
+            "{}{}{}{}{}",
+            encode_message_tag(tag),
+            encoded_request_id,
+            SERVER_CODEC_MARKER,
+            MESSAGE_CODEC.version(),
+            serialized
+        ))
+    }
+}
+
+/// Returns codec and encoded payload of the server message after its header. Payloads without the codec marker are
+/// sent by servers which still use `Base94Codec::BigInt`
+fn split_server_payload(data: &str) -> Result<(Base94Codec, &str), SerializationError> {
+    let Some(marked) = data.strip_prefix(SERVER_CODEC_MARKER) else {
+        return Ok((Base94Codec::BigInt, data));
+    };
+    marked
+        .as_bytes()
+        .first()
+        .and_then(|version| Base94Codec::from_version(version.wrapping_sub(b'0')))
+        .map(|codec| (codec, &marked[1..]))
+        .ok_or_else(|| SerializationError::BadData {
+            msg: "Unknown payload codec".to_string(),
+        })
+}
+
+fn signed_payload(tag: u16, data: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(data.len() + 2);
+    payload.extend_from_slice(&tag.to_be_bytes());
+    payload.extend_from_slice(data);
+    payload
 }
 
 fn encode_to_binary(
@@ -222,18 +536,34 @@ fn encode_to_binary(
 ) -> Result<Vec<u8>, SerializationError> {
     let config = bincode::config::standard();
 
-    // Manually pre-allocate vector with +1 capacity so that request_id would fit without reallocation along with encoded data
+    // Manually pre-allocate vector so that request_id would fit without reallocation along with encoded data
     let mut size_writer = bincode::enc::write::SizeWriter::default();
     bincode::encode_into_writer(msg, &mut size_writer, config)?;
     let msg_size = size_writer.bytes_written;
-    let mut data = vec![0; msg_size + 1];
+    bincode::encode_into_writer(request_id, &mut size_writer, config)?;
+    let mut data = vec![0; size_writer.bytes_written];
 
-    // Now encode the message and add request_id itself as a last byte
+    // Now encode the message and add request_id itself after it as a varint, so ids below 251 take a single byte
     bincode::encode_into_slice(msg, &mut data, config)?;
-    data[msg_size] = request_id;
+    bincode::encode_into_slice(request_id, &mut data[msg_size..], config)?;
     Ok(data)
 }
 
+fn validate_idempotency_key(key: &str) -> Result<(), SerializationError> {
+    let valid_chars = key
+        .bytes()
+        .all(|char| char.is_ascii_alphanumeric() || char == b'-' || char == b'_');
+    if key.is_empty() || key.len() > IDEMPOTENCY_KEY_MAX_LEN || !valid_chars {
+        return Err(SerializationError::BadData {
+            msg: format!(
+                "Idempotency key has to be 1 to {} letters, digits, dashes or underscores",
+                IDEMPOTENCY_KEY_MAX_LEN
+            ),
+        });
+    }
+    Ok(())
+}
+
 fn decode_from_binary<T>(data: &[u8]) -> Result<(T, RequestId), SerializationError>
 where
     T: bincode::Decode,
@@ -243,20 +573,42 @@ where
             msg: "Data too short".to_string(),
         });
     }
-    let request_id = data[data.len() - 1];
-    let payload = &data[..&data.len() - 1];
-    let instance: T = bincode::decode_from_slice(payload, bincode::config::standard())?.0;
+    let config = bincode::config::standard();
+    let (instance, msg_size): (T, usize) = bincode::decode_from_slice(data, config)?;
+    let (request_id, request_id_size) = bincode::decode_from_slice(&data[msg_size..], config)?;
+    if msg_size + request_id_size != data.len() {
+        return Err(SerializationError::BadData {
+            msg: "Unexpected data after request id".to_string(),
+        });
+    }
     Ok((instance, request_id))
 }
 
-/// Decode string back to the request_id or fallback to 0 value
+/// Decode request id at the start of the string back to the request_id or fallback to 0 value. String is the server
+/// message without its two characters tag, anything after the request id is ignored
 #[uniffi::export]
 pub fn parse_request_id(data: String) -> RequestId {
-    binary_encoding::decode_request_id(data.as_bytes()).unwrap_or({
-        // Maybe it would make more sense to simply crash in this case as unparsable request id
-        // means message is terribly broken or receiving logic is wrong
-        0
-    })
+    binary_encoding::decode_request_id(data.as_bytes())
+        .map(|(request_id, _)| request_id)
+        .unwrap_or({
+            // Maybe it would make more sense to simply crash in this case as unparsable request id
+            // means message is terribly broken or receiving logic is wrong
+            0
+        })
+}
+
+/// Adds idempotency key to the serialized player message, so the server applies the message only once even if it's
+/// retried. Retries have to be serialized again with the same key, as every signed message can be sent only once
+#[uniffi::export]
+pub fn add_idempotency_key(message: String, key: String) -> Result<String, SerializationError> {
+    ClientPlayerMessage::add_idempotency_key(&message, &key)
+}
+
+/// Returns true if the server message was pushed by the server and is not a response to a client request
+#[uniffi::export]
+pub fn is_push_message(data: String) -> bool {
+    data.get(2..2 + REQUEST_ID_LEN)
+        .is_some_and(|request_id| is_push_request_id(request_id.as_bytes()))
 }
 
 #[cfg(test)]
@@ -272,12 +624,14 @@ mod tests {
 
     use super::*;
 
+    const TIME: u64 = 1726219252000;
+
     #[test]
     fn client_messages_serialization() {
         let msg = Ping {};
         let data = ClientPublicMessage::serialize(&msg, 1, 1).unwrap();
-        assert_eq!(data.len(), 18);
-        assert_eq!(data, r#"{"k":"-.","v":"!"}"#);
+        assert_eq!(data.len(), 27);
+        assert_eq!(data, r#"{"k":"-.","e":"2","v":" !"}"#);
 
         // Ensure deserialization works
         let got: (Ping, RequestId) = ClientPublicMessage::deserialize(&data, 1).unwrap();
@@ -292,24 +646,176 @@ mod tests {
     fn client_signed_message_serialization() {
         let msg = Ping {};
         let keys = encryption::generate_new_keys();
-        let data = ClientPlayerMessage::serialize(&msg, 1, 1, &keys.public_key, &keys.private_key)
-            .unwrap();
+        let now = Timestamp::from_milliseconds(TIME);
+        let window = Duration::from_milliseconds(DEFAULT_REPLAY_WINDOW_MS);
+        let data =
+            ClientPlayerMessage::serialize(&msg, 1, 1, &now, &keys.public_key, &keys.private_key)
+                .unwrap();
 
         // Ensure it's valid JSON
         let _: Value = serde_json::from_slice(data.as_bytes()).unwrap();
 
-        // We can't assert for actual data as keys are generated, but length is constant
-        assert_eq!(data.len(), 136);
-        let parsed = ClientPlayerMessage::deserialize::<Ping>(&data, 1).unwrap();
+        // We can't assert for actual data as keys and nonce are generated, but length is constant
+        assert_eq!(data.len(), 175);
+        let parsed = ClientPlayerMessage::deserialize::<Ping>(&data, 1, &now, &window).unwrap();
         assert_eq!(parsed.0, msg);
-        assert_eq!(parsed.1.as_string(), keys.public_key.as_string());
+        assert_eq!(parsed.1.public_key.as_string(), keys.public_key.as_string());
+        assert_eq!(parsed.1.timestamp, now);
         assert_eq!(parsed.2, 1);
 
-        // Signature is stable for the same content
+        // Every message gets a new nonce, so the same content is never signed twice
         let data_repeat =
-            ClientPlayerMessage::serialize(&msg, 1, 1, &keys.public_key, &keys.private_key)
+            ClientPlayerMessage::serialize(&msg, 1, 1, &now, &keys.public_key, &keys.private_key)
                 .unwrap();
-        assert_eq!(data, data_repeat);
+        assert_ne!(data, data_repeat);
+        let parsed_repeat =
+            ClientPlayerMessage::deserialize::<Ping>(&data_repeat, 1, &now, &window).unwrap();
+        assert_ne!(parsed.1.nonce, parsed_repeat.1.nonce);
+        assert!(!ClientPlayerMessage::is_unsigned(&data, 1));
+    }
+
+    #[test]
+    fn client_signed_message_replay() {
+        let msg = Ping {};
+        let keys = encryption::generate_new_keys();
+        let signed_at = Timestamp::from_milliseconds(TIME);
+        let window = Duration::from_milliseconds(1000);
+        let data = ClientPlayerMessage::serialize(
+            &msg,
+            1,
+            1,
+            &signed_at,
+            &keys.public_key,
+            &keys.private_key,
+        )
+        .unwrap();
+
+        // Timestamp has to be within the window in both directions
+        for now in [TIME - 1000, TIME + 1000] {
+            let now = Timestamp::from_milliseconds(now);
+            assert!(ClientPlayerMessage::deserialize::<Ping>(&data, 1, &now, &window).is_ok());
+        }
+        for now in [TIME - 1001, TIME + 1001] {
+            let now = Timestamp::from_milliseconds(now);
+            assert!(matches!(
+                ClientPlayerMessage::deserialize::<Ping>(&data, 1, &now, &window),
+                Err(SerializationError::Replay { .. })
+            ));
+        }
+
+        // Message tag is signed, so the message cannot be re-routed to a different tag
+        let rerouted = data.replacen(&encode_message_tag(1), &encode_message_tag(2), 1);
+        assert!(matches!(
+            ClientPlayerMessage::deserialize::<Ping>(&rerouted, 2, &signed_at, &window),
+            Err(SerializationError::BadData { .. })
+        ));
+    }
+
+    #[test]
+    fn client_unsigned_message_serialization() {
+        let msg = Ping {};
+        let data = ClientPlayerMessage::serialize_unsigned(&msg, 1, 1).unwrap();
+        assert_eq!(data, r#"{"k":"-.","e":"2","c":" !"}"#);
+        assert!(ClientPlayerMessage::is_unsigned(&data, 1));
+        assert!(!ClientPlayerMessage::is_unsigned(&data, 2));
+        let got: (Ping, RequestId) = ClientPlayerMessage::deserialize_unsigned(&data, 1).unwrap();
+        assert_eq!(got, (msg, 1));
+
+        // Unsigned format is different from both signed and public ones
+        let now = Timestamp::from_milliseconds(TIME);
+        let window = Duration::from_milliseconds(DEFAULT_REPLAY_WINDOW_MS);
+        assert!(ClientPlayerMessage::deserialize::<Ping>(&data, 1, &now, &window).is_err());
+        let public = ClientPublicMessage::serialize(&Ping {}, 1, 1).unwrap();
+        assert!(ClientPlayerMessage::deserialize_unsigned::<Ping>(&public, 1).is_err());
+    }
+
+    #[test]
+    fn wide_request_ids() {
+        let msg = Ping {};
+        for request_id in [250, 251, u8::MAX as u32 + 1, 4354, u32::MAX] {
+            let data = ClientPublicMessage::serialize(&msg, 1, request_id).unwrap();
+            let got: (Ping, RequestId) = ClientPublicMessage::deserialize(&data, 1).unwrap();
+            assert_eq!(got, (Ping {}, request_id));
+            let data = ClientPlayerMessage::serialize_unsigned(&msg, 1, request_id).unwrap();
+            let got: (Ping, RequestId) =
+                ClientPlayerMessage::deserialize_unsigned(&data, 1).unwrap();
+            assert_eq!(got.1, request_id);
+
+            let status = ServerStatus {
+                timestamp: Arc::new(ServerTimestamp::from_milliseconds_pure(1)),
+                status: Status::OK,
+                min_protocol_version: MIN_PROTOCOL_VERSION,
+            };
+            let data = ServerMessage::serialize(&status, 1, request_id).unwrap();
+            assert_eq!(parse_request_id(data[2..].to_string()), request_id);
+            assert!(!is_push_message(data.clone()));
+            let got: (ServerStatus, RequestId) = ServerMessage::deserialize(&data, 1).unwrap();
+            assert_eq!(got, (status, request_id));
+            let replaced = ServerMessage::replace_request_id(&data, 1).unwrap();
+            assert_eq!(replaced, "-.-.§2 A?&t:5*QW9  ");
+        }
+
+        // Old two characters header is still parsed on its own
+        assert_eq!(parse_request_id("-.".to_string()), 1);
+        assert_eq!(parse_request_id("zz".to_string()), 0);
+    }
+
+    #[test]
+    fn client_message_idempotency_key() {
+        let data = ClientPlayerMessage::serialize_unsigned(&Ping {}, 1, 1).unwrap();
+        let with_key = add_idempotency_key(data.clone(), "01J-key_1".to_string()).unwrap();
+        assert_eq!(with_key, r#"{"k":"-.","e":"2","c":" !","i":"01J-key_1"}"#);
+        let _: Value = serde_json::from_slice(with_key.as_bytes()).unwrap();
+        assert_eq!(
+            ClientPlayerMessage::split_idempotency_key(&with_key).unwrap(),
+            (data.clone(), Some("01J-key_1".to_string()))
+        );
+        assert_eq!(
+            ClientPlayerMessage::split_idempotency_key(&data).unwrap(),
+            (data.clone(), None)
+        );
+        assert!(ClientPlayerMessage::add_idempotency_key(&with_key, "other").is_err());
+
+        // Key has to be safe to embed into JSON
+        for key in ["", "a\"b", &"k".repeat(IDEMPOTENCY_KEY_MAX_LEN + 1)] {
+            assert!(ClientPlayerMessage::add_idempotency_key(&data, key).is_err());
+        }
+        let bad_key = r#"{"k":"-.","c":" !","i":"a b"}"#;
+        assert!(ClientPlayerMessage::split_idempotency_key(bad_key).is_err());
+    }
+
+    #[test]
+    fn legacy_codec_messages() {
+        // Messages without codec version come from clients and servers which still use the original codec
+        let data = encode_to_binary(&Ping {}, 1).unwrap();
+        let client = format!(
+            r#"{{"k":"-.","v":"{}"}}"#,
+            Base94Codec::BigInt.encode(&data)
+        );
+        let got: (Ping, RequestId) = ClientPublicMessage::deserialize(&client, 1).unwrap();
+        assert_eq!(got, (Ping {}, 1));
+        let client = format!(
+            r#"{{"k":"-.","c":"{}"}}"#,
+            Base94Codec::BigInt.encode(&data)
+        );
+        assert!(ClientPlayerMessage::is_unsigned(&client, 1));
+        let got: (Ping, RequestId) = ClientPlayerMessage::deserialize_unsigned(&client, 1).unwrap();
+        assert_eq!(got, (Ping {}, 1));
+
+        let msg = ServerStatus {
+            timestamp: Arc::new(ServerTimestamp::from_milliseconds_pure(1)),
+            status: Status::OK,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+        };
+        let data = bincode::encode_to_vec(&msg, bincode::config::standard()).unwrap();
+        let server = format!("-.-.{}", Base94Codec::BigInt.encode(&data));
+        let got: (ServerStatus, RequestId) = ServerMessage::deserialize(&server, 1).unwrap();
+        assert_eq!(got, (msg, 1));
+
+        // Unknown codecs are rejected
+        let client = r#"{"k":"-.","e":"9","v":" !"}"#;
+        assert!(ClientPublicMessage::deserialize::<Ping>(client, 1).is_err());
+        assert!(ServerMessage::deserialize::<ServerStatus>("-.-.§9 !", 1).is_err());
     }
 
     #[test]
@@ -317,12 +823,96 @@ mod tests {
         let msg = ServerStatus {
             timestamp: Arc::new(ServerTimestamp::from_milliseconds_pure(1)),
             status: Status::OK,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
         };
         let data = ServerMessage::serialize(&msg, 1, 1).unwrap();
-        assert_eq!(data, "-.-.#f");
-        assert_eq!(data.len(), 6); // 2(tag) + 2(request_id) + 1(timestamp) + 1(status)
+        assert_eq!(data, "-.-.§2 A?&t:5*QW9  ");
+        assert_eq!(data.chars().count(), 19); // 2(tag) + 2(request_id) + 2(codec) + 13(tagged timestamp, status and version)
+                                              // Messages are embedded into JSON responses without escaping
+        assert_eq!(
+            serde_json::to_string(&data).unwrap(),
+            format!("\"{}\"", data)
+        );
         let got: (ServerStatus, RequestId) = ServerMessage::deserialize(&data, 1).unwrap();
         assert_eq!(got.0, msg);
-        assert_eq!(got.1, 1)
+        assert_eq!(got.1, 1);
+        assert!(!is_push_message(data.clone()));
+        assert!(ServerMessage::deserialize_push::<ServerStatus>(&data, 1).is_err());
+
+        let replaced = ServerMessage::replace_request_id(&data, 2).unwrap();
+        let got: (ServerStatus, RequestId) = ServerMessage::deserialize(&replaced, 1).unwrap();
+        assert_eq!(got, (msg, 2));
+    }
+
+    #[test]
+    fn server_push_message_serialization() {
+        let msg = ServerStatus {
+            timestamp: Arc::new(ServerTimestamp::from_milliseconds_pure(1)),
+            status: Status::OK,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+        };
+        let data = ServerMessage::serialize_push(&msg, 1).unwrap();
+        assert_eq!(data, "-.zz§2 A?&t:5*QW9  ");
+        assert!(is_push_message(data.clone()));
+        let got: ServerStatus = ServerMessage::deserialize_push(&data, 1).unwrap();
+        assert_eq!(got, msg);
+        let got: (ServerStatus, Option<RequestId>) =
+            ServerMessage::deserialize_any(&data, 1).unwrap();
+        assert_eq!(got, (msg, None));
+
+        // Pushed message has no request id, so it can't be deserialized as a response
+        assert!(ServerMessage::deserialize::<ServerStatus>(&data, 1).is_err());
+        assert!(ServerMessage::replace_request_id(&data, 1).is_err());
+        assert!(!is_push_message("-.".to_string()));
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn tagged_messages_raise_min_protocol_version() {
+        use crate::{
+            messages::{
+                common::{
+                    auth::{Authenticated, Challenge},
+                    version::HandshakeResult,
+                },
+                game::{decay::Decay, goals::Goals, identity::IdentityState},
+                ServerMessage as _,
+            },
+            server_error::ServerError,
+        };
+
+        // Protocol version in which a server message was switched to tagged fields, add the message here once it's
+        // switched and raise MIN_PROTOCOL_VERSION to that version
+        let tagged_since = [
+            (ServerStatus::tag(), 4),
+            (Decay::tag(), 4),
+            (ServerError::tag(), 4),
+        ];
+        let server_messages = [
+            (ServerStatus::tag(), ServerStatus::is_tagged()),
+            (Decay::tag(), Decay::is_tagged()),
+            (ServerError::tag(), ServerError::is_tagged()),
+            (Challenge::tag(), Challenge::is_tagged()),
+            (Authenticated::tag(), Authenticated::is_tagged()),
+            (IdentityState::tag(), IdentityState::is_tagged()),
+            (Goals::tag(), Goals::is_tagged()),
+            (HandshakeResult::tag(), HandshakeResult::is_tagged()),
+        ];
+
+        for (tag, is_tagged) in server_messages {
+            let since = tagged_since
+                .iter()
+                .find(|(tagged_tag, _)| *tagged_tag == tag)
+                .map(|(_, version)| *version);
+            assert_eq!(
+                is_tagged,
+                since.is_some(),
+                "Server message tag={} switched to tagged without raising MIN_PROTOCOL_VERSION",
+                tag
+            );
+            if let Some(version) = since {
+                assert!(MIN_PROTOCOL_VERSION >= version);
+            }
+        }
     }
 }
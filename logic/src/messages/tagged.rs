@@ -0,0 +1,168 @@
+//! Tagged fields encoding for messages which have to stay compatible between client and server versions. Messages
+//! opt in with `tagged` argument of the message macros and every field gets an id with `#[field(ID)]` attribute.
+//!
+//! Every field is encoded as its id, length of the value and the bincode encoded value itself, the list of fields
+//! ends with the reserved 0 id. Decoder skips fields with unknown ids, so newer messages can be read by older
+//! clients, and fields marked as `#[field(ID, default)]` fall back to their default value when missing, so older
+//! messages can be read by newer clients. Ids of removed fields must never be reused
+
+use bincode::{
+    de::{read::Reader, Decoder},
+    enc::{write::Writer, Encoder},
+    error::{DecodeError, EncodeError},
+    Decode, Encode,
+};
+
+/// Reserved field id which ends the list of fields
+const END_FIELD_ID: u16 = 0;
+
+/// Encodes field with given id and value
+pub fn encode_field<E: Encoder>(
+    encoder: &mut E,
+    field_id: u16,
+    value: &impl Encode,
+) -> Result<(), EncodeError> {
+    let data = bincode::encode_to_vec(value, *encoder.config())?;
+    field_id.encode(encoder)?;
+    (data.len() as u64).encode(encoder)?;
+    encoder.writer().write(&data)
+}
+
+/// Encodes the end of the fields list
+pub fn encode_end<E: Encoder>(encoder: &mut E) -> Result<(), EncodeError> {
+    END_FIELD_ID.encode(encoder)
+}
+
+/// Decodes id and length of the next field, returns None at the end of the fields list
+pub fn decode_field_header<D: Decoder>(
+    decoder: &mut D,
+) -> Result<Option<(u16, usize)>, DecodeError> {
+    let field_id = u16::decode(decoder)?;
+    if field_id == END_FIELD_ID {
+        return Ok(None);
+    }
+    let len = usize::try_from(u64::decode(decoder)?)
+        .map_err(|_| DecodeError::OtherString("Field is too long".to_string()))?;
+    Ok(Some((field_id, len)))
+}
+
+/// Decodes value of the field with given length, the value has to take exactly that length
+pub fn decode_field_value<D: Decoder, T: Decode>(
+    decoder: &mut D,
+    len: usize,
+) -> Result<T, DecodeError> {
+    let config = *decoder.config();
+    let data = peek_field(decoder, len)?;
+    let (value, read) = bincode::decode_from_slice(data, config)?;
+    if read != len {
+        return Err(DecodeError::OtherString(format!(
+            "Field value takes {} bytes instead of {}",
+            read, len
+        )));
+    }
+    decoder.reader().consume(len);
+    Ok(value)
+}
+
+/// Skips value of the field with given length, used for fields unknown to this version
+pub fn skip_field<D: Decoder>(decoder: &mut D, len: usize) -> Result<(), DecodeError> {
+    peek_field(decoder, len)?;
+    decoder.reader().consume(len);
+    Ok(())
+}
+
+/// Returns an error for the required field which is missing in the decoded data
+pub fn missing_field(name: &str, field_id: u16) -> DecodeError {
+    DecodeError::OtherString(format!("Missing field {} with id={}", name, field_id))
+}
+
+// Messages are always decoded from the memory, so fields are read without copying and their length is never trusted
+// for allocations
+fn peek_field<D: Decoder>(decoder: &mut D, len: usize) -> Result<&[u8], DecodeError> {
+    decoder.claim_bytes_read(len)?;
+    decoder
+        .reader()
+        .peek_read(len)
+        .ok_or(DecodeError::UnexpectedEnd { additional: len })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        datetime::ServerTimestamp,
+        messages::{
+            common::ping::{ServerStatus, Status},
+            serializers::MIN_PROTOCOL_VERSION,
+        },
+    };
+
+    use super::*;
+
+    /// Older version of `ServerStatus` which knows only some of its fields
+    #[derive(Debug, PartialEq)]
+    struct OldServerStatus {
+        timestamp: Arc<ServerTimestamp>,
+        status: Status,
+    }
+
+    impl Encode for OldServerStatus {
+        fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+            encode_field(encoder, 1, &self.timestamp)?;
+            encode_field(encoder, 2, &self.status)?;
+            encode_end(encoder)
+        }
+    }
+
+    impl Decode for OldServerStatus {
+        fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+            let (mut timestamp, mut status) = (None, None);
+            while let Some((field_id, len)) = decode_field_header(decoder)? {
+                match field_id {
+                    1 => timestamp = Some(decode_field_value(decoder, len)?),
+                    2 => status = Some(decode_field_value(decoder, len)?),
+                    _ => skip_field(decoder, len)?,
+                }
+            }
+            Ok(Self {
+                timestamp: timestamp.ok_or_else(|| missing_field("timestamp", 1))?,
+                status: status.ok_or_else(|| missing_field("status", 2))?,
+            })
+        }
+    }
+
+    #[test]
+    fn tagged_fields_compatibility() {
+        let config = bincode::config::standard();
+        let timestamp = Arc::new(ServerTimestamp::from_milliseconds_pure(1));
+        let status = ServerStatus {
+            timestamp: timestamp.clone(),
+            status: Status::OK,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+        };
+
+        // Newer fields are skipped by older decoders
+        let data = bincode::encode_to_vec(&status, config).unwrap();
+        let (old, read): (OldServerStatus, usize) =
+            bincode::decode_from_slice(&data, config).unwrap();
+        assert_eq!(old.timestamp, timestamp);
+        assert_eq!(old.status, Status::OK);
+        assert_eq!(read, data.len());
+
+        // Missing fields with defaults are filled in, while required ones fail decoding
+        let data = bincode::encode_to_vec(&old, config).unwrap();
+        let (new, _): (ServerStatus, usize) = bincode::decode_from_slice(&data, config).unwrap();
+        assert_eq!(new.timestamp, timestamp);
+        assert_eq!(new.min_protocol_version, 0);
+        let data = [END_FIELD_ID as u8];
+        assert!(bincode::decode_from_slice::<ServerStatus, _>(&data, config).is_err());
+
+        // Length of the field is checked, so broken data is not misread
+        let mut data = bincode::encode_to_vec(&old, config).unwrap();
+        data[1] += 1;
+        assert!(bincode::decode_from_slice::<OldServerStatus, _>(&data, config).is_err());
+        data[1] = u8::MAX - 5;
+        assert!(bincode::decode_from_slice::<OldServerStatus, _>(&data, config).is_err());
+    }
+}
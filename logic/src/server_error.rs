@@ -8,24 +8,30 @@ use messages_macro::server_message;
 use crate::messages::serializers::SerializationError;
 
 /// General server error
-#[server_message(3)]
+#[server_message(3, tagged)]
 pub struct ServerError {
     /// Error code
+    #[field(1)]
     pub error_code: ErrorCode,
 
     /// Error description that can be shown to the player. It should include a suggestion on how the error can be resolved
+    #[field(2)]
     pub error_description: String,
 
     /// Additional information about the error context, used only for debugging and not meant to be shown to users
+    #[field(3)]
     pub error_context: Option<String>,
 
     /// Message request identifier for which the error was created
-    pub request_id: u8,
+    #[field(4)]
+    pub request_id: u32,
 
     /// Message tag for which the error was created
+    #[field(5)]
     pub message_tag: u16,
 
     /// Indicates whether the error is temporary and if the corresponding message can be safely retried
+    #[field(6)]
     pub recoverable: bool,
 }
 
@@ -46,6 +52,21 @@ pub enum ErrorCode {
 
     /// Undefined server error
     ServerError,
+
+    /// Signed message was already processed or its timestamp is outside of the allowed window
+    ReplayError,
+
+    /// There is no handler for the message, client may be newer than the server
+    UnknownMessage,
+
+    /// Client sends messages too often and has to slow down
+    RateLimited,
+
+    /// Client protocol version is not supported anymore and the client has to be updated
+    OutdatedClient,
+
+    /// Goal status doesn't allow the requested change, e.g. archived goal cannot be completed
+    InvalidGoalState,
 }
 
 impl ServerError {
@@ -53,11 +74,21 @@ impl ServerError {
     pub fn from_serialization_error(
         err: SerializationError,
         message_tag: u16,
-        request_id: u8,
+        request_id: u32,
     ) -> Self {
+        let (error_code, error_description) = match err {
+            SerializationError::BadData { .. } => (
+                ErrorCode::SerializationError,
+                "Data is invalid and cannot be processed",
+            ),
+            SerializationError::Replay { .. } => (
+                ErrorCode::ReplayError,
+                "Message was already processed or has expired, sync the time and sign it again",
+            ),
+        };
         Self {
-            error_code: ErrorCode::SerializationError,
-            error_description: "Data is invalid and cannot be processed".to_string(),
+            error_code,
+            error_description: error_description.to_string(),
             error_context: Some(err.to_string()),
             request_id,
             message_tag,
@@ -9,6 +9,7 @@ usage() {
     ./run.sh test [ client-unreal ]
     ./run.sh deploy [ client-web | api/[lambda-name] | www ]
     ./run.sh lint   
+    ./run.sh dev
     ./run.sh deps"
   exit 1
 }
@@ -125,6 +126,12 @@ lint() {
   (cd infra && terraform fmt -check -recursive)
 }
 
+# Run local WebSocket server with all the API handlers, useful for running clients against local backend
+dev() {
+  log "Starting dev server"
+  cargo run -p dev-server
+}
+
 s3_site_sync() {
   local files=$1
   local bucket=$2
@@ -171,6 +178,7 @@ case "$ACTION" in
   "deploy") deploy "$PARAM" ;;
   "deps") deps ;;
   "lint") lint ;;
+  "dev") dev ;;
   "ci") 
     build ""
     lint 